@@ -0,0 +1,306 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::block_len;
+use super::true_peak::{upsample, upsampler, OVERSAMPLING, UPSAMPLER_DELAY};
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::filters::fir::Fir;
+use crate::frame::FrameIterator;
+use crate::progress::Progress;
+use hound::WavReader;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Samples at or above this level in dBFS are clipped.
+    #[arg(short = 'l', long, default_value_t = -0.01, allow_hyphen_values = true)]
+    clip_level_db: f64,
+    /// Minimum number of consecutive clipped samples which are reported.
+    #[arg(short, long, default_value_t = 3)]
+    min_run: usize,
+    /// True peak overs above this level in dBTP are reported.
+    #[arg(short, long, default_value_t = 0.0, allow_hyphen_values = true)]
+    threshold_db: f64,
+    /// Print the number of events per channel after the list.
+    #[arg(short, long)]
+    summary: bool,
+}
+
+impl Settings {
+    /// Print the number of events per channel after the list.
+    pub fn summary(&self) -> bool {
+        self.summary
+    }
+
+    pub fn analyze<R>(
+        &self,
+        input: &mut WavReader<R>,
+    ) -> Result<Vec<Event>, Error>
+    where
+        R: std::io::Read,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+        let mut detector = ClipDetector::new(spec.channels as usize, self)?;
+
+        let mut progress = Progress::new(duration as usize, "Analyzing sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    detector.process(block.channels())?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(detector.finalize())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Run of consecutive samples at full scale
+    Clip,
+    /// Reconstructed signal above the true peak threshold
+    Over,
+}
+
+/// Detected overload
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub kind: Kind,
+    /// Channel index
+    pub channel: usize,
+    /// First affected frame
+    pub start: usize,
+    /// Number of affected frames
+    pub len: usize,
+    /// Highest absolute sample or true peak value
+    pub peak: f64,
+}
+
+/// Open run of clipped samples or true peak over.
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    start: usize,
+    peak: f64,
+}
+
+/// Detector state of one channel
+#[derive(Debug, Clone)]
+struct Channel {
+    /// Upsampling filter for true peak measurement
+    filter: Fir,
+    clip: Option<Run>,
+    over: Option<Run>,
+}
+
+/// Detects clipped sample runs and true peak overs.
+#[derive(Debug, Clone)]
+pub struct ClipDetector {
+    channels: Vec<Channel>,
+    /// Linear clip level
+    clip_level: f64,
+    /// Minimum number of clipped samples in a run.
+    min_run: usize,
+    /// Linear true peak threshold
+    threshold: f64,
+    /// Current position in frames
+    pos: usize,
+    /// Detected events
+    events: Vec<Event>,
+    /// Upsampling working buffer
+    buffer: Vec<f64>,
+}
+
+impl ClipDetector {
+    pub fn new(channels: usize, settings: &Settings) -> Result<Self, Error> {
+        if settings.min_run == 0 {
+            return Err(Error::InvalidArgument(
+                "Minimum run length must be greater than zero.".into(),
+            ));
+        }
+
+        Ok(Self {
+            channels: vec![
+                Channel {
+                    filter: upsampler(),
+                    clip: None,
+                    over: None,
+                };
+                channels
+            ],
+            clip_level: 10.0_f64.powf(settings.clip_level_db / 20.0),
+            min_run: settings.min_run,
+            threshold: 10.0_f64.powf(settings.threshold_db / 20.0),
+            pos: 0,
+            events: Vec::new(),
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Analyze block of planar channel samples and record overloads.
+    pub fn process<C>(&mut self, block: &[C]) -> Result<(), Error>
+    where
+        C: AsRef<[f32]>,
+    {
+        let len = block_len(block, self.channels.len())?;
+
+        for (idx, (channel, samples)) in
+            self.channels.iter_mut().zip(block).enumerate()
+        {
+            for (i, x) in samples.as_ref().iter().enumerate() {
+                let x = (*x as f64).abs();
+                let pos = self.pos + i;
+                match (&mut channel.clip, x >= self.clip_level) {
+                    (None, true) => {
+                        channel.clip = Some(Run {
+                            start: pos,
+                            peak: x,
+                        })
+                    }
+                    (Some(run), true) => run.peak = run.peak.max(x),
+                    (Some(run), false) => {
+                        if pos - run.start >= self.min_run {
+                            self.events.push(Event {
+                                kind: Kind::Clip,
+                                channel: idx,
+                                start: run.start,
+                                len: pos - run.start,
+                                peak: run.peak,
+                            });
+                        }
+                        channel.clip = None;
+                    }
+                    (None, false) => (),
+                }
+            }
+
+            upsample(&mut channel.filter, samples.as_ref(), &mut self.buffer);
+            let offset = self.pos * OVERSAMPLING;
+            for (i, x) in self.buffer.iter().enumerate() {
+                let x = x.abs();
+                let pos = offset + i;
+                match (&mut channel.over, x > self.threshold) {
+                    (None, true) => {
+                        channel.over = Some(Run {
+                            start: pos,
+                            peak: x,
+                        })
+                    }
+                    (Some(run), true) => run.peak = run.peak.max(x),
+                    (Some(run), false) => {
+                        self.events.push(Self::over(idx, *run, pos));
+                        channel.over = None;
+                    }
+                    (None, false) => (),
+                }
+            }
+        }
+        self.pos += len;
+
+        Ok(())
+    }
+
+    /// Closes open events at the end of the input and returns all events
+    /// sorted by position.
+    pub fn finalize(&mut self) -> Vec<Event> {
+        // Drain the upsampling filter delay line.
+        let padding = vec![
+            vec![0.0; UPSAMPLER_DELAY / OVERSAMPLING + 1];
+            self.channels.len()
+        ];
+        // The padding has the correct number of channels.
+        let _ = self.process(&padding);
+
+        // Clipped runs were closed by the padding.
+        let end = self.pos * OVERSAMPLING;
+        for (idx, channel) in self.channels.iter_mut().enumerate() {
+            if let Some(run) = channel.over.take() {
+                self.events.push(Self::over(idx, run, end));
+            }
+        }
+
+        let mut events = std::mem::take(&mut self.events);
+        events.sort_by_key(|x| (x.start, x.channel));
+        events
+    }
+
+    /// Converts a true peak over on the upsampled time grid to frames.
+    fn over(channel: usize, run: Run, end: usize) -> Event {
+        let start = run.start.saturating_sub(UPSAMPLER_DELAY) / OVERSAMPLING;
+        let end = (end.saturating_sub(UPSAMPLER_DELAY) + OVERSAMPLING - 1)
+            / OVERSAMPLING;
+        Event {
+            kind: Kind::Over,
+            channel,
+            start,
+            len: end.saturating_sub(start).max(1),
+            peak: run.peak,
+        }
+    }
+}
+
+#[test]
+fn test_clip_detector() {
+    let settings = Settings {
+        clip_level_db: -0.01,
+        min_run: 3,
+        threshold_db: 0.0,
+        summary: false,
+    };
+    let mut detector = ClipDetector::new(2, &settings).unwrap();
+
+    // Short and long clipped runs in the left channel, inter-sample overs
+    // of a quarter sampling rate sine with 45 degree phase in the right.
+    let mut left = vec![0.0_f32; 1000];
+    left[100..102].fill(1.0);
+    left[200..205].fill(-1.0);
+    left[997..].fill(1.0);
+    let mut right = vec![0.0_f32; 1000];
+    for (i, x) in right[500..520].iter_mut().enumerate() {
+        *x = if i % 4 < 2 { 0.9 } else { -0.9 };
+    }
+
+    for (left, right) in left.chunks(300).zip(right.chunks(300)) {
+        detector.process(&[left, right]).unwrap();
+    }
+    let events = detector.finalize();
+
+    let clips: Vec<&Event> =
+        events.iter().filter(|x| x.kind == Kind::Clip).collect();
+    assert_eq!(clips.len(), 2);
+    assert_eq!(
+        (clips[0].channel, clips[0].start, clips[0].len),
+        (0, 200, 5)
+    );
+    assert_eq!(clips[0].peak, 1.0);
+    assert_eq!((clips[1].start, clips[1].len), (997, 3));
+
+    // The steps of the clipped runs overshoot as well.
+    let overs: Vec<&Event> = events
+        .iter()
+        .filter(|x| x.kind == Kind::Over && x.channel == 1)
+        .collect();
+    assert!(overs
+        .iter()
+        .all(|x| x.start >= 500 && x.start + x.len <= 521));
+    assert!(overs.iter().all(|x| x.peak > 1.0));
+    assert!(!overs.is_empty());
+}
@@ -0,0 +1,119 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::block_len;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::frame::FrameIterator;
+use crate::progress::Progress;
+use hound::WavReader;
+use kahan::KahanSum;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {}
+
+impl Settings {
+    /// Returns the DC offset of every channel.
+    pub fn analyze<R>(
+        &self,
+        input: &mut WavReader<R>,
+    ) -> Result<Vec<f64>, Error>
+    where
+        R: std::io::Read,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+        let mut analyzer = DcOffset::new(spec.channels as usize);
+
+        let mut progress = Progress::new(duration as usize, "Analyzing sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    analyzer.process(block.channels())?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(analyzer.offsets())
+    }
+}
+
+/// DC offset analyzer
+#[derive(Debug, Clone)]
+pub struct DcOffset {
+    /// Accumulated samples per channel
+    sums: Vec<KahanSum<f64>>,
+    /// Sample counter for averaging operation
+    counter: usize,
+}
+
+impl DcOffset {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            sums: vec![KahanSum::new(); channels],
+            counter: 0,
+        }
+    }
+
+    /// Analyze block of planar channel samples and add it to the
+    /// cumulative mean.
+    pub fn process<C>(&mut self, block: &[C]) -> Result<(), Error>
+    where
+        C: AsRef<[f32]>,
+    {
+        let len = block_len(block, self.sums.len())?;
+
+        for (sum, channel) in self.sums.iter_mut().zip(block) {
+            for sample in channel.as_ref() {
+                *sum += *sample as f64;
+            }
+        }
+        self.counter += len;
+
+        Ok(())
+    }
+
+    /// Returns the mean value of every channel in linear units.
+    pub fn offsets(&self) -> Vec<f64> {
+        self.sums
+            .iter()
+            .map(|x| {
+                if self.counter == 0 {
+                    0.0
+                } else {
+                    x.sum() / self.counter as f64
+                }
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_dc_offset() {
+    let mut analyzer = DcOffset::new(2);
+    assert_eq!(analyzer.offsets(), vec![0.0, 0.0]);
+
+    let left = [0.5, -0.5, 0.5, -0.5];
+    let right = [0.25, 0.0, 0.5, 0.25];
+    analyzer.process(&[&left[..2], &right[..2]]).unwrap();
+    analyzer.process(&[&left[2..], &right[2..]]).unwrap();
+    assert_eq!(analyzer.offsets(), vec![0.0, 0.25]);
+    assert!(analyzer.process(&[&left[..]]).is_err());
+}
@@ -15,10 +15,11 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 \******************************************************************************/
+use super::block_len;
 use crate::conversion::Conversion;
 use crate::error::Error;
 use crate::filters::{biquad::Biquad, Filter};
-use crate::frame::FrameIterator;
+use crate::frame::{ChannelLayout, ChannelMap, FrameIterator};
 use crate::progress::Progress;
 use hound::WavReader;
 use std::collections::VecDeque;
@@ -46,39 +47,48 @@ impl Settings {
     pub fn analyze<R>(
         &self,
         input: &mut WavReader<R>,
+        layout: &ChannelLayout,
     ) -> Result<Vec<f64>, Error>
     where
         R: std::io::Read,
     {
         let spec = input.spec();
         let duration = input.duration();
+        if layout.len() != spec.channels as usize {
+            return Err(Error::InvalidArgument(
+                "Channel layout does not match channel count.".into(),
+            ));
+        }
         let mut analyzer = if self.channel_independent {
-            vec![
-                Loudness::new(spec.sample_rate as f64, 1);
-                spec.channels as usize
-            ]
+            layout
+                .positions()
+                .iter()
+                .map(|x| {
+                    Loudness::new(
+                        spec.sample_rate as f64,
+                        std::slice::from_ref(x),
+                    )
+                })
+                .collect()
         } else {
-            vec![Loudness::new(
-                spec.sample_rate as f64,
-                spec.channels as usize,
-            )]
+            vec![Loudness::new(spec.sample_rate as f64, layout.positions())]
         };
 
         let mut progress = Progress::new(duration as usize, "Analyzing sample");
         let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
-        while let Some(frame) = frames.next() {
-            progress.next();
-            match frame {
-                Ok(frame) => {
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
                     if self.channel_independent {
-                        for (i, x) in frame.iter().enumerate() {
-                            match analyzer.get_mut(i) {
-                                Some(a) => a.process(&vec![*x])?,
-                                None => return Err(Error::InvalidFrame),
-                            }
+                        block_len(block.channels(), spec.channels as usize)?;
+                        for (a, channel) in
+                            analyzer.iter_mut().zip(block.channels())
+                        {
+                            a.process(std::slice::from_ref(channel))?;
                         }
                     } else {
-                        analyzer[0].process(frame)?
+                        analyzer[0].process(block.channels())?
                     }
                 }
                 Err(e) => return Err(e.into()),
@@ -93,10 +103,14 @@ impl Settings {
             })
             .collect::<Result<Vec<f64>, Error>>()?;
         if !self.strict_ebur128 && !self.channel_independent {
-            loudness = loudness
+            let channels = layout
+                .positions()
                 .iter()
-                .map(|x| 2.0 * x / spec.channels as f64)
-                .collect();
+                .filter(|x| x.loudness_weight() != 0.0)
+                .count()
+                .max(1);
+            loudness =
+                loudness.iter().map(|x| 2.0 * x / channels as f64).collect();
         }
 
         Ok(loudness)
@@ -106,8 +120,8 @@ impl Settings {
 /// EBUR128 loudness analyzer
 #[derive(Debug, Clone)]
 pub struct Loudness {
-    /// Number of channels
-    channels: usize,
+    /// Channel weights according to the speaker positions
+    weights: Vec<f64>,
     /// Weighting channels, one HSF/HPF pair for every channel.
     filter: Vec<[Biquad; 2]>,
     /// Working ringbuffer
@@ -120,6 +134,10 @@ pub struct Loudness {
     block_overlap: usize,
     /// Loudness histogram
     histogram: Vec<usize>,
+    /// Filtered samples of one channel
+    filtered: Vec<f64>,
+    /// Weighted square sums of one block of frames
+    sq_sum: Vec<f64>,
 }
 
 impl Loudness {
@@ -130,9 +148,6 @@ impl Loudness {
     /// EBU R128 absolute threshold
     const GAMMA_A: f64 = (-70.0 + 0.691) / 10.0;
 
-    /// Channel weights according to EBU R128
-    const CHANNEL_WEIGHT: [f64; 8] = [1.0, 1.0, 1.0, 0.0, 1.4, 1.4, 1.4, 1.4];
-
     /// Calculates with k-weighting filters for one channel.
     ///
     /// EBU R128 parameter sampling rate adaption after
@@ -140,7 +155,7 @@ impl Loudness {
     /// "Implementation and Evaluation of Autonomous Multi-track Fader Control."
     /// Paper presented at the 132nd Audio Engineering Society Convention,
     /// Budapest, Hungary, 2012."
-    fn k_filter(fs: f64) -> [Biquad; 2] {
+    pub fn k_filter(fs: f64) -> [Biquad; 2] {
         // High shelf filter
         let db: f64 = 3.999843853973347;
         let f0: f64 = 1681.974450955533;
@@ -173,52 +188,65 @@ impl Loudness {
         [hsf, hpf]
     }
 
-    pub fn new(fs: f64, channels: usize) -> Self {
+    pub fn new(fs: f64, layout: &[ChannelMap]) -> Self {
         // 400 ms blocks
         let block_size = (0.4 * fs).ceil() as usize;
         // 100 ms overlap
         let block_overlap = (0.1 * fs).ceil() as usize;
 
         Self {
-            channels,
-            filter: vec![Self::k_filter(fs); channels],
+            weights: layout.iter().map(|x| x.loudness_weight()).collect(),
+            filter: vec![Self::k_filter(fs); layout.len()],
             buffer: VecDeque::with_capacity(block_size),
             counter: 0,
             block_size,
             block_overlap,
             histogram: vec![0; Self::BIN_COUNT],
+            filtered: Vec::new(),
+            sq_sum: Vec::new(),
         }
     }
 
-    /// Analyze frame of samples and add it to the cumulative loudness
-    /// statistics.
-    pub fn process(&mut self, frame: &Vec<f32>) -> Result<(), Error> {
-        if frame.len() != self.channels {
-            return Err(Error::InvalidFrame);
-        }
+    /// Analyze block of planar channel samples and add it to the cumulative
+    /// loudness statistics.
+    pub fn process<C>(&mut self, block: &[C]) -> Result<(), Error>
+    where
+        C: AsRef<[f32]>,
+    {
+        let len = block_len(block, self.weights.len())?;
 
-        let mut sq_sum: f64 = 0.0;
-        for (i, x) in frame.iter().enumerate() {
+        self.sq_sum.clear();
+        self.sq_sum.resize(len, 0.0);
+        for (i, channel) in block.iter().enumerate() {
+            let weight = self.weights[i];
             // Skip unnecessary calculations for LFE channel.
-            if Self::CHANNEL_WEIGHT[i] == 0.0 {
+            if weight == 0.0 {
                 continue;
             }
             // Apply k-weighting filter.
             // True-peak analysis is unnecessary as it does not change the RMS.
-            let val = self.filter[i][0].process(*x as f64);
-            let val = self.filter[i][1].process(val);
-            sq_sum += Self::CHANNEL_WEIGHT[i] * val * val;
+            self.filtered.clear();
+            self.filtered
+                .extend(channel.as_ref().iter().map(|x| *x as f64));
+            for filter in self.filter[i].iter_mut() {
+                filter.process_block(&mut self.filtered);
+            }
+            for (sq_sum, val) in self.sq_sum.iter_mut().zip(&self.filtered) {
+                *sq_sum += weight * val * val;
+            }
         }
 
-        self.buffer.push_back(sq_sum);
-        self.counter += 1;
+        for i in 0..len {
+            self.buffer.push_back(self.sq_sum[i]);
+            self.counter += 1;
 
-        // Commit every time a new overlapping section starts
-        // and if we have a complete block.
-        if self.counter % self.block_overlap == 0
-            && self.buffer.len() >= self.block_size
-        {
-            self.commit_block()?;
+            // Commit every time a new overlapping section starts
+            // and if we have a complete block.
+            if self.counter % self.block_overlap == 0
+                && self.buffer.len() >= self.block_size
+            {
+                self.commit_block()?;
+            }
         }
         Ok(())
     }
@@ -241,6 +269,9 @@ impl Loudness {
         // Reset counter to avoid overflow, just in case as the actual value
         // does not matter, only the modulo to block size.
         self.counter = self.block_size;
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
         // Buffer contains mean square values without root
         // (called z_i in EBU R128).
         let block_sum: f64 = self.buffer.iter().sum();
@@ -265,7 +296,8 @@ impl Loudness {
         } else if idx >= 0 {
             self.histogram[idx as usize] += 1;
         }
-        self.buffer.drain(..self.block_size);
+        // The buffer may contain less than one block during finalize.
+        self.buffer.drain(..self.block_size.min(self.buffer.len()));
         Ok(())
     }
 
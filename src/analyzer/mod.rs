@@ -15,6 +15,29 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 \******************************************************************************/
+pub mod clipping;
+pub mod dc;
 pub mod loudness;
 pub mod rms;
+pub mod silence;
+pub mod spectrogram;
+pub mod spectrum;
 pub mod true_peak;
+
+use crate::error::Error;
+
+/// Validates a block of planar channel samples and returns its length
+/// in frames.
+fn block_len<C>(block: &[C], channels: usize) -> Result<usize, Error>
+where
+    C: AsRef<[f32]>,
+{
+    if block.len() != channels {
+        return Err(Error::InvalidFrame);
+    }
+    let len = block.first().map_or(0, |x| x.as_ref().len());
+    if block.iter().any(|x| x.as_ref().len() != len) {
+        return Err(Error::InvalidFrame);
+    }
+    Ok(len)
+}
@@ -15,6 +15,8 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 \******************************************************************************/
+use super::block_len;
+use super::dc::DcOffset;
 use crate::conversion::Conversion;
 use crate::error::Error;
 use crate::frame::FrameIterator;
@@ -27,12 +29,16 @@ pub struct Settings {
     /// Analyze multiple channels independently
     #[arg(short)]
     channel_independent: bool,
+    /// Include the DC offset in the RMS value.
+    #[arg(long)]
+    include_dc: bool,
 }
 
 impl Settings {
-    pub fn new(channel_independent: bool) -> Self {
+    pub fn new(channel_independent: bool, include_dc: bool) -> Self {
         Self {
             channel_independent,
+            include_dc,
         }
     }
 
@@ -53,26 +59,29 @@ impl Settings {
 
         let mut progress = Progress::new(duration as usize, "Analyzing sample");
         let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
-        while let Some(frame) = frames.next() {
-            progress.next();
-            match frame {
-                Ok(frame) => {
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
                     if self.channel_independent {
-                        for (i, x) in frame.iter().enumerate() {
-                            match analyzer.get_mut(i) {
-                                Some(a) => a.process(&vec![*x])?,
-                                None => return Err(Error::InvalidFrame),
-                            }
+                        block_len(block.channels(), spec.channels as usize)?;
+                        for (a, channel) in
+                            analyzer.iter_mut().zip(block.channels())
+                        {
+                            a.process(std::slice::from_ref(channel))?;
                         }
                     } else {
-                        analyzer[0].process(frame)?
+                        analyzer[0].process(block.channels())?
                     }
                 }
                 Err(e) => return Err(e.into()),
             }
         }
 
-        Ok(analyzer.iter().map(|x| x.rms()).collect())
+        Ok(analyzer
+            .iter()
+            .map(|x| if self.include_dc { x.rms() } else { x.ac_rms() })
+            .collect())
     }
 }
 
@@ -85,6 +94,8 @@ pub struct Rms {
     counter: usize,
     /// Accumulated RMS
     sq_sum: KahanSum<f64>,
+    /// DC offset of every channel
+    dc: DcOffset,
 }
 
 impl Rms {
@@ -93,19 +104,25 @@ impl Rms {
             channels,
             counter: 0,
             sq_sum: KahanSum::new(),
+            dc: DcOffset::new(channels),
         }
     }
 
-    /// Analyze frame of samples and add it to the cumulative RMS.
-    pub fn process(&mut self, frame: &Vec<f32>) -> Result<(), Error> {
-        if frame.len() != self.channels {
-            return Err(Error::InvalidFrame);
-        }
+    /// Analyze block of planar channel samples and add it to the
+    /// cumulative RMS.
+    pub fn process<C>(&mut self, block: &[C]) -> Result<(), Error>
+    where
+        C: AsRef<[f32]>,
+    {
+        let len = block_len(block, self.channels)?;
 
-        for sample in frame {
-            self.sq_sum += (*sample as f64) * (*sample as f64);
+        for channel in block {
+            for sample in channel.as_ref() {
+                self.sq_sum += (*sample as f64) * (*sample as f64);
+            }
         }
-        self.counter += 1;
+        self.counter += len;
+        self.dc.process(block)?;
 
         Ok(())
     }
@@ -115,4 +132,24 @@ impl Rms {
     pub fn rms(&self) -> f64 {
         (self.sq_sum.sum() / ((self.channels * self.counter) as f64)).sqrt()
     }
+
+    /// Returns the root-mean-square value of the processed audio without
+    /// the DC offset of every channel in linear units.
+    pub fn ac_rms(&self) -> f64 {
+        let dc_power = self.dc.offsets().iter().map(|x| x * x).sum::<f64>()
+            / self.channels as f64;
+        let rms = self.rms();
+        (rms * rms - dc_power).max(0.0).sqrt()
+    }
+}
+
+#[test]
+fn test_rms_dc_offset() {
+    let mut rms = Rms::new(2);
+    let left = [1.5, 0.5, 1.5, 0.5];
+    let right = [-0.5, 0.5, -0.5, 0.5];
+    rms.process(&[&left, &right]).unwrap();
+
+    assert!((rms.rms() - 0.75_f64.sqrt()).abs() < 1e-12);
+    assert!((rms.ac_rms() - 0.5).abs() < 1e-12);
 }
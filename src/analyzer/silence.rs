@@ -0,0 +1,303 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::block_len;
+use super::loudness::Loudness;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::filters::{biquad::Biquad, mov_rms::MovRms, Filter};
+use crate::frame::{ChannelLayout, FrameIterator};
+use crate::progress::Progress;
+use hound::WavReader;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Detector {
+    /// Moving RMS level in dBFS
+    Rms,
+    /// Momentary loudness in LUFS according to EBU R128
+    Momentary,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+#[group(skip)]
+pub struct Settings {
+    /// Level detector
+    #[arg(short, long, value_enum, default_value_t = Detector::Rms)]
+    detector: Detector,
+    /// Signals below this level in dBFS or LUFS are silent.
+    #[arg(short, long, default_value_t = -60.0, allow_hyphen_values = true)]
+    threshold: f64,
+    /// Minimum duration of silent regions in seconds.
+    #[arg(short, long, default_value_t = 0.5)]
+    min_duration: f64,
+    /// RMS detector window length in seconds. The momentary loudness
+    /// always uses 400 ms.
+    #[arg(short, long, default_value_t = 0.05)]
+    window: f64,
+}
+
+impl Settings {
+    /// Returns the silent regions as pairs of first and one past the last
+    /// frame.
+    pub fn analyze<R>(
+        &self,
+        input: &mut WavReader<R>,
+        layout: &ChannelLayout,
+    ) -> Result<Vec<(usize, usize)>, Error>
+    where
+        R: std::io::Read,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+        if layout.len() != spec.channels as usize {
+            return Err(Error::InvalidArgument(
+                "Channel layout does not match channel count.".into(),
+            ));
+        }
+        let mut detector =
+            SilenceDetector::new(spec.sample_rate as f64, layout, self)?;
+
+        let mut progress = Progress::new(duration as usize, "Analyzing sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    detector.process(block.channels())?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(detector.finalize())
+    }
+}
+
+/// Detects silent regions with a moving RMS level detector.
+#[derive(Debug, Clone)]
+pub struct SilenceDetector {
+    /// Channel weights, the RMS detector averages all channels.
+    weights: Vec<f64>,
+    /// Optional k-weighting filters, one HSF/HPF pair for every channel.
+    filter: Vec<[Biquad; 2]>,
+    /// Level detector
+    mov_rms: MovRms,
+    /// Detector window length in frames
+    window: usize,
+    /// Squared threshold level
+    threshold: f64,
+    /// Minimum length of a silent region in frames.
+    min_length: usize,
+    /// Current position in frames
+    pos: usize,
+    /// Start of the current silent region
+    silent_since: Option<usize>,
+    /// Detected silent regions
+    regions: Vec<(usize, usize)>,
+    /// Filtered samples of one channel
+    filtered: Vec<f64>,
+    /// Weighted square sums of one block of frames
+    sq_sum: Vec<f64>,
+}
+
+impl SilenceDetector {
+    pub fn new(
+        fs: f64,
+        layout: &ChannelLayout,
+        settings: &Settings,
+    ) -> Result<Self, Error> {
+        if settings.min_duration < 0.0 {
+            return Err(Error::InvalidArgument(
+                "Minimum duration must not be negative.".into(),
+            ));
+        }
+
+        let channels = layout.len();
+        let (weights, filter, window, threshold) = match settings.detector {
+            Detector::Rms => {
+                if settings.window <= 0.0 {
+                    return Err(Error::InvalidArgument(
+                        "Window length must be greater than zero.".into(),
+                    ));
+                }
+                (
+                    vec![1.0 / channels as f64; channels],
+                    Vec::new(),
+                    settings.window,
+                    10.0_f64.powf(settings.threshold / 10.0),
+                )
+            }
+            Detector::Momentary => (
+                layout
+                    .positions()
+                    .iter()
+                    .map(|x| x.loudness_weight())
+                    .collect(),
+                vec![Loudness::k_filter(fs); channels],
+                0.4,
+                // LUFS is defined as -0.691 dB + 10*log10(sum(channels))
+                10.0_f64.powf((settings.threshold + 0.691) / 10.0),
+            ),
+        };
+        let window = ((window * fs).round() as usize).max(1);
+
+        Ok(Self {
+            weights,
+            filter,
+            mov_rms: MovRms::new(1.0, window),
+            window,
+            threshold,
+            min_length: (settings.min_duration * fs).round() as usize,
+            pos: 0,
+            silent_since: None,
+            regions: Vec::new(),
+            filtered: Vec::new(),
+            sq_sum: Vec::new(),
+        })
+    }
+
+    /// Analyze block of planar channel samples and record silent regions.
+    pub fn process<C>(&mut self, block: &[C]) -> Result<(), Error>
+    where
+        C: AsRef<[f32]>,
+    {
+        let len = block_len(block, self.weights.len())?;
+
+        self.sq_sum.clear();
+        self.sq_sum.resize(len, 0.0);
+        for (i, channel) in block.iter().enumerate() {
+            let weight = self.weights[i];
+            if weight == 0.0 {
+                continue;
+            }
+            self.filtered.clear();
+            self.filtered
+                .extend(channel.as_ref().iter().map(|x| *x as f64));
+            if let Some(filter) = self.filter.get_mut(i) {
+                for filter in filter.iter_mut() {
+                    filter.process_block(&mut self.filtered);
+                }
+            }
+            for (sq_sum, val) in self.sq_sum.iter_mut().zip(&self.filtered) {
+                *sq_sum += weight * val * val;
+            }
+        }
+
+        // The moving RMS of the square root yields the mean square
+        // over the window.
+        for x in self.sq_sum.iter_mut() {
+            *x = x.sqrt();
+        }
+        self.mov_rms.process_block(&mut self.sq_sum);
+
+        let levels = std::mem::take(&mut self.sq_sum);
+        for level in &levels {
+            let silent = level * level < self.threshold;
+            match (self.silent_since, silent) {
+                // The whole window ending at the current frame is silent.
+                (None, true) => {
+                    self.silent_since =
+                        Some((self.pos + 1).saturating_sub(self.window))
+                }
+                (Some(start), false) => {
+                    self.commit(start, self.pos);
+                    self.silent_since = None;
+                }
+                _ => (),
+            }
+            self.pos += 1;
+        }
+
+        self.sq_sum = levels;
+
+        Ok(())
+    }
+
+    /// Closes a silent region at the end of the input and returns all
+    /// silent regions.
+    pub fn finalize(&mut self) -> Vec<(usize, usize)> {
+        if let Some(start) = self.silent_since.take() {
+            self.commit(start, self.pos);
+        }
+        std::mem::take(&mut self.regions)
+    }
+
+    fn commit(&mut self, start: usize, end: usize) {
+        // The window of a new region may overlap the previous region.
+        let start = match self.regions.last() {
+            Some((_, last_end)) if *last_end > start => *last_end,
+            _ => start,
+        };
+        if end - start >= self.min_length.max(1) {
+            self.regions.push((start, end));
+        }
+    }
+}
+
+#[test]
+fn test_silence_detector() {
+    let fs = 8000;
+    let settings = Settings {
+        detector: Detector::Rms,
+        threshold: -40.0,
+        min_duration: 0.05,
+        window: 0.01,
+    };
+    let layout = ChannelLayout::from_channels(2);
+
+    // Silence, noise floor, tone, short pause, tone, noise floor.
+    // Time is given in ms.
+    let mut left = Vec::new();
+    for i in 0..5 * fs {
+        let ms = i / (fs / 1000);
+        let phi = 2.0 * std::f32::consts::PI * 400.0 * i as f32 / fs as f32;
+        left.push(match ms {
+            0..=999 => 0.0,
+            2000..=2999 | 3030..=3999 => 0.5 * phi.cos(),
+            _ => 0.001 * phi.cos(),
+        });
+    }
+    let right: Vec<f32> = left.iter().map(|x| -x).collect();
+
+    let mut detector =
+        SilenceDetector::new(fs as f64, &layout, &settings).unwrap();
+    for (left, right) in left.chunks(512).zip(right.chunks(512)) {
+        detector.process(&[left, right]).unwrap();
+    }
+    let regions = detector.finalize();
+    assert_eq!(regions, vec![(0, 2 * fs), (4 * fs, 5 * fs)]);
+
+    // Momentary loudness of a 400 Hz tone at -6 dBFS is about -9 LUFS.
+    let mut detector = SilenceDetector::new(
+        fs as f64,
+        &layout,
+        &Settings {
+            detector: Detector::Momentary,
+            threshold: -20.0,
+            ..settings
+        },
+    )
+    .unwrap();
+    detector.process(&[&left, &right]).unwrap();
+    let regions = detector.finalize();
+    assert_eq!(regions.len(), 2);
+    assert_eq!(regions[0].0, 0);
+    // The long window blurs the borders of the regions.
+    assert!((regions[0].1 as f64 - 2.0 * fs as f64).abs() < 400.0);
+    assert!((regions[1].0 as f64 - 4.0 * fs as f64).abs() < 400.0);
+    assert_eq!(regions[1].1, 5 * fs);
+}
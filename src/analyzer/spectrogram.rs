@@ -0,0 +1,403 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::fft::Window;
+use crate::frame::FrameIterator;
+use crate::png;
+use crate::progress::Progress;
+use crate::stft::{add_powers, Stft};
+use hound::WavReader;
+
+/// Maximum image width in pixels without explicit width.
+const MAX_WIDTH: usize = 16384;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Scale {
+    /// Linear frequency axis
+    Linear,
+    /// Logarithmic frequency axis
+    Log,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Colormap {
+    /// Black over purple and orange to light yellow
+    Inferno,
+    /// Black to white
+    Grayscale,
+}
+
+impl Colormap {
+    const INFERNO: [[f64; 3]; 5] = [
+        [0.0, 0.0, 4.0],
+        [87.0, 16.0, 110.0],
+        [188.0, 55.0, 84.0],
+        [249.0, 142.0, 9.0],
+        [252.0, 255.0, 164.0],
+    ];
+
+    /// Color of the relative level "x" between 0 and 1.
+    pub fn color(self, x: f64) -> [u8; 3] {
+        let x = x.clamp(0.0, 1.0);
+        match self {
+            Self::Inferno => {
+                let pos = x * (Self::INFERNO.len() - 1) as f64;
+                let idx = (pos.floor() as usize).min(Self::INFERNO.len() - 2);
+                let frac = pos - idx as f64;
+                let (a, b) = (Self::INFERNO[idx], Self::INFERNO[idx + 1]);
+                [0, 1, 2].map(|i| (a[i] + frac * (b[i] - a[i])).round() as u8)
+            }
+            Self::Grayscale => [(255.0 * x).round() as u8; 3],
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// FFT size in samples, must be a power of two. Determines the
+    /// frequency resolution.
+    #[arg(short = 'n', long, default_value_t = 2048)]
+    fft_size: usize,
+    /// Frames between consecutive image columns. Determines the time
+    /// resolution, defaults to a quarter of the FFT size.
+    #[arg(short = 't', long)]
+    hop: Option<usize>,
+    /// Analysis window
+    #[arg(short, long, value_enum, default_value_t = Window::Hann)]
+    window: Window,
+    /// Frequency axis scale
+    #[arg(short, long, value_enum, default_value_t = Scale::Linear)]
+    scale: Scale,
+    /// Lowest frequency of the logarithmic frequency axis in Hz.
+    #[arg(short = 'f', long, default_value_t = 20.0)]
+    min_frequency: f64,
+    /// Levels between 0 dBFS and this many dB below are colored.
+    #[arg(short, long, default_value_t = 120.0)]
+    range_db: f64,
+    /// Color map
+    #[arg(short, long, value_enum, default_value_t = Colormap::Inferno)]
+    colormap: Colormap,
+    /// Image height in pixels
+    #[arg(long, default_value_t = 512)]
+    height: usize,
+    /// Maximum image width in pixels. Consecutive FFT frames are combined
+    /// into one column by their maximum power. Defaults to one column per
+    /// FFT frame.
+    #[arg(long)]
+    width: Option<usize>,
+}
+
+impl Settings {
+    /// Renders the spectrogram of the average power of all channels
+    /// as PNG image. Returns the image width and height.
+    pub fn render<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut W,
+    ) -> Result<(usize, usize), Error>
+    where
+        R: std::io::Read,
+        W: std::io::Write,
+    {
+        if self.range_db <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "Dynamic range must be greater than zero.".into(),
+            ));
+        }
+        if self.height == 0 || self.width == Some(0) {
+            return Err(Error::InvalidArgument(
+                "Image size must be greater than zero.".into(),
+            ));
+        }
+
+        let spec = input.spec();
+        let duration = input.duration();
+        let channels = spec.channels as usize;
+        let fs = spec.sample_rate as f64;
+        let hop = self.hop.unwrap_or(self.fft_size / 4);
+        let stft = Stft::new(self.fft_size, hop, self.window)?;
+        let rows = self.rows(fs)?;
+
+        // FFT frames start every hop, the last one is zero padded.
+        let hops = duration as usize / hop + 1;
+        let hops_per_column = match self.width {
+            Some(width) => (hops + width - 1) / width,
+            None if hops > MAX_WIDTH => {
+                return Err(Error::InvalidArgument(format!(
+                    "Spectrogram would be {} pixels wide, limit it with \
+                     the width option.",
+                    hops
+                )))
+            }
+            None => 1,
+        };
+        let mut column = Column::new(self.fft_size / 2 + 1, hops_per_column);
+
+        let mut stft = vec![stft; channels];
+        let scale = stft[0].power_scale() / channels as f64;
+        let mut columns = Vec::new();
+        let mut powers = vec![Vec::new(); channels];
+
+        let mut progress = Progress::new(duration as usize, "Analyzing sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    for ((stft, powers), channel) in stft
+                        .iter_mut()
+                        .zip(powers.iter_mut())
+                        .zip(block.channels())
+                    {
+                        stft.process(channel, |x| {
+                            let mut column = vec![0.0; x.len()];
+                            add_powers(x, scale, &mut column);
+                            powers.push(column);
+                        });
+                    }
+                    self.add_columns(
+                        &mut powers,
+                        &rows,
+                        &mut column,
+                        &mut columns,
+                    );
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        for (stft, powers) in stft.iter_mut().zip(powers.iter_mut()) {
+            stft.finish(|x| {
+                let mut column = vec![0.0; x.len()];
+                add_powers(x, scale, &mut column);
+                powers.push(column);
+            });
+        }
+        self.add_columns(&mut powers, &rows, &mut column, &mut columns);
+        if column.hops != 0 {
+            columns.push(self.colorize(&column.powers, &rows));
+        }
+
+        let width = columns.len();
+        let height = self.height;
+        let mut pixels = vec![0; 3 * width * height];
+        for (x, column) in columns.iter().enumerate() {
+            // The highest frequency is at the top.
+            for (y, color) in column.iter().rev().enumerate() {
+                let idx = 3 * (y * width + x);
+                pixels[idx..idx + 3].copy_from_slice(color);
+            }
+        }
+        png::write_rgb(output, width, height, &pixels)?;
+
+        Ok((width, height))
+    }
+
+    /// Combines the channel powers of FFT frames which were computed for
+    /// all channels into columns and maps complete columns to pixel colors.
+    fn add_columns(
+        &self,
+        powers: &mut [Vec<Vec<f64>>],
+        rows: &[(usize, usize)],
+        column: &mut Column,
+        columns: &mut Vec<Vec<[u8; 3]>>,
+    ) {
+        let count = powers.iter().map(|x| x.len()).min().unwrap_or(0);
+        for i in 0..count {
+            for (k, power) in column.powers.iter_mut().enumerate() {
+                *power = power.max(powers.iter().map(|x| x[i][k]).sum());
+            }
+            column.hops += 1;
+            if column.hops == column.len {
+                columns.push(self.colorize(&column.powers, rows));
+                column.clear();
+            }
+        }
+        for channel in powers.iter_mut() {
+            channel.drain(..count);
+        }
+    }
+
+    /// Maps the bin powers of one column to pixel colors.
+    fn colorize(
+        &self,
+        powers: &[f64],
+        rows: &[(usize, usize)],
+    ) -> Vec<[u8; 3]> {
+        rows.iter()
+            .map(|(low, high)| {
+                let power =
+                    powers[*low..=*high].iter().copied().fold(0.0, f64::max);
+                let level = 10.0 * power.log10();
+                self.colormap.color((level + self.range_db) / self.range_db)
+            })
+            .collect()
+    }
+
+    /// Calculates the FFT bins of every image row from bottom to top as
+    /// pairs of first and last bin. Rows narrower than one bin use the
+    /// nearest bin.
+    fn rows(&self, fs: f64) -> Result<Vec<(usize, usize)>, Error> {
+        let nyquist = fs / 2.0;
+        let bins = self.fft_size / 2;
+        let df = fs / self.fft_size as f64;
+        if self.scale == Scale::Log
+            && (self.min_frequency <= 0.0 || self.min_frequency >= nyquist)
+        {
+            return Err(Error::InvalidArgument(
+                "Minimum frequency must be between 0 and the Nyquist frequency."
+                    .into(),
+            ));
+        }
+
+        let frequency = |y: f64| {
+            let x = y / self.height as f64;
+            match self.scale {
+                Scale::Linear => x * nyquist,
+                Scale::Log => {
+                    self.min_frequency * (nyquist / self.min_frequency).powf(x)
+                }
+            }
+        };
+
+        Ok((0..self.height)
+            .map(|y| {
+                let low = frequency(y as f64);
+                let high = frequency(y as f64 + 1.0);
+                let first = (low / df).ceil() as usize;
+                let last = ((high / df).ceil() as usize).saturating_sub(1);
+                if first > last {
+                    let nearest = (frequency(y as f64 + 0.5) / df).round();
+                    let nearest = (nearest as usize).min(bins);
+                    (nearest, nearest)
+                } else {
+                    (first.min(bins), last.min(bins))
+                }
+            })
+            .collect())
+    }
+}
+
+/// Maximum bin powers of consecutive FFT frames of one image column
+#[derive(Debug, Clone)]
+struct Column {
+    powers: Vec<f64>,
+    /// Number of combined FFT frames
+    hops: usize,
+    /// Number of FFT frames per column
+    len: usize,
+}
+
+impl Column {
+    fn new(bins: usize, len: usize) -> Self {
+        Self {
+            powers: vec![0.0; bins],
+            hops: 0,
+            len,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.powers.iter_mut().for_each(|x| *x = 0.0);
+        self.hops = 0;
+    }
+}
+
+#[test]
+fn test_spectrogram() {
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    assert_eq!(Colormap::Inferno.color(0.0), [0, 0, 4]);
+    assert_eq!(Colormap::Inferno.color(2.0), [252, 255, 164]);
+    assert_eq!(Colormap::Grayscale.color(0.5), [128; 3]);
+
+    let settings = Settings {
+        fft_size: 256,
+        hop: None,
+        window: Window::Hann,
+        scale: Scale::Linear,
+        min_frequency: 20.0,
+        range_db: 120.0,
+        colormap: Colormap::Grayscale,
+        height: 128,
+        width: None,
+    };
+    let rows = settings.rows(8000.0).unwrap();
+    assert_eq!(rows.len(), 128);
+    assert_eq!(rows[0], (0, 0));
+    assert_eq!(rows[127], (127, 127));
+    let rows = Settings {
+        scale: Scale::Log,
+        ..settings.clone()
+    }
+    .rows(8000.0)
+    .unwrap();
+    assert_eq!(rows[0], (1, 1));
+    assert_eq!(rows[127], (123, 127));
+
+    // One FFT frame is rendered as one column.
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate: 8000,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut input = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut input, spec).unwrap();
+    for i in 0..1000 {
+        let phi = 2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 8000.0;
+        writer.write_sample(phi.sin()).unwrap();
+        writer.write_sample(phi.sin()).unwrap();
+    }
+    writer.finalize().unwrap();
+    input.set_position(0);
+
+    let mut png = Vec::new();
+    let (width, height) = settings
+        .render(&mut WavReader::new(&mut input).unwrap(), &mut png)
+        .unwrap();
+    // Columns start at 0, 64, ..., 704 and the padded rest at 768.
+    assert_eq!((width, height), (13, 128));
+
+    // Pairs of FFT frames are combined for a width of 8.
+    input.set_position(0);
+    let (width, _) = Settings {
+        width: Some(8),
+        ..settings.clone()
+    }
+    .render(&mut WavReader::new(&mut input).unwrap(), &mut Vec::new())
+    .unwrap();
+    assert_eq!(width, 7);
+
+    let mut columns = Vec::new();
+    let mut column = Column::new(129, 2);
+    let mut powers = vec![vec![vec![0.0; 129]; 2]];
+    powers[0][0][32] = 1.0;
+    powers[0][1][100] = 1e-6;
+    settings.add_columns(
+        &mut powers,
+        &settings.rows(8000.0).unwrap(),
+        &mut column,
+        &mut columns,
+    );
+    assert!(powers[0].is_empty());
+    assert_eq!(columns.len(), 1);
+    assert_eq!(columns[0][32], [255; 3]);
+    assert_eq!(columns[0][100], [128; 3]);
+    assert_eq!(columns[0][0], [0; 3]);
+}
@@ -0,0 +1,393 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::block_len;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::fft::Window;
+use crate::frame::{ChannelLayout, FrameIterator};
+use crate::progress::Progress;
+use crate::stft::{add_powers, Stft};
+use hound::WavReader;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// One row per frequency with one column per channel
+    Csv,
+    /// Object with frequency and per channel level arrays
+    Json,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// FFT size in samples, must be a power of two.
+    #[arg(short = 'n', long, default_value_t = 4096)]
+    fft_size: usize,
+    /// Analysis window
+    #[arg(short, long, value_enum, default_value_t = Window::Hann)]
+    window: Window,
+    /// Overlap of consecutive FFT frames as a fraction of the FFT size.
+    #[arg(short = 'l', long, default_value_t = 0.5)]
+    overlap: f64,
+    /// Aggregate the spectrum into 1/3-octave bands.
+    #[arg(short, long)]
+    bands: bool,
+    /// Fractional octave smoothing width, e.g. 0.333 for 1/3 octave.
+    #[arg(short, long)]
+    smoothing: Option<f64>,
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+}
+
+impl Settings {
+    pub fn analyze<R>(
+        &self,
+        input: &mut WavReader<R>,
+    ) -> Result<Spectrum, Error>
+    where
+        R: std::io::Read,
+    {
+        if let Some(x) = self.smoothing {
+            if x <= 0.0 {
+                return Err(Error::InvalidArgument(
+                    "Smoothing width must be greater than zero.".into(),
+                ));
+            }
+        }
+
+        let spec = input.spec();
+        let duration = input.duration();
+        let mut analyzer = SpectrumAnalyzer::new(
+            spec.sample_rate as f64,
+            spec.channels as usize,
+            self.fft_size,
+            self.window,
+            self.overlap,
+        )?;
+
+        let mut progress = Progress::new(duration as usize, "Analyzing sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    analyzer.process(block.channels())?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut spectrum = analyzer.finalize();
+        if let Some(octaves) = self.smoothing {
+            spectrum = spectrum.smooth(octaves);
+        }
+        if self.bands {
+            spectrum = spectrum.third_octave_bands();
+        }
+        Ok(spectrum)
+    }
+
+    /// Writes the spectrum in dB in the selected format.
+    pub fn write<W>(
+        &self,
+        spectrum: &Spectrum,
+        layout: &ChannelLayout,
+        output: &mut W,
+    ) -> Result<(), Error>
+    where
+        W: std::io::Write,
+    {
+        let labels: Vec<&str> = if layout.len() == spectrum.powers.len() {
+            layout.positions().iter().map(|x| x.label()).collect()
+        } else {
+            vec!["level"; spectrum.powers.len()]
+        };
+        let levels = spectrum.levels_db();
+
+        match self.format {
+            Format::Csv => {
+                writeln!(output, "frequency,{}", labels.join(","))?;
+                for (i, frequency) in spectrum.frequencies.iter().enumerate() {
+                    let row: Vec<String> =
+                        levels.iter().map(|x| format!("{:.2}", x[i])).collect();
+                    writeln!(output, "{:.2},{}", frequency, row.join(","))?;
+                }
+            }
+            Format::Json => {
+                let format = |values: &[f64]| {
+                    values
+                        .iter()
+                        .map(|x| format!("{:.2}", x))
+                        .collect::<Vec<String>>()
+                        .join(",")
+                };
+                writeln!(output, "{{")?;
+                writeln!(
+                    output,
+                    "  \"frequencies\": [{}],",
+                    format(&spectrum.frequencies)
+                )?;
+                writeln!(output, "  \"channels\": [")?;
+                for (i, (label, values)) in
+                    labels.iter().zip(&levels).enumerate()
+                {
+                    let separator = if i + 1 < levels.len() { "," } else { "" };
+                    writeln!(
+                        output,
+                        "    {{\"label\": \"{}\", \"levels\": [{}]}}{}",
+                        label,
+                        format(values),
+                        separator
+                    )?;
+                }
+                writeln!(output, "  ]")?;
+                writeln!(output, "}}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Power spectrum with one power value per frequency and channel.
+/// A full scale sinusoid has a power of 1.
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    /// Frequencies in Hz
+    pub frequencies: Vec<f64>,
+    /// Linear powers, one vector per channel
+    pub powers: Vec<Vec<f64>>,
+    /// Equivalent noise bandwidth of the analysis window in bins
+    enbw: f64,
+}
+
+impl Spectrum {
+    /// Lowest level in the output to avoid infinite values.
+    const FLOOR_DB: f64 = -300.0;
+
+    /// Levels in dB, one vector per channel.
+    pub fn levels_db(&self) -> Vec<Vec<f64>> {
+        self.powers
+            .iter()
+            .map(|channel| {
+                channel
+                    .iter()
+                    .map(|x| (10.0 * x.log10()).max(Self::FLOOR_DB))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Fractional octave smoothing. Every bin is replaced by the average
+    /// power of all bins within "octaves" around it.
+    pub fn smooth(&self, octaves: f64) -> Self {
+        let df = self.frequencies.get(1).copied().unwrap_or(1.0);
+        let half_width = 2.0_f64.powf(octaves / 2.0);
+        let len = self.frequencies.len();
+
+        let powers = self
+            .powers
+            .iter()
+            .map(|channel| {
+                // Prefix sums allow averaging arbitrary ranges in
+                // constant time.
+                let mut sums = Vec::with_capacity(len + 1);
+                sums.push(0.0);
+                for x in channel {
+                    sums.push(sums.last().unwrap() + x);
+                }
+                self.frequencies
+                    .iter()
+                    .enumerate()
+                    .map(|(i, frequency)| {
+                        let low = ((frequency / half_width / df).ceil()
+                            as usize)
+                            .min(i);
+                        let high = ((frequency * half_width / df).floor()
+                            as usize)
+                            .clamp(i, len - 1);
+                        (sums[high + 1] - sums[low]) / (high + 1 - low) as f64
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            frequencies: self.frequencies.clone(),
+            powers,
+            enbw: self.enbw,
+        }
+    }
+
+    /// Aggregates the bins into 1/3-octave bands according to IEC 61260
+    /// with center frequencies from 20 Hz to 20 kHz. Bands without any bin
+    /// are omitted.
+    pub fn third_octave_bands(&self) -> Self {
+        let mut frequencies = Vec::new();
+        let mut powers = vec![Vec::new(); self.powers.len()];
+        let nyquist = self.frequencies.last().copied().unwrap_or(0.0);
+
+        for band in -17..=13 {
+            let center = 1000.0 * 10.0_f64.powf(band as f64 / 10.0);
+            let low = center * 10.0_f64.powf(-0.05);
+            let high = center * 10.0_f64.powf(0.05);
+            if low >= nyquist {
+                break;
+            }
+            let bins: Vec<usize> = self
+                .frequencies
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| **x >= low && **x < high)
+                .map(|(i, _)| i)
+                .collect();
+            if bins.is_empty() {
+                continue;
+            }
+
+            frequencies.push(center);
+            for (band, channel) in powers.iter_mut().zip(&self.powers) {
+                // The window spreads a sinusoid over "enbw" bins.
+                band.push(
+                    bins.iter().map(|i| channel[*i]).sum::<f64>() / self.enbw,
+                );
+            }
+        }
+
+        Self {
+            frequencies,
+            powers,
+            enbw: 1.0,
+        }
+    }
+}
+
+/// Welch averaged power spectrum analyzer.
+#[derive(Debug, Clone)]
+pub struct SpectrumAnalyzer {
+    /// Sampling frequency
+    fs: f64,
+    /// Transforms, one for every channel
+    stft: Vec<Stft>,
+    /// Power normalization of the window
+    scale: f64,
+    /// Accumulated powers, one vector per channel.
+    sums: Vec<Vec<f64>>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(
+        fs: f64,
+        channels: usize,
+        fft_size: usize,
+        window: Window,
+        overlap: f64,
+    ) -> Result<Self, Error> {
+        if !(0.0..1.0).contains(&overlap) {
+            return Err(Error::InvalidArgument(
+                "Overlap must be at least 0 and less than 1.".into(),
+            ));
+        }
+
+        let hop = (((1.0 - overlap) * fft_size as f64).round() as usize).max(1);
+        let stft = Stft::new(fft_size, hop, window)?;
+
+        Ok(Self {
+            fs,
+            scale: stft.power_scale(),
+            stft: vec![stft; channels],
+            sums: vec![vec![0.0; fft_size / 2 + 1]; channels],
+        })
+    }
+
+    /// Analyze block of planar channel samples.
+    pub fn process<C>(&mut self, block: &[C]) -> Result<(), Error>
+    where
+        C: AsRef<[f32]>,
+    {
+        block_len(block, self.stft.len())?;
+        let scale = self.scale;
+        for ((stft, sums), channel) in
+            self.stft.iter_mut().zip(self.sums.iter_mut()).zip(block)
+        {
+            stft.process(channel.as_ref(), |x| add_powers(x, scale, sums));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the averaged power spectrum. Inputs shorter than one FFT
+    /// frame are zero padded.
+    pub fn finalize(mut self) -> Spectrum {
+        let scale = self.scale;
+        for (stft, sums) in self.stft.iter_mut().zip(self.sums.iter_mut()) {
+            if stft.frames() == 0 {
+                stft.finish(|x| add_powers(x, scale, sums));
+            }
+        }
+
+        let len = self.stft.first().map_or(0, |x| x.window().len());
+        let count = self.stft.first().map_or(1, |x| x.frames().max(1)) as f64;
+        Spectrum {
+            frequencies: (0..len / 2 + 1)
+                .map(|k| k as f64 * self.fs / len as f64)
+                .collect(),
+            powers: self
+                .sums
+                .iter()
+                .map(|channel| channel.iter().map(|x| x / count).collect())
+                .collect(),
+            enbw: self.stft.first().map_or(1.0, |x| x.enbw()),
+        }
+    }
+}
+
+#[test]
+fn test_spectrum() {
+    let fs = 8000.0;
+    let len = 1024;
+    // 1 kHz is the center of bin 128.
+    let signal: Vec<f32> = (0..8 * len)
+        .map(|n| {
+            0.5 * (2.0 * std::f32::consts::PI * 1000.0 * n as f32 / fs as f32)
+                .sin()
+        })
+        .collect();
+
+    for window in [Window::Hann, Window::BlackmanHarris, Window::FlatTop] {
+        let mut analyzer =
+            SpectrumAnalyzer::new(fs, 1, len, window, 0.5).unwrap();
+        for chunk in signal.chunks(1000) {
+            analyzer.process(&[chunk]).unwrap();
+        }
+        assert_eq!(analyzer.stft[0].frames(), 15);
+        let spectrum = analyzer.finalize();
+        let levels = spectrum.levels_db();
+        assert_eq!(spectrum.frequencies[128], 1000.0);
+        assert!((levels[0][128] + 6.02).abs() < 0.01);
+        assert!(levels[0][300] < -100.0);
+
+        let bands = spectrum.third_octave_bands();
+        let idx = bands.frequencies.iter().position(|x| *x == 1000.0).unwrap();
+        assert!((10.0 * bands.powers[0][idx].log10() + 6.02).abs() < 0.1);
+
+        let smooth = spectrum.smooth(1.0 / 3.0);
+        assert!(smooth.powers[0][128] < spectrum.powers[0][128]);
+        assert!(smooth.powers[0][120] > spectrum.powers[0][120]);
+    }
+}
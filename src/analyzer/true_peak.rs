@@ -15,6 +15,7 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 \******************************************************************************/
+use super::block_len;
 use crate::conversion::Conversion;
 use crate::error::Error;
 use crate::filters::{fir::Fir, Filter};
@@ -53,19 +54,19 @@ impl Settings {
 
         let mut progress = Progress::new(duration as usize, "Analyzing sample");
         let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
-        while let Some(frame) = frames.next() {
-            progress.next();
-            match frame {
-                Ok(frame) => {
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
                     if self.channel_independent {
-                        for (i, x) in frame.iter().enumerate() {
-                            match analyzer.get_mut(i) {
-                                Some(a) => a.process(&vec![*x])?,
-                                None => return Err(Error::InvalidFrame),
-                            }
+                        block_len(block.channels(), spec.channels as usize)?;
+                        for (a, channel) in
+                            analyzer.iter_mut().zip(block.channels())
+                        {
+                            a.process(std::slice::from_ref(channel))?;
                         }
                     } else {
-                        analyzer[0].process(frame)?
+                        analyzer[0].process(block.channels())?
                     }
                 }
                 Err(e) => return Err(e.into()),
@@ -76,6 +77,25 @@ impl Settings {
     }
 }
 
+/// Oversampling factor of the true peak measurement
+pub const OVERSAMPLING: usize = 4;
+/// Delay of the upsampling filter in upsampled samples
+pub const UPSAMPLER_DELAY: usize = 11;
+
+/// Upsampling filter for true peak measurement
+pub fn upsampler() -> Fir {
+    Fir::lanczos(OVERSAMPLING, 3)
+}
+
+/// Upsamples "samples" by factor four with "filter" into "buffer".
+pub fn upsample(filter: &mut Fir, samples: &[f32], buffer: &mut Vec<f64>) {
+    buffer.clear();
+    for sample in samples {
+        buffer.extend([*sample as f64, 0.0, 0.0, 0.0]);
+    }
+    filter.process_block(buffer);
+}
+
 /// True peak analyzer
 #[derive(Debug, Clone)]
 pub struct TruePeak {
@@ -85,6 +105,8 @@ pub struct TruePeak {
     true_peak: f64,
     /// Upsampling filters
     filter: Vec<Fir>,
+    /// Upsampling working buffer
+    buffer: Vec<f64>,
 }
 
 impl TruePeak {
@@ -92,23 +114,22 @@ impl TruePeak {
         Self {
             channels,
             true_peak: 0.0,
-            filter: vec![Fir::lanczos(4, 3); channels],
+            filter: vec![upsampler(); channels],
+            buffer: Vec::new(),
         }
     }
 
-    /// Analyze frame of samples and update true peak value.
-    pub fn process(&mut self, frame: &Vec<f32>) -> Result<(), Error> {
-        if frame.len() != self.channels {
-            return Err(Error::InvalidFrame);
-        }
+    /// Analyze block of planar channel samples and update true peak value.
+    pub fn process<C>(&mut self, block: &[C]) -> Result<(), Error>
+    where
+        C: AsRef<[f32]>,
+    {
+        block_len(block, self.channels)?;
 
-        for (i, sample) in frame.iter().enumerate() {
-            // Upsample by factor four.
-            let val = self.filter[i].process(*sample as f64);
-            self.true_peak = self.true_peak.max(val.abs());
+        for (channel, filter) in block.iter().zip(self.filter.iter_mut()) {
+            upsample(filter, channel.as_ref(), &mut self.buffer);
 
-            for _ in 1..4 {
-                let val = self.filter[i].process(0.0);
+            for val in self.buffer.iter() {
                 self.true_peak = self.true_peak.max(val.abs());
             }
         }
@@ -0,0 +1,163 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::error::Error;
+use hound::WavWriter;
+
+/// Block of multi-channel audio data with planar channel layout.
+///
+/// The buffer allocates memory for "block_size" frames on construction.
+/// Changing the length up to the block size does not allocate.
+#[derive(Clone, Debug)]
+pub struct AudioBuffer {
+    /// Sample data, one vector per channel.
+    channels: Vec<Vec<f32>>,
+    /// Maximum number of frames in this buffer.
+    block_size: usize,
+}
+
+impl AudioBuffer {
+    pub fn new(channels: usize, block_size: usize) -> Self {
+        Self {
+            channels: vec![Vec::with_capacity(block_size); channels],
+            block_size,
+        }
+    }
+
+    /// Number of channels.
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Number of frames currently stored in the buffer.
+    pub fn len(&self) -> usize {
+        self.channels.first().map_or(0, |x| x.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Maximum number of frames the buffer can hold without re-allocation.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Removes all frames from the buffer.
+    pub fn clear(&mut self) {
+        for channel in self.channels.iter_mut() {
+            channel.clear();
+        }
+    }
+
+    /// Resizes the buffer to "len" frames, new samples are zero.
+    pub fn resize(&mut self, len: usize) {
+        for channel in self.channels.iter_mut() {
+            channel.resize(len, 0.0);
+        }
+    }
+
+    /// Shortens the buffer to "len" frames.
+    pub fn truncate(&mut self, len: usize) {
+        for channel in self.channels.iter_mut() {
+            channel.truncate(len);
+        }
+    }
+
+    /// Removes the first "count" frames from the buffer.
+    pub fn discard_front(&mut self, count: usize) {
+        let count = count.min(self.len());
+        for channel in self.channels.iter_mut() {
+            channel.drain(..count);
+        }
+    }
+
+    /// Samples of channel "idx".
+    pub fn channel(&self, idx: usize) -> &[f32] {
+        &self.channels[idx]
+    }
+
+    /// Mutable samples of channel "idx".
+    pub fn channel_mut(&mut self, idx: usize) -> &mut [f32] {
+        &mut self.channels[idx]
+    }
+
+    /// All channels as planar sample slices.
+    pub fn channels(&self) -> &[Vec<f32>] {
+        &self.channels
+    }
+
+    /// Iterator over mutable channel sample slices.
+    pub fn channels_mut(&mut self) -> impl Iterator<Item = &mut [f32]> {
+        self.channels.iter_mut().map(|x| x.as_mut_slice())
+    }
+
+    /// Appends one frame with one sample for every channel.
+    pub fn push_frame<I>(&mut self, frame: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = f32>,
+    {
+        let mut count = 0;
+        for (channel, sample) in self.channels.iter_mut().zip(frame) {
+            channel.push(sample);
+            count += 1;
+        }
+        if count != self.channels.len() {
+            return Err(Error::InvalidFrame);
+        }
+        Ok(())
+    }
+
+    /// Iterator over the samples of frame "idx".
+    pub fn frame(&self, idx: usize) -> impl Iterator<Item = f32> + '_ {
+        self.channels.iter().map(move |x| x[idx])
+    }
+
+    /// Iterator over all samples in interleaved order.
+    pub fn interleaved(&self) -> impl Iterator<Item = f32> + '_ {
+        (0..self.len()).flat_map(move |i| self.frame(i))
+    }
+
+    /// Writes all frames to a wave file.
+    pub fn write<W>(&self, output: &mut WavWriter<W>) -> Result<(), Error>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        for sample in self.interleaved() {
+            output.write_sample(sample)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_interleaved_conversion() {
+    let data = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+    let mut buffer = AudioBuffer::new(2, 4);
+
+    for frame in data.chunks(2) {
+        buffer.push_frame(frame.iter().copied()).unwrap();
+    }
+    assert_eq!(buffer.len(), 3);
+    assert_eq!(buffer.channel(0), &[1.0, 3.0, 5.0]);
+    assert_eq!(buffer.channel(1), &[2.0, 4.0, 6.0]);
+    assert_eq!(buffer.interleaved().collect::<Vec<f32>>(), data);
+
+    buffer.discard_front(1);
+    assert_eq!(buffer.frame(0).collect::<Vec<f32>>(), vec![3.0, 4.0]);
+    assert!(buffer.push_frame([1.0]).is_err());
+}
@@ -63,13 +63,16 @@ impl Settings {
             ));
         };
 
-        while let Some(frame) = frames.next() {
-            progress.next();
-            match frame {
-                Ok(frame) => {
-                    for (i, sample) in frame.iter().enumerate() {
-                        output.write_sample(sample * gain[i])?;
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    for (channel, gain) in block.channels_mut().zip(&gain) {
+                        for sample in channel.iter_mut() {
+                            *sample *= gain;
+                        }
                     }
+                    block.write(output)?;
                 }
                 Err(e) => return Err(e.into()),
             }
@@ -15,6 +15,7 @@
     You should have received a copy of the GNU Affero General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 \******************************************************************************/
+use crate::buffer::AudioBuffer;
 use crate::conversion::Conversion;
 use crate::error::Error;
 use crate::filters::{lag1::Lag1, mov_max::MovMax, mov_rms::MovRms, Filter};
@@ -31,7 +32,31 @@ pub enum PeakDetector {
     Rms,
 }
 
-#[derive(Debug, clap::Args)]
+impl PeakDetector {
+    /// Creates the envelope detector preprocessing filter with the given
+    /// sliding window length in samples.
+    pub fn filter(&self, window_length: usize) -> Box<dyn Filter> {
+        match self {
+            Self::Peak => Box::new(MovMax::new(window_length)),
+            Self::Rms => Box::new(MovRms::new(2.0, window_length)),
+        }
+    }
+}
+
+/// Creates one zero filled delay line of "len" samples for every channel.
+/// Each line reserves one additional element so that push before pop
+/// does not re-allocate.
+pub fn delay_lines(channels: usize, len: usize) -> Vec<VecDeque<f32>> {
+    (0..channels)
+        .map(|_| {
+            let mut delay = VecDeque::with_capacity(len + 1);
+            delay.resize(len, 0.0);
+            delay
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, clap::Args)]
 pub struct Settings {
     /// Peak detector to use
     detector: PeakDetector,
@@ -57,6 +82,11 @@ pub struct Settings {
 }
 
 impl Settings {
+    /// Compressor attack time in seconds.
+    pub fn attack_time(&self) -> f64 {
+        self.attack_time
+    }
+
     pub fn compress<R, W>(
         &self,
         input: &mut WavReader<R>,
@@ -85,36 +115,13 @@ impl Settings {
         )?;
         input.seek(0)?;
 
-        let mut counter = 0;
+        let latency = compressor.latency();
         let mut progress =
             Progress::new(duration as usize, "Compressing sample");
-        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
-        while let Some(frame) = frames.next() {
-            progress.next();
-            match frame {
-                Ok(frame) => {
-                    let proc = compressor.process(frame)?;
-                    // Compensate filter latency
-                    if counter > compressor.latency() {
-                        for sample in proc {
-                            output.write_sample(sample)?;
-                        }
-                    } else {
-                        counter += 1;
-                    }
-                }
-                Err(e) => return Err(e.into()),
-            }
-        }
-
-        // Drain processing pipeline
-        let padding = vec![0.0; spec.channels as usize];
-        for _ in 1..compressor.latency() {
-            let proc = compressor.process(&padding)?;
-            for sample in proc {
-                output.write_sample(sample)?;
-            }
-        }
+        FrameIterator::new(input.samples_f32(), spec.channels)
+            .process_delayed(output, latency, &mut progress, |block, _| {
+                compressor.process(block)
+            })?;
 
         Ok(())
     }
@@ -129,17 +136,17 @@ impl Settings {
     where
         R: std::io::Read + std::io::Seek,
     {
-        let mut counter = 0;
-        let settling_len = Lag1::settling_len(fs, attack_time);
+        let mut remaining = Lag1::settling_len(fs, attack_time);
         let mut frames = FrameIterator::new(input.samples_f32(), channels);
-        while let Some(frame) = frames.next() {
-            if counter > settling_len {
+        while let Some(block) = frames.next() {
+            if remaining == 0 {
                 break;
             }
-            match frame {
-                Ok(frame) => {
-                    compressor.process_initial(frame)?;
-                    counter += 1;
+            match block {
+                Ok(block) => {
+                    block.truncate(remaining);
+                    compressor.process_initial(block)?;
+                    remaining -= block.len();
                 }
                 Err(e) => return Err(e.into()),
             }
@@ -149,19 +156,23 @@ impl Settings {
     }
 }
 
-/// EBUR128 loudness analyzer
+/// Dynamic range compressor
 #[derive(Debug)]
 pub struct Compressor {
     /// Number of channels
     channels: usize,
     /// Envelope detector preprocessing filter
     preprocessor: Box<dyn Filter>,
+    /// Preprocessing filter working buffer with interleaved samples.
+    preproc_buffer: Vec<f64>,
     /// Lookahead in samples, this is also the filter latency.
     lookahead: usize,
     /// Main envelope detection filter.
     envelope: Lag1,
-    /// Filter input data buffer.
-    buffer: VecDeque<Vec<f32>>,
+    /// Envelope and gain working buffer, one value per frame.
+    gain_buffer: Vec<f64>,
+    /// Filter input data delay lines, one for each channel.
+    delay: Vec<VecDeque<f32>>,
     /// Compressor threshold in dB.
     threshold_db: f64,
     /// Compression ratio.
@@ -176,20 +187,13 @@ impl Compressor {
     pub fn new(fs: f64, channels: usize, settings: &Settings) -> Self {
         let lookahead = (settings.lookahead_time * fs) as usize;
         let hold = (settings.hold_time * fs) as usize;
-        let preprocessor = match settings.detector {
-            PeakDetector::Peak => {
-                Box::new(MovMax::new((lookahead + hold) * channels))
-                    as Box<dyn Filter>
-            }
-            PeakDetector::Rms => {
-                Box::new(MovRms::new(2.0, (lookahead + hold) * channels))
-                    as Box<dyn Filter>
-            }
-        };
+        let preprocessor =
+            settings.detector.filter((lookahead + hold) * channels);
 
         Self {
             channels,
             preprocessor,
+            preproc_buffer: Vec::new(),
             lookahead,
             envelope: Lag1::new(
                 match settings.detector {
@@ -202,10 +206,8 @@ impl Compressor {
                 settings.release_time,
                 fs,
             ),
-            buffer: VecDeque::from(vec![
-                vec![0.0; channels as usize];
-                lookahead
-            ]),
+            gain_buffer: Vec::new(),
+            delay: delay_lines(channels, lookahead),
             threshold_db: settings.threshold_db,
             ratio: settings.ratio,
             knee_width_db: settings.knee_width_db,
@@ -217,44 +219,68 @@ impl Compressor {
         self.lookahead
     }
 
+    /// Compresses a block of frames in place. The output is delayed
+    /// by "latency()" frames.
     // TODO: add test to validate effect
-    pub fn process(&mut self, frame: &Vec<f32>) -> Result<Vec<f32>, Error> {
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
         if self.ratio <= 1.0 {
             return Err(Error::InvalidArgument(
                 "Ratio must be greater than one.".into(),
             ));
         }
-        if frame.len() != self.channels {
+        if block.channel_count() != self.channels {
             return Err(Error::InvalidFrame);
         }
 
-        let mut preproc = 0.0;
-        for x in frame {
-            preproc = self.preprocessor.process(*x as f64);
+        self.detect(block);
+        for i in 0..self.gain_buffer.len() {
+            let gain = self.gain(self.gain_buffer[i]);
+            self.gain_buffer[i] = gain;
         }
 
-        let envelope = self.envelope.process(preproc);
-        let gain = self.gain(envelope) as f32;
-        // TODO: avoid re-construction of inner vectors
-        let current_frame = self.buffer.pop_front().unwrap();
-        self.buffer.push_back(frame.to_owned());
+        for (channel, delay) in block.channels_mut().zip(self.delay.iter_mut())
+        {
+            for (x, gain) in channel.iter_mut().zip(&self.gain_buffer) {
+                delay.push_back(*x);
+                *x = delay.pop_front().unwrap_or(0.0) * (*gain as f32);
+            }
+        }
 
-        Ok(current_frame.iter().map(|x| x * gain).collect())
+        Ok(())
     }
 
-    pub fn process_initial(&mut self, frame: &Vec<f32>) -> Result<(), Error> {
-        if frame.len() != self.channels {
+    /// Updates the envelope detector state without producing output.
+    pub fn process_initial(
+        &mut self,
+        block: &AudioBuffer,
+    ) -> Result<(), Error> {
+        if block.channel_count() != self.channels {
             return Err(Error::InvalidFrame);
         }
-        let mut preproc = 0.0;
-        for x in frame {
-            preproc = self.preprocessor.process(*x as f64);
-        }
-        self.envelope.process(preproc);
+        self.detect(block);
 
         Ok(())
     }
 
+    /// Runs the envelope detector over all samples of the block and
+    /// stores the envelope of every frame in the gain buffer.
+    fn detect(&mut self, block: &AudioBuffer) {
+        // All samples of a frame are fed into the preprocessing filter
+        // and its last output value is used for the frame.
+        self.preproc_buffer.clear();
+        self.preproc_buffer
+            .extend(block.interleaved().map(|x| x as f64));
+        self.preprocessor.process_block(&mut self.preproc_buffer);
+
+        self.gain_buffer.clear();
+        self.gain_buffer.extend(
+            self.preproc_buffer
+                .chunks(self.channels)
+                .map(|x| *x.last().unwrap_or(&0.0)),
+        );
+        self.envelope.process_block(&mut self.gain_buffer);
+    }
+
     fn gain(&self, env: f64) -> f64 {
         let env_db = 20.0 * env.log10();
         // Prevent NaN propagation with a very low dB value if envelope is zero.
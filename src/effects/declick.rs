@@ -0,0 +1,486 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::frame::FrameIterator;
+use crate::lpc;
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+use std::ops::Range;
+
+/// Number of frames which are analyzed at once.
+const BLOCK_SIZE: usize = 2048;
+/// Samples around a detected impulse which are interpolated as well.
+const PADDING: usize = 1;
+/// Detection threshold in robust standard deviations at sensitivity 1.
+const THRESHOLD: f64 = 10.0;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Detection sensitivity. Higher values detect quieter clicks.
+    #[arg(short, long, default_value_t = 1.0)]
+    sensitivity: f64,
+    /// Longer impulses in samples are considered part of the music.
+    #[arg(short, long, default_value_t = 64)]
+    max_len: usize,
+    /// Order of the linear prediction model.
+    #[arg(short = 'p', long, default_value_t = 32)]
+    order: usize,
+    /// List detected clicks without changing the audio.
+    #[arg(short, long)]
+    report: bool,
+}
+
+impl Settings {
+    /// List detected clicks without changing the audio.
+    pub fn report(&self) -> bool {
+        self.report
+    }
+
+    /// Removes clicks from the input. Returns the number of
+    /// removed clicks.
+    pub fn declick<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<usize, Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+
+        let mut declicker = Declicker::new(spec.channels as usize, self)?;
+
+        let latency = declicker.latency();
+        let mut progress =
+            Progress::new(duration as usize, "Declicking sample");
+        FrameIterator::new(input.samples_f32(), spec.channels)
+            .process_delayed(
+                output,
+                latency,
+                &mut progress,
+                |block, padding| {
+                    if padding {
+                        declicker.finish(block)
+                    } else {
+                        declicker.process(block)
+                    }
+                },
+            )?;
+
+        Ok(declicker.clicks().len())
+    }
+
+    /// Detects clicks in the input and returns them sorted by position.
+    pub fn analyze<R>(
+        &self,
+        input: &mut WavReader<R>,
+    ) -> Result<Vec<Click>, Error>
+    where
+        R: std::io::Read,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+        let mut declicker = Declicker::new(spec.channels as usize, self)?;
+
+        let mut progress = Progress::new(duration as usize, "Analyzing sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    declicker.process(block)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut padding =
+            AudioBuffer::new(spec.channels as usize, declicker.latency());
+        padding.resize(declicker.latency());
+        declicker.finish(&mut padding)?;
+
+        Ok(declicker.clicks().to_vec())
+    }
+}
+
+/// Detected click
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Click {
+    /// Channel index
+    pub channel: usize,
+    /// First interpolated frame
+    pub start: usize,
+    /// Number of interpolated frames
+    pub len: usize,
+}
+
+/// Sample history of one channel
+#[derive(Debug, Clone, Default)]
+struct Channel {
+    /// Samples starting at absolute position "offset"
+    samples: Vec<f64>,
+    /// Absolute position of the first buffered sample
+    offset: usize,
+}
+
+/// Detects impulses in the linear prediction residual and replaces them
+/// with the least squares AR interpolation.
+#[derive(Debug, Clone)]
+pub struct Declicker {
+    channels: Vec<Channel>,
+    /// Detection threshold in robust standard deviations
+    threshold: f64,
+    /// Maximum click length
+    max_len: usize,
+    /// Prediction model order
+    order: usize,
+    /// Number of processed frames
+    pos: usize,
+    /// Start of the next analysis block
+    block: usize,
+    /// End of the input if it is known
+    end: Option<usize>,
+    /// Detected clicks
+    clicks: Vec<Click>,
+}
+
+impl Declicker {
+    pub fn new(channels: usize, settings: &Settings) -> Result<Self, Error> {
+        if settings.sensitivity <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "Sensitivity must be greater than zero.".into(),
+            ));
+        }
+        if settings.max_len == 0 || settings.order == 0 {
+            return Err(Error::InvalidArgument(
+                "Maximum click length and model order must be greater than \
+                 zero."
+                    .into(),
+            ));
+        }
+        if 4 * settings.order > BLOCK_SIZE {
+            return Err(Error::InvalidArgument(format!(
+                "Model order must not exceed {}.",
+                BLOCK_SIZE / 4
+            )));
+        }
+
+        Ok(Self {
+            channels: vec![Channel::default(); channels],
+            threshold: THRESHOLD / settings.sensitivity,
+            max_len: settings.max_len,
+            order: settings.order,
+            pos: 0,
+            block: 0,
+            end: None,
+            clicks: Vec::new(),
+        })
+    }
+
+    /// Latency in frames. Blocks are analyzed after the following
+    /// samples which are affected by a click were received.
+    pub fn latency(&self) -> usize {
+        BLOCK_SIZE + self.lookahead()
+    }
+
+    /// Detected clicks sorted by position
+    pub fn clicks(&self) -> &[Click] {
+        &self.clicks
+    }
+
+    /// Drains the lookahead with a block of padding frames. The padding
+    /// is not analyzed.
+    pub fn finish(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        self.end = Some(self.pos);
+        self.process(block)
+    }
+
+    fn lookahead(&self) -> usize {
+        self.max_len + 2 * (self.order + PADDING)
+    }
+
+    /// Removes clicks from a block of frames in place.
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        if block.channel_count() != self.channels.len() {
+            return Err(Error::InvalidFrame);
+        }
+
+        let latency = self.latency();
+        for i in 0..block.len() {
+            let pos = self.pos + i;
+            for (channel, samples) in
+                self.channels.iter_mut().zip(block.channels())
+            {
+                channel.samples.push(samples[i] as f64);
+            }
+            if pos + 1 == self.block + BLOCK_SIZE + self.lookahead() {
+                self.analyze();
+                self.block += BLOCK_SIZE;
+            }
+
+            let out = pos.checked_sub(latency);
+            for (channel, samples) in
+                self.channels.iter().zip(block.channels_mut())
+            {
+                samples[i] = match out {
+                    Some(out) => channel.samples[out - channel.offset] as f32,
+                    None => 0.0,
+                };
+            }
+        }
+        self.pos += block.len();
+
+        // Keep the model context of the next block and all samples
+        // which were not written yet.
+        let keep = self
+            .block
+            .saturating_sub(self.order + PADDING)
+            .min(self.pos.saturating_sub(latency));
+        for channel in self.channels.iter_mut() {
+            if keep > channel.offset {
+                channel.samples.drain(..keep - channel.offset);
+                channel.offset = keep;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Detects and removes clicks which start in the current block.
+    fn analyze(&mut self) {
+        let start = self.block.saturating_sub(self.order + PADDING);
+        let end = (self.block + BLOCK_SIZE + self.lookahead())
+            .min(self.end.unwrap_or(usize::MAX));
+        if end <= start {
+            return;
+        }
+        let mut channels = std::mem::take(&mut self.channels);
+        for (idx, channel) in channels.iter_mut().enumerate() {
+            let window = &mut channel.samples
+                [start - channel.offset..end - channel.offset];
+            let offset = self.block - start;
+
+            let clicks = self.detect(window, offset);
+            if clicks.is_empty() {
+                continue;
+            }
+
+            // Estimate the signal model without the damaged samples.
+            let mut segments = Vec::new();
+            let mut known = 0;
+            for click in &clicks {
+                segments.push(&window[known..click.start]);
+                known = click.end;
+            }
+            segments.push(&window[known..]);
+            let model = match lpc::estimate(&segments, self.order) {
+                Some(model) => model,
+                None => continue,
+            };
+
+            for click in clicks {
+                if lpc::interpolate(window, click.clone(), &model).is_ok() {
+                    self.clicks.push(Click {
+                        channel: idx,
+                        start: start + click.start,
+                        len: click.len(),
+                    });
+                }
+            }
+        }
+        self.channels = channels;
+        self.clicks.sort_by_key(|x| (x.start, x.channel));
+    }
+
+    /// Finds impulses in the prediction residual of "window" which start
+    /// at or after "offset" within the block size. Returns the damaged
+    /// sample ranges.
+    fn detect(&self, window: &[f64], offset: usize) -> Vec<Range<usize>> {
+        let order = self.order;
+        if window.len() < 4 * order {
+            return Vec::new();
+        }
+        let model = match lpc::estimate(&[window], order) {
+            Some(model) => model,
+            None => return Vec::new(),
+        };
+
+        // Filtering the residual with the time reversed prediction error
+        // filter concentrates the response to an impulse at its position.
+        let residual: Vec<f64> = (order..window.len())
+            .map(|t| {
+                model
+                    .iter()
+                    .enumerate()
+                    .fold(0.0, |acc, (i, a)| acc + a * window[t - i])
+            })
+            .collect();
+        let detection: Vec<f64> = (0..residual.len() - order)
+            .map(|t| {
+                model
+                    .iter()
+                    .enumerate()
+                    .fold(0.0, |acc, (i, a)| acc + a * residual[t + i])
+            })
+            .collect();
+
+        // The median absolute deviation is insensitive to the clicks.
+        let mut magnitudes: Vec<f64> =
+            detection.iter().map(|x| x.abs()).collect();
+        magnitudes.sort_by(|x, y| {
+            x.partial_cmp(y).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let sigma = magnitudes[magnitudes.len() / 2] / 0.6745;
+        if sigma <= 0.0 {
+            return Vec::new();
+        }
+        let threshold = self.threshold * sigma;
+
+        // The response to an impulse is the autocorrelation of the model.
+        // Its side lobes are removed by subtracting the response of the
+        // strongest remaining impulse until all are below the threshold.
+        let response: Vec<f64> = (0..=order)
+            .map(|d| {
+                (0..=order - d)
+                    .fold(0.0, |acc, i| acc + model[i] * model[i + d])
+            })
+            .collect();
+        let mut detection = detection;
+        let mut impulses = vec![false; detection.len()];
+        for _ in 0..detection.len() {
+            let (u, peak) = detection.iter().enumerate().fold(
+                (0, 0.0),
+                |(u, peak), (i, x)| {
+                    if x.abs() > peak {
+                        (i, x.abs())
+                    } else {
+                        (u, peak)
+                    }
+                },
+            );
+            if peak <= threshold {
+                break;
+            }
+            let amplitude = detection[u] / response[0];
+            let first = u.saturating_sub(order);
+            let last = (u + order).min(detection.len() - 1);
+            for (t, x) in detection[first..=last].iter_mut().enumerate() {
+                let t = first + t;
+                let d = if t > u { t - u } else { u - t };
+                *x -= amplitude * response[d];
+            }
+            detection[u] = 0.0;
+            impulses[u] = true;
+        }
+
+        let mut clicks: Vec<Range<usize>> = Vec::new();
+        let mut run: Option<Range<usize>> = None;
+        for (i, impulse) in impulses.iter().enumerate() {
+            let t = i + order;
+            if *impulse {
+                run = match run {
+                    // Join impulses which are separated by a few samples.
+                    Some(run) if t <= run.end + 2 * PADDING => {
+                        Some(run.start..t + 1)
+                    }
+                    Some(run) => {
+                        clicks.push(run);
+                        Some(t..t + 1)
+                    }
+                    None => Some(t..t + 1),
+                };
+            }
+        }
+        clicks.extend(run);
+
+        clicks
+            .into_iter()
+            .filter(|x| {
+                x.start >= offset
+                    && x.start < offset + BLOCK_SIZE
+                    && x.len() <= self.max_len
+            })
+            .map(|x| {
+                x.start.saturating_sub(PADDING)
+                    ..(x.end + PADDING).min(window.len())
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_declicker() {
+    let settings = Settings {
+        sensitivity: 1.0,
+        max_len: 64,
+        order: 32,
+        report: false,
+    };
+    let mut declicker = Declicker::new(2, &settings).unwrap();
+    let latency = declicker.latency();
+
+    // Tones with some noise from a linear congruential generator.
+    let mut seed = 1_u32;
+    let original: Vec<f64> = (0..10000)
+        .map(|n| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let noise = (seed >> 16) as f64 / 32768.0 - 1.0;
+            let phi = 2.0 * std::f64::consts::PI * n as f64 / 48000.0;
+            0.5 * (phi * 440.0).sin()
+                + 0.2 * (phi * 3100.0).sin()
+                + 1e-3 * noise
+        })
+        .collect();
+    let mut damaged = original.clone();
+    damaged[1500] += 0.3;
+    damaged[4000..4003].copy_from_slice(&[-0.2, 0.4, -0.3]);
+    damaged[2047] -= 0.25;
+
+    let mut output = Vec::new();
+    for (damaged, original) in damaged.chunks(1500).zip(original.chunks(1500)) {
+        let mut block = AudioBuffer::new(2, damaged.len());
+        for (x, y) in damaged.iter().zip(original) {
+            block.push_frame([*x as f32, *y as f32]).unwrap();
+        }
+        declicker.process(&mut block).unwrap();
+        output.extend_from_slice(block.channel(0));
+    }
+    let mut padding = AudioBuffer::new(2, latency);
+    padding.resize(latency);
+    declicker.finish(&mut padding).unwrap();
+    output.extend_from_slice(padding.channel(0));
+    let output = &output[latency..];
+
+    // The clean channel is unchanged.
+    let clicks = declicker.clicks();
+    assert_eq!(clicks.len(), 3);
+    assert!(clicks.iter().all(|x| x.channel == 0));
+    for (click, position) in clicks.iter().zip([1500, 2047, 4000]) {
+        assert!(click.start <= position);
+        assert!(click.start + click.len > position);
+        assert!(click.len <= 8);
+    }
+
+    for (x, y) in output.iter().zip(&original) {
+        assert!((*x as f64 - y).abs() < 0.01);
+    }
+}
@@ -0,0 +1,289 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::frame::FrameIterator;
+use crate::lpc;
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Samples at or above this level in dBFS are clipped.
+    #[arg(short = 'l', long, default_value_t = -0.01, allow_hyphen_values = true)]
+    clip_level_db: f64,
+    /// Longer runs of clipped samples are left unchanged.
+    #[arg(short, long, default_value_t = 100)]
+    max_run: usize,
+    /// Order of the autoregressive signal model.
+    #[arg(short = 'p', long, default_value_t = 32)]
+    order: usize,
+    /// Number of unclipped samples on each side of a run which are used
+    /// to estimate the signal model.
+    #[arg(short, long, default_value_t = 256)]
+    context: usize,
+    /// Output gain in dB to make headroom for the reconstructed peaks.
+    #[arg(short, long, default_value_t = 0.0, allow_hyphen_values = true)]
+    gain_db: f64,
+}
+
+impl Settings {
+    pub fn declip<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+
+        let mut declipper = Declipper::new(spec.channels as usize, self)?;
+
+        let latency = declipper.latency();
+        let mut progress =
+            Progress::new(duration as usize, "Declipping sample");
+        FrameIterator::new(input.samples_f32(), spec.channels)
+            .process_delayed(output, latency, &mut progress, |block, _| {
+                declipper.process(block)
+            })?;
+
+        Ok(())
+    }
+}
+
+/// Lookahead state of one channel
+#[derive(Debug, Clone)]
+struct Channel {
+    /// Samples starting at absolute position "offset"
+    samples: Vec<f64>,
+    /// Absolute position of the first buffered sample
+    offset: usize,
+    /// Start of the current clipped run and whether it is too long.
+    run: Option<(usize, bool)>,
+    /// Finished runs which wait for context samples, as absolute
+    /// positions of first and one past the last clipped sample.
+    repairs: VecDeque<(usize, usize)>,
+}
+
+/// Reconstructs clipped runs with least squares AR interpolation.
+#[derive(Debug, Clone)]
+pub struct Declipper {
+    channels: Vec<Channel>,
+    /// Linear clip level
+    clip_level: f64,
+    /// Maximum length of repaired runs
+    max_run: usize,
+    /// AR model order
+    order: usize,
+    /// Context length on each side of a run
+    context: usize,
+    /// Linear output gain
+    gain: f64,
+    /// Number of processed frames
+    pos: usize,
+}
+
+impl Declipper {
+    pub fn new(channels: usize, settings: &Settings) -> Result<Self, Error> {
+        if settings.max_run == 0 || settings.order == 0 {
+            return Err(Error::InvalidArgument(
+                "Maximum run length and model order must be greater than zero."
+                    .into(),
+            ));
+        }
+        if settings.context < 2 * settings.order {
+            return Err(Error::InvalidArgument(
+                "Context must be at least twice the model order.".into(),
+            ));
+        }
+
+        Ok(Self {
+            channels: vec![
+                Channel {
+                    samples: Vec::new(),
+                    offset: 0,
+                    run: None,
+                    repairs: VecDeque::new(),
+                };
+                channels
+            ],
+            clip_level: 10.0_f64.powf(settings.clip_level_db / 20.0),
+            max_run: settings.max_run,
+            order: settings.order,
+            context: settings.context,
+            gain: 10.0_f64.powf(settings.gain_db / 20.0),
+            pos: 0,
+        })
+    }
+
+    /// Latency in frames. A run is repaired after it and its context
+    /// were received.
+    pub fn latency(&self) -> usize {
+        self.max_run + self.context
+    }
+
+    /// Declips a block of frames in place.
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        if block.channel_count() != self.channels.len() {
+            return Err(Error::InvalidFrame);
+        }
+
+        let latency = self.latency();
+        let mut channels = std::mem::take(&mut self.channels);
+        for (channel, samples) in channels.iter_mut().zip(block.channels_mut())
+        {
+            for (i, x) in samples.iter_mut().enumerate() {
+                let pos = self.pos + i;
+                channel.samples.push(*x as f64);
+                self.detect(channel, pos, *x as f64);
+                while let Some((start, end)) = channel.repairs.front().copied()
+                {
+                    if end + self.context > pos + 1 {
+                        break;
+                    }
+                    self.repair(channel, start, end);
+                    channel.repairs.pop_front();
+                }
+
+                *x = match pos.checked_sub(latency) {
+                    Some(out) => {
+                        (self.gain * channel.samples[out - channel.offset])
+                            as f32
+                    }
+                    None => 0.0,
+                };
+            }
+
+            // Keep the context before the next output sample.
+            let keep = (self.pos + samples.len())
+                .saturating_sub(latency + self.context);
+            if keep > channel.offset {
+                channel.samples.drain(..keep - channel.offset);
+                channel.offset = keep;
+            }
+        }
+        self.channels = channels;
+        self.pos += block.len();
+
+        Ok(())
+    }
+
+    /// Tracks clipped runs.
+    fn detect(&self, channel: &mut Channel, pos: usize, x: f64) {
+        let clipped = x.abs() >= self.clip_level;
+        match (channel.run, clipped) {
+            (None, true) => channel.run = Some((pos, false)),
+            (Some((start, false)), true) if pos + 1 - start > self.max_run => {
+                channel.run = Some((start, true))
+            }
+            (Some((start, too_long)), false) => {
+                if !too_long && start >= self.context {
+                    channel.repairs.push_back((start, pos));
+                }
+                channel.run = None;
+            }
+            _ => (),
+        }
+    }
+
+    /// Interpolates a clipped run from its context.
+    fn repair(&self, channel: &mut Channel, start: usize, end: usize) {
+        let begin = start - self.context - channel.offset;
+        let segment =
+            &mut channel.samples[begin..end + self.context - channel.offset];
+        let gap = self.context..self.context + end - start;
+
+        let model = lpc::estimate(
+            &[&segment[..gap.start], &segment[gap.end..]],
+            self.order,
+        );
+        let clipped = segment[gap.clone()].to_vec();
+        let a = match model {
+            Some(a) => a,
+            None => return,
+        };
+        if lpc::interpolate(segment, gap.clone(), &a).is_err() {
+            segment[gap].copy_from_slice(&clipped);
+            return;
+        }
+
+        // The original signal exceeded the clipped samples.
+        for (x, clipped) in segment[gap].iter_mut().zip(clipped) {
+            if *x * clipped.signum() < clipped.abs() {
+                *x = clipped;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_declipper() {
+    let settings = Settings {
+        clip_level_db: -0.01,
+        max_run: 100,
+        order: 32,
+        context: 256,
+        gain_db: -6.0,
+    };
+    let mut declipper = Declipper::new(1, &settings).unwrap();
+    let latency = declipper.latency();
+
+    // Two tones peaking at about 1.4 are clipped at full scale.
+    let fs = 48000.0;
+    let original: Vec<f64> = (0..8000)
+        .map(|n| {
+            let t = n as f64 / fs;
+            let phi = 2.0 * std::f64::consts::PI * t;
+            (phi * 440.0).sin() + 0.4 * (phi * 1250.0 + 0.3).sin()
+        })
+        .collect();
+    let clipped: Vec<f32> =
+        original.iter().map(|x| x.clamp(-1.0, 1.0) as f32).collect();
+
+    let mut output = Vec::new();
+    for chunk in clipped.chunks(1000) {
+        let mut block = AudioBuffer::new(1, chunk.len());
+        for x in chunk {
+            block.push_frame([*x]).unwrap();
+        }
+        declipper.process(&mut block).unwrap();
+        output.extend_from_slice(block.channel(0));
+    }
+    let output = &output[latency..];
+
+    // Compare within the steady state after the first context.
+    let range = 1000..output.len();
+    let error = |signal: &[f64]| {
+        range
+            .clone()
+            .fold(0.0, |acc, i| acc + (signal[i] - original[i]).powi(2))
+    };
+    let gain = 10.0_f64.powf(-6.0 / 20.0);
+    let repaired: Vec<f64> = output.iter().map(|x| *x as f64 / gain).collect();
+    let unrepaired: Vec<f64> = clipped.iter().map(|x| *x as f64).collect();
+    assert!(error(&repaired) < 1e-4 * error(&unrepaired));
+    let peak = repaired[range]
+        .iter()
+        .fold(0.0_f64, |acc, x| acc.max(x.abs()));
+    assert!(peak > 1.3);
+}
@@ -0,0 +1,353 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::compressor::{delay_lines, PeakDetector};
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::filters::{biquad::Biquad, lag1::Lag1, Filter};
+use crate::frame::FrameIterator;
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Sidechain {
+    /// Bandpass filter around the given frequency
+    Bandpass,
+    /// Highpass filter above the given frequency
+    Highpass,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Mode {
+    /// Attenuate the whole signal
+    Wideband,
+    /// Attenuate only the sidechain band
+    Split,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Sidechain filter frequency in Hz
+    frequency: f64,
+    /// De-esser threshold in dB
+    threshold_db: f64,
+    /// Sidechain filter type
+    #[arg(short, long, value_enum, default_value_t = Sidechain::Bandpass)]
+    sidechain: Sidechain,
+    /// Quality factor of the bandpass sidechain filter.
+    #[arg(short, long, default_value_t = 1.0)]
+    q: f64,
+    /// Attenuation mode
+    #[arg(short, long, value_enum, default_value_t = Mode::Split)]
+    mode: Mode,
+    /// Peak detector to use
+    #[arg(short, long, value_enum, default_value_t = PeakDetector::Peak)]
+    detector: PeakDetector,
+    /// Compression ratio. Must be greater than one.
+    #[arg(short, long, default_value_t = 4.0)]
+    ratio: f64,
+    /// De-esser attack time in seconds.
+    #[arg(long, default_value_t = 0.001)]
+    attack_time: f64,
+    /// De-esser release time in seconds.
+    #[arg(long, default_value_t = 0.05)]
+    release_time: f64,
+    /// De-esser lookahead time in seconds.
+    #[arg(long, default_value_t = 0.002)]
+    lookahead_time: f64,
+    /// Output the detection band instead of the processed signal.
+    #[arg(short, long)]
+    listen: bool,
+}
+
+impl Settings {
+    pub fn deess<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+
+        let mut deesser = DeEsser::new(
+            spec.sample_rate as f64,
+            spec.channels as usize,
+            self,
+        )?;
+
+        let latency = deesser.latency();
+        let mut progress = Progress::new(duration as usize, "De-essing sample");
+        FrameIterator::new(input.samples_f32(), spec.channels)
+            .process_delayed(output, latency, &mut progress, |block, _| {
+                deesser.process(block)
+            })?;
+
+        Ok(())
+    }
+}
+
+/// Frequency selective compressor for sibilance reduction
+#[derive(Debug)]
+pub struct DeEsser {
+    /// Number of channels
+    channels: usize,
+    /// Sidechain filters, one for each channel.
+    sidechain: Vec<Biquad>,
+    /// Sidechain band working buffers, one for each channel.
+    band: Vec<Vec<f64>>,
+    /// Envelope detector preprocessing filter
+    preprocessor: Box<dyn Filter>,
+    /// Preprocessing filter working buffer with interleaved samples.
+    preproc_buffer: Vec<f64>,
+    /// Lookahead in samples, this is also the filter latency.
+    lookahead: usize,
+    /// Main envelope detection filter.
+    envelope: Lag1,
+    /// Envelope and gain working buffer, one value per frame.
+    gain_buffer: Vec<f64>,
+    /// Input data delay lines, one for each channel.
+    delay: Vec<VecDeque<f32>>,
+    /// Sidechain band delay lines, one for each channel.
+    band_delay: Vec<VecDeque<f32>>,
+    /// De-esser threshold in dB.
+    threshold_db: f64,
+    /// Compression ratio.
+    ratio: f64,
+    /// Attenuation mode
+    mode: Mode,
+    /// Output the detection band.
+    listen: bool,
+}
+
+impl DeEsser {
+    pub fn new(
+        fs: f64,
+        channels: usize,
+        settings: &Settings,
+    ) -> Result<Self, Error> {
+        if settings.ratio <= 1.0 {
+            return Err(Error::InvalidArgument(
+                "Ratio must be greater than one.".into(),
+            ));
+        }
+        if settings.frequency <= 0.0 || settings.frequency >= fs / 2.0 {
+            return Err(Error::InvalidArgument(
+                "Frequency must be below the Nyquist frequency.".into(),
+            ));
+        }
+
+        let sidechain = match settings.sidechain {
+            Sidechain::Bandpass => {
+                Biquad::bandpass(fs, settings.frequency, settings.q)
+            }
+            Sidechain::Highpass => Biquad::highpass(
+                fs,
+                settings.frequency,
+                std::f64::consts::FRAC_1_SQRT_2,
+            ),
+        };
+        let lookahead = (settings.lookahead_time * fs) as usize;
+
+        Ok(Self {
+            channels,
+            sidechain: vec![sidechain; channels],
+            band: vec![Vec::new(); channels],
+            preprocessor: settings.detector.filter(lookahead.max(1) * channels),
+            preproc_buffer: Vec::new(),
+            lookahead,
+            envelope: Lag1::new(
+                1.0,
+                settings.attack_time,
+                settings.release_time,
+                fs,
+            ),
+            gain_buffer: Vec::new(),
+            delay: delay_lines(channels, lookahead),
+            band_delay: delay_lines(channels, lookahead),
+            threshold_db: settings.threshold_db,
+            ratio: settings.ratio,
+            mode: settings.mode,
+            listen: settings.listen,
+        })
+    }
+
+    pub fn latency(&self) -> usize {
+        self.lookahead
+    }
+
+    /// De-esses a block of frames in place. The output is delayed
+    /// by "latency()" frames.
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        if block.channel_count() != self.channels {
+            return Err(Error::InvalidFrame);
+        }
+
+        for ((samples, filter), band) in block
+            .channels()
+            .iter()
+            .zip(self.sidechain.iter_mut())
+            .zip(self.band.iter_mut())
+        {
+            band.clear();
+            band.extend(samples.iter().map(|x| *x as f64));
+            filter.process_block(band);
+        }
+
+        // All samples of a frame are fed into the preprocessing filter
+        // and its last output value is used for the frame.
+        self.preproc_buffer.clear();
+        for i in 0..block.len() {
+            self.preproc_buffer.extend(self.band.iter().map(|x| x[i]));
+        }
+        self.preprocessor.process_block(&mut self.preproc_buffer);
+
+        self.gain_buffer.clear();
+        self.gain_buffer.extend(
+            self.preproc_buffer
+                .chunks(self.channels)
+                .map(|x| *x.last().unwrap_or(&0.0)),
+        );
+        self.envelope.process_block(&mut self.gain_buffer);
+        for i in 0..self.gain_buffer.len() {
+            let gain = self.gain(self.gain_buffer[i]);
+            self.gain_buffer[i] = gain;
+        }
+
+        for (((channel, band), delay), band_delay) in block
+            .channels_mut()
+            .zip(&self.band)
+            .zip(self.delay.iter_mut())
+            .zip(self.band_delay.iter_mut())
+        {
+            for ((x, b), gain) in
+                channel.iter_mut().zip(band).zip(&self.gain_buffer)
+            {
+                delay.push_back(*x);
+                band_delay.push_back(*b as f32);
+                let x_delayed = delay.pop_front().unwrap_or(0.0);
+                let b_delayed = band_delay.pop_front().unwrap_or(0.0);
+
+                *x = if self.listen {
+                    b_delayed
+                } else {
+                    match self.mode {
+                        Mode::Wideband => x_delayed * (*gain as f32),
+                        Mode::Split => {
+                            x_delayed - (1.0 - *gain as f32) * b_delayed
+                        }
+                    }
+                };
+            }
+        }
+
+        Ok(())
+    }
+
+    fn gain(&self, env: f64) -> f64 {
+        // Prevent infinite values with a very low dB value
+        // if envelope is zero.
+        let env_db = (20.0 * env.log10()).max(-200.0);
+        if env_db <= self.threshold_db {
+            1.0
+        } else {
+            10.0_f64.powf(
+                (self.threshold_db + (env_db - self.threshold_db) / self.ratio
+                    - env_db)
+                    / 20.0,
+            )
+        }
+    }
+}
+
+#[test]
+fn test_deesser() {
+    let fs = 48000;
+    let settings = Settings {
+        frequency: 7000.0,
+        threshold_db: -30.0,
+        sidechain: Sidechain::Bandpass,
+        q: 1.0,
+        mode: Mode::Split,
+        detector: PeakDetector::Peak,
+        ratio: 100.0,
+        attack_time: 0.001,
+        release_time: 0.05,
+        lookahead_time: 0.002,
+        listen: false,
+    };
+
+    // Voice fundamental with strong sibilance
+    let mut input = AudioBuffer::new(1, fs);
+    for i in 0..fs {
+        let t = i as f32 / fs as f32;
+        let x = 0.5 * (2.0 * std::f32::consts::PI * 300.0 * t).sin()
+            + 0.3 * (2.0 * std::f32::consts::PI * 7000.0 * t).sin();
+        input.push_frame([x]).unwrap();
+    }
+    let amplitude = |block: &AudioBuffer, f: f64| {
+        let (mut re, mut im) = (0.0, 0.0);
+        let samples = &block.channel(0)[fs / 2..];
+        for (n, x) in samples.iter().enumerate() {
+            let phi = 2.0 * std::f64::consts::PI * f / fs as f64 * n as f64;
+            re += *x as f64 * phi.cos();
+            im -= *x as f64 * phi.sin();
+        }
+        2.0 * (re * re + im * im).sqrt() / samples.len() as f64
+    };
+
+    // Only the sibilant band is attenuated.
+    let mut output = input.clone();
+    let mut deesser = DeEsser::new(fs as f64, 1, &settings).unwrap();
+    deesser.process(&mut output).unwrap();
+    assert!((amplitude(&output, 300.0) - 0.5).abs() < 0.02);
+    assert!(amplitude(&output, 7000.0) < 0.06);
+
+    // Wideband mode attenuates everything.
+    let mut output = input.clone();
+    let mut deesser = DeEsser::new(
+        fs as f64,
+        1,
+        &Settings {
+            mode: Mode::Wideband,
+            ..settings.clone()
+        },
+    )
+    .unwrap();
+    deesser.process(&mut output).unwrap();
+    assert!(amplitude(&output, 300.0) < 0.2);
+
+    // Listen mode outputs the detection band.
+    let mut output = input.clone();
+    let mut deesser = DeEsser::new(
+        fs as f64,
+        1,
+        &Settings {
+            listen: true,
+            ..settings.clone()
+        },
+    )
+    .unwrap();
+    deesser.process(&mut output).unwrap();
+    assert!((amplitude(&output, 7000.0) - 0.3).abs() < 0.01);
+    assert!(amplitude(&output, 300.0) < 0.05);
+}
@@ -0,0 +1,339 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::filters::{biquad::Biquad, dc_block::DcBlock, Filter};
+use crate::frame::FrameIterator;
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+
+/// Mains frequencies which are considered by the automatic detection.
+const MAINS_FREQUENCIES: [f64; 2] = [50.0, 60.0];
+/// Cutoff frequency of the DC blocking filter in Hz
+const DC_CUTOFF: f64 = 5.0;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Mains frequency in Hz. Either 50 or 60 Hz is detected
+    /// automatically if it is not given.
+    #[arg(short, long)]
+    frequency: Option<f64>,
+    /// Number of harmonics which are removed in addition to the
+    /// fundamental.
+    #[arg(short = 'n', long, default_value_t = 4)]
+    harmonics: usize,
+    /// Quality factor of the notch filters. Higher values remove less
+    /// of the surrounding signal.
+    #[arg(short, long, default_value_t = 30.0)]
+    q: f64,
+    /// Remove the DC offset as well.
+    #[arg(short, long)]
+    dc_block: bool,
+}
+
+impl Settings {
+    /// Removes hum from the input. Returns the used mains frequency.
+    pub fn dehum<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<f64, Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+        let fs = spec.sample_rate as f64;
+
+        let frequency = match self.frequency {
+            Some(frequency) => frequency,
+            None => {
+                let frequency = self.detect(input)?;
+                input.seek(0)?;
+                frequency
+            }
+        };
+        let mut dehum =
+            Dehum::new(fs, spec.channels as usize, frequency, self)?;
+
+        let mut progress = Progress::new(duration as usize, "Dehumming sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    dehum.process(block)?;
+                    block.write(output)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(frequency)
+    }
+
+    /// Returns the mains frequency with the highest hum power in the
+    /// fundamental and its harmonics.
+    fn detect<R>(&self, input: &mut WavReader<R>) -> Result<f64, Error>
+    where
+        R: std::io::Read,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+        let fs = spec.sample_rate as f64;
+        let mut detectors: Vec<Vec<Goertzel>> = MAINS_FREQUENCIES
+            .iter()
+            .map(|f| {
+                harmonics(fs, *f, self.harmonics)
+                    .map(|f| Goertzel::new(fs, f))
+                    .collect()
+            })
+            .collect();
+
+        let mut progress = Progress::new(duration as usize, "Analyzing sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    for i in 0..block.len() {
+                        let x = block.frame(i).map(|x| x as f64).sum::<f64>();
+                        for detector in detectors.iter_mut().flatten() {
+                            detector.process(x);
+                        }
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let powers = detectors
+            .iter()
+            .map(|x| x.iter().map(|x| x.power()).sum::<f64>());
+        Ok(MAINS_FREQUENCIES
+            .iter()
+            .zip(powers)
+            .fold((MAINS_FREQUENCIES[0], 0.0), |acc, (f, power)| {
+                if power > acc.1 {
+                    (*f, power)
+                } else {
+                    acc
+                }
+            })
+            .0)
+    }
+}
+
+/// Fundamental "frequency" and up to "count" harmonics below the
+/// Nyquist frequency.
+fn harmonics(
+    fs: f64,
+    frequency: f64,
+    count: usize,
+) -> impl Iterator<Item = f64> {
+    (1..=count + 1)
+        .map(move |n| n as f64 * frequency)
+        .take_while(move |f| *f < fs / 2.0)
+}
+
+/// Accumulates the power of one frequency over blocks of one second.
+/// Short blocks tolerate small deviations of the mains frequency.
+#[derive(Debug, Clone)]
+struct Goertzel {
+    /// Recursion coefficient 2*cos(omega)
+    coeff: f64,
+    /// Previous recursion values
+    state: [f64; 2],
+    /// Block length in samples
+    len: usize,
+    /// Samples in the current block
+    count: usize,
+    /// Accumulated power of all completed blocks
+    power: f64,
+}
+
+impl Goertzel {
+    fn new(fs: f64, frequency: f64) -> Self {
+        let omega = 2.0 * std::f64::consts::PI * frequency / fs;
+        Self {
+            coeff: 2.0 * omega.cos(),
+            state: [0.0; 2],
+            len: fs.round().max(1.0) as usize,
+            count: 0,
+            power: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f64) {
+        let [s1, s2] = self.state;
+        self.state = [input + self.coeff * s1 - s2, s1];
+        self.count += 1;
+        if self.count == self.len {
+            let [s1, s2] = self.state;
+            self.power += s1 * s1 + s2 * s2 - self.coeff * s1 * s2;
+            self.state = [0.0; 2];
+            self.count = 0;
+        }
+    }
+
+    fn power(&self) -> f64 {
+        self.power
+    }
+}
+
+/// Bank of notch filters at the mains frequency and its harmonics
+#[derive(Debug, Clone)]
+pub struct Dehum {
+    notches: Vec<Vec<Biquad>>,
+    dc_block: Vec<Option<DcBlock>>,
+    /// Filter working buffer
+    buffer: Vec<f64>,
+}
+
+impl Dehum {
+    pub fn new(
+        fs: f64,
+        channels: usize,
+        frequency: f64,
+        settings: &Settings,
+    ) -> Result<Self, Error> {
+        if frequency <= 0.0 || frequency >= fs / 2.0 {
+            return Err(Error::InvalidArgument(
+                "Mains frequency must be between 0 and the Nyquist frequency."
+                    .into(),
+            ));
+        }
+        if settings.q <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "Quality factor must be greater than zero.".into(),
+            ));
+        }
+
+        let notches: Vec<Biquad> = harmonics(fs, frequency, settings.harmonics)
+            .map(|f| Biquad::notch(fs, f, settings.q))
+            .collect();
+        let dc_block = if settings.dc_block {
+            Some(DcBlock::new(fs, DC_CUTOFF))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            notches: vec![notches; channels],
+            dc_block: vec![dc_block; channels],
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Removes hum from a block of frames in place.
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        if block.channel_count() != self.notches.len() {
+            return Err(Error::InvalidFrame);
+        }
+
+        for ((notches, dc_block), samples) in self
+            .notches
+            .iter_mut()
+            .zip(self.dc_block.iter_mut())
+            .zip(block.channels_mut())
+        {
+            self.buffer.clear();
+            self.buffer.extend(samples.iter().map(|x| *x as f64));
+            if let Some(dc_block) = dc_block {
+                dc_block.process_block(&mut self.buffer);
+            }
+            for notch in notches.iter_mut() {
+                notch.process_block(&mut self.buffer);
+            }
+            for (x, y) in samples.iter_mut().zip(&self.buffer) {
+                *x = *y as f32;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_dehum() {
+    let fs = 48000.0;
+    let settings = Settings {
+        frequency: None,
+        harmonics: 4,
+        q: 30.0,
+        dc_block: true,
+    };
+
+    // 60 Hz hum with its third harmonic, a tone and an offset.
+    let hum = |n: usize| {
+        let phi = 2.0 * std::f64::consts::PI * 60.0 * n as f64 / fs;
+        0.1 * phi.sin() + 0.05 * (3.0 * phi).sin()
+    };
+    let tone = |n: usize| {
+        0.5 * (2.0 * std::f64::consts::PI * 1000.0 * n as f64 / fs).sin()
+    };
+    let signal: Vec<f32> = (0..2 * fs as usize)
+        .map(|n| (0.1 + hum(n) + tone(n)) as f32)
+        .collect();
+
+    let mut detectors: Vec<Goertzel> = MAINS_FREQUENCIES
+        .iter()
+        .map(|f| Goertzel::new(fs, *f))
+        .collect();
+    for x in &signal {
+        for detector in detectors.iter_mut() {
+            detector.process(*x as f64);
+        }
+    }
+    assert!(detectors[1].power() > 1e4 * detectors[0].power());
+
+    let mut dehum = Dehum::new(fs, 1, 60.0, &settings).unwrap();
+    assert!(Dehum::new(fs, 1, 30000.0, &settings).is_err());
+    let mut output = Vec::new();
+    for chunk in signal.chunks(1000) {
+        let mut block = AudioBuffer::new(1, chunk.len());
+        for x in chunk {
+            block.push_frame([*x]).unwrap();
+        }
+        dehum.process(&mut block).unwrap();
+        output.extend_from_slice(block.channel(0));
+    }
+
+    // Hum and offset are removed after the filters settled while the
+    // tone passes.
+    let settled = &output[fs as usize..];
+    let power = |f: f64| {
+        let mut detector = Goertzel::new(fs, f);
+        for x in settled {
+            detector.process(*x as f64);
+        }
+        detector.power()
+    };
+    let tone_power = power(1000.0);
+    assert!(10.0 * (power(60.0) / tone_power).log10() < -60.0);
+    assert!(10.0 * (power(180.0) / tone_power).log10() < -60.0);
+    let mean =
+        settled.iter().map(|x| *x as f64).sum::<f64>() / settled.len() as f64;
+    assert!(mean.abs() < 1e-3);
+    let rms = (settled.iter().map(|x| (*x as f64).powi(2)).sum::<f64>()
+        / settled.len() as f64)
+        .sqrt();
+    assert!((20.0 * (rms / (0.5 / 2.0_f64.sqrt())).log10()).abs() < 0.1);
+}
@@ -0,0 +1,417 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::filters::{biquad::Biquad, Filter};
+use crate::frame::{FrameIterator, BLOCK_SIZE};
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+
+/// Lower limit of the tail threshold in dB which keeps the drain finite.
+const MIN_TAIL_THRESHOLD_DB: f64 = -200.0;
+
+/// Delay time either in milliseconds or as tempo synced note value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DelayTime {
+    /// Delay time in milliseconds
+    Millis(f64),
+    /// Note value as fraction of a whole note, including dotted
+    /// and triplet modifiers.
+    Note(f64),
+}
+
+impl DelayTime {
+    /// Delay time in seconds.
+    pub fn seconds(&self, bpm: Option<f64>) -> Result<f64, Error> {
+        match self {
+            Self::Millis(x) => Ok(x / 1000.0),
+            Self::Note(x) => match bpm {
+                // A whole note spans four beats.
+                Some(bpm) if bpm > 0.0 => Ok(4.0 * x * 60.0 / bpm),
+                _ => Err(Error::InvalidArgument(
+                    "Note values require a positive tempo.".into(),
+                )),
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for DelayTime {
+    type Err = String;
+
+    /// Parses "250" as milliseconds and "1/4", "1/8." (dotted) or
+    /// "1/8t" (triplet) as note values.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (numerator, denominator) = match s.split_once('/') {
+            Some(x) => x,
+            None => {
+                return match s.parse::<f64>() {
+                    Ok(x) if x > 0.0 => Ok(Self::Millis(x)),
+                    _ => Err(format!("Invalid delay time '{}'", s)),
+                }
+            }
+        };
+
+        let (denominator, factor) =
+            if let Some(x) = denominator.strip_suffix('.') {
+                (x, 1.5)
+            } else if let Some(x) = denominator.strip_suffix('t') {
+                (x, 2.0 / 3.0)
+            } else {
+                (denominator, 1.0)
+            };
+        match (numerator.parse::<f64>(), denominator.parse::<f64>()) {
+            (Ok(n), Ok(d)) if n > 0.0 && d > 0.0 => {
+                Ok(Self::Note(n / d * factor))
+            }
+            _ => Err(format!("Invalid note value '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FeedbackFilter {
+    /// Unfiltered feedback
+    None,
+    /// Lowpass filter in the feedback path
+    Lowpass,
+    /// Highpass filter in the feedback path
+    Highpass,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Delay times in ms or as note values like "1/4", "1/8." or "1/8t",
+    /// separated by commas. Either one for all channels or one per channel.
+    #[arg(short, long, value_delimiter = ',', required = true)]
+    time: Vec<DelayTime>,
+    /// Tempo in beats per minute for note values.
+    #[arg(short, long)]
+    bpm: Option<f64>,
+    /// Feedback gain, must be less than one.
+    #[arg(short, long, default_value_t = 0.4)]
+    feedback: f64,
+    /// Filter in the feedback path
+    #[arg(long, value_enum, default_value_t = FeedbackFilter::None)]
+    filter: FeedbackFilter,
+    /// Feedback filter cutoff frequency in Hz.
+    #[arg(long, default_value_t = 4000.0)]
+    cutoff: f64,
+    /// Feed each channel's echoes into the next channel.
+    #[arg(short, long)]
+    ping_pong: bool,
+    /// Dry/wet mix, 0 is dry only and 1 is wet only.
+    #[arg(short, long, default_value_t = 0.5)]
+    mix: f64,
+    /// The tail is drained until it decays below this level in dB.
+    #[arg(long, default_value_t = -90.0)]
+    tail_threshold_db: f64,
+}
+
+impl Settings {
+    pub fn delay<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+
+        let mut delay =
+            Delay::new(spec.sample_rate as f64, spec.channels as usize, self)?;
+
+        let mut progress = Progress::new(duration as usize, "Delaying sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    delay.process(block)?;
+                    block.write(output)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // Drain the echo tail until it has decayed.
+        let threshold_db = self.tail_threshold_db.max(MIN_TAIL_THRESHOLD_DB);
+        let threshold = 10.0_f64.powf(threshold_db / 20.0);
+        let mut blocks =
+            delay.max_tail_len(delay.tail_level(), threshold) / BLOCK_SIZE + 1;
+        let mut padding = AudioBuffer::new(spec.channels as usize, BLOCK_SIZE);
+        while delay.tail_level() > threshold && blocks > 0 {
+            padding.clear();
+            padding.resize(BLOCK_SIZE);
+            delay.process(&mut padding)?;
+            padding.write(output)?;
+            blocks -= 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Feedback delay line of a single channel.
+#[derive(Clone, Debug)]
+struct Line {
+    /// Ring buffer which holds one delay period.
+    buffer: Vec<f64>,
+    /// Current read and write position
+    pos: usize,
+    /// Filter in the feedback path
+    filter: Option<Biquad>,
+}
+
+/// Echo effect with filtered feedback
+#[derive(Debug)]
+pub struct Delay {
+    /// Delay lines, one for each channel.
+    lines: Vec<Line>,
+    /// Delayed output of each line for the current frame.
+    taps: Vec<f64>,
+    /// Feedback gain
+    feedback: f64,
+    /// Feed echoes into the next channel.
+    ping_pong: bool,
+    /// Dry/wet mix
+    mix: f64,
+}
+
+impl Delay {
+    pub fn new(
+        fs: f64,
+        channels: usize,
+        settings: &Settings,
+    ) -> Result<Self, Error> {
+        if settings.time.len() != 1 && settings.time.len() != channels {
+            return Err(Error::InvalidArgument(format!(
+                "Expected one delay time or one for each of the {} channels.",
+                channels
+            )));
+        }
+        if settings.feedback.abs() >= 1.0 {
+            return Err(Error::InvalidArgument(
+                "Feedback must be less than one.".into(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&settings.mix) {
+            return Err(Error::InvalidArgument(
+                "Mix must be between zero and one.".into(),
+            ));
+        }
+
+        let filter = match settings.filter {
+            FeedbackFilter::None => None,
+            FeedbackFilter::Lowpass => Some(Biquad::lowpass(
+                fs,
+                settings.cutoff,
+                std::f64::consts::FRAC_1_SQRT_2,
+            )),
+            FeedbackFilter::Highpass => Some(Biquad::highpass(
+                fs,
+                settings.cutoff,
+                std::f64::consts::FRAC_1_SQRT_2,
+            )),
+        };
+
+        let lines = (0..channels)
+            .map(|i| {
+                let time = settings.time[i.min(settings.time.len() - 1)];
+                let len = (time.seconds(settings.bpm)? * fs).round() as usize;
+                Ok(Line {
+                    buffer: vec![0.0; len.max(1)],
+                    pos: 0,
+                    filter: filter.clone(),
+                })
+            })
+            .collect::<Result<Vec<Line>, Error>>()?;
+
+        Ok(Self {
+            lines,
+            taps: vec![0.0; channels],
+            feedback: settings.feedback,
+            ping_pong: settings.ping_pong,
+            mix: settings.mix,
+        })
+    }
+
+    /// Adds echoes to a block of frames in place.
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        let channels = self.lines.len();
+        if block.channel_count() != channels {
+            return Err(Error::InvalidFrame);
+        }
+
+        for i in 0..block.len() {
+            for (tap, line) in self.taps.iter_mut().zip(&self.lines) {
+                *tap = line.buffer[line.pos];
+            }
+
+            for (idx, line) in self.lines.iter_mut().enumerate() {
+                let source = if self.ping_pong {
+                    (idx + channels - 1) % channels
+                } else {
+                    idx
+                };
+                let echo = match &mut line.filter {
+                    Some(filter) => filter.process(self.taps[source]),
+                    None => self.taps[source],
+                };
+
+                let x = &mut block.channel_mut(idx)[i];
+                line.buffer[line.pos] = *x as f64 + self.feedback * echo;
+                line.pos = (line.pos + 1) % line.buffer.len();
+                *x = ((1.0 - self.mix) * *x as f64 + self.mix * self.taps[idx])
+                    as f32;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Peak level of the wet signal which is still stored in the delay lines.
+    pub fn tail_level(&self) -> f64 {
+        self.lines
+            .iter()
+            .flat_map(|x| x.buffer.iter())
+            .fold(0.0_f64, |acc, x| acc.max(x.abs()))
+            * self.mix
+    }
+
+    /// Upper bound for the number of frames until a tail at "level"
+    /// decays below "threshold".
+    pub fn max_tail_len(&self, level: f64, threshold: f64) -> usize {
+        let period = self.lines.iter().map(|x| x.buffer.len()).max();
+        let echoes = if level <= threshold {
+            0.0
+        } else if self.feedback == 0.0 {
+            1.0
+        } else {
+            (threshold / level).ln() / self.feedback.abs().ln() + 1.0
+        };
+        echoes.ceil() as usize * period.unwrap_or(0)
+    }
+}
+
+#[test]
+fn test_delay_time() {
+    let bpm = Some(120.0);
+    let seconds = |s: &str| s.parse::<DelayTime>().unwrap().seconds(bpm);
+
+    assert_eq!(seconds("250").unwrap(), 0.25);
+    assert_eq!(seconds("1/4").unwrap(), 0.5);
+    assert_eq!(seconds("1/8.").unwrap(), 0.375);
+    assert!((seconds("1/4t").unwrap() - 1.0 / 3.0).abs() < 1e-12);
+    assert!("1/0".parse::<DelayTime>().is_err());
+    assert!("1/4".parse::<DelayTime>().unwrap().seconds(None).is_err());
+}
+
+#[test]
+fn test_delay_ping_pong() {
+    let settings = Settings {
+        time: vec![DelayTime::Millis(10.0)],
+        bpm: None,
+        feedback: 0.5,
+        filter: FeedbackFilter::None,
+        cutoff: 4000.0,
+        ping_pong: false,
+        mix: 1.0,
+        tail_threshold_db: -90.0,
+    };
+
+    let mut block = AudioBuffer::new(2, 40);
+    block.resize(40);
+    block.channel_mut(0)[0] = 1.0;
+    let input = block.clone();
+
+    let mut delay = Delay::new(1000.0, 2, &settings).unwrap();
+    delay.process(&mut block).unwrap();
+    for (i, x) in block.channel(0).iter().enumerate() {
+        let expected = match i {
+            10 => 1.0,
+            20 => 0.5,
+            30 => 0.25,
+            _ => 0.0,
+        };
+        assert_eq!(*x, expected);
+    }
+    assert!(block.channel(1).iter().all(|x| *x == 0.0));
+
+    let mut block = input;
+    let mut delay = Delay::new(
+        1000.0,
+        2,
+        &Settings {
+            ping_pong: true,
+            ..settings
+        },
+    )
+    .unwrap();
+    delay.process(&mut block).unwrap();
+    assert_eq!(block.channel(0)[10], 1.0);
+    assert_eq!(block.channel(1)[20], 0.5);
+    assert_eq!(block.channel(0)[30], 0.25);
+    assert_eq!(block.channel(1)[30], 0.0);
+}
+
+#[test]
+fn test_delay_tail() {
+    use hound::{SampleFormat, WavSpec};
+
+    let settings = Settings {
+        time: vec![DelayTime::Millis(10.0)],
+        bpm: None,
+        feedback: 0.5,
+        filter: FeedbackFilter::None,
+        cutoff: 4000.0,
+        ping_pong: false,
+        mix: 1.0,
+        tail_threshold_db: -10000.0,
+    };
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: 48000,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut input = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut input, spec).unwrap();
+    writer.write_sample(1.0_f32).unwrap();
+    writer.finalize().unwrap();
+    input.set_position(0);
+
+    // A threshold below the smallest float terminates nevertheless.
+    let mut output = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut output, spec).unwrap();
+    settings
+        .delay(&mut WavReader::new(&mut input).unwrap(), &mut writer)
+        .unwrap();
+    let delay = Delay::new(48000.0, 1, &settings).unwrap();
+    let threshold = 10.0_f64.powf(MIN_TAIL_THRESHOLD_DB / 20.0);
+    assert!(
+        writer.len() as usize
+            <= delay.max_tail_len(1.0, threshold) + 2 * BLOCK_SIZE
+    );
+    assert_eq!(delay.max_tail_len(1.0, 0.25), 3 * 480);
+}
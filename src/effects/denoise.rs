@@ -0,0 +1,595 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::fft::{Complex, Window};
+use crate::frame::FrameIterator;
+use crate::progress::Progress;
+use crate::stft::{Istft, Stft};
+use crate::time::Timestamp;
+use hound::{WavReader, WavWriter};
+use std::collections::VecDeque;
+
+/// First line of a noise profile file
+const PROFILE_HEADER: &str = "wavehacker noise profile";
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    #[command(subcommand)]
+    action: Action,
+}
+
+impl Settings {
+    pub fn action(&self) -> &Action {
+        &self.action
+    }
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum Action {
+    /// Learn a noise profile from the input and save it to a file
+    Learn(LearnSettings),
+    /// Reduce noise with a learned profile
+    Apply(ApplySettings),
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct LearnSettings {
+    /// Noise profile filename
+    profile: String,
+    /// Start of the noise region as "48000smp", seconds or "hh:mm:ss.ms".
+    /// Quiet regions are detected automatically if no region is given.
+    #[arg(short, long)]
+    start: Option<Timestamp>,
+    /// End of the noise region as "48000smp", seconds or "hh:mm:ss.ms".
+    #[arg(short, long)]
+    end: Option<Timestamp>,
+    /// FFT size in samples, must be a power of two.
+    #[arg(short = 'n', long, default_value_t = 2048)]
+    fft_size: usize,
+    /// Percentage of the quietest frames which are used as noise
+    /// by the automatic detection.
+    #[arg(short, long, default_value_t = 10.0)]
+    quiet: f64,
+}
+
+impl LearnSettings {
+    /// Noise profile filename
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    /// Averages the noise power spectrum within the given region or
+    /// within the quietest frames of the input.
+    pub fn learn<R>(&self, input: &mut WavReader<R>) -> Result<Profile, Error>
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let spec = input.spec();
+        let fs = spec.sample_rate as f64;
+        let hop = self.fft_size / 4;
+        let channels = spec.channels as usize;
+        let bins = self.fft_size / 2 + 1;
+
+        // Frames which contain noise only
+        let selected: Vec<bool> = if self.start.is_some() || self.end.is_some()
+        {
+            let start = self.start.map(|x| x.frames(fs)).unwrap_or(0);
+            let end = self
+                .end
+                .map(|x| x.frames(fs))
+                .unwrap_or(input.duration() as usize);
+            let duration = input.duration() as usize;
+            let count = if duration >= self.fft_size {
+                (duration - self.fft_size) / hop + 1
+            } else {
+                0
+            };
+            (0..count)
+                .map(|i| i * hop >= start && i * hop + self.fft_size <= end)
+                .collect()
+        } else {
+            if self.quiet <= 0.0 || self.quiet > 100.0 {
+                return Err(Error::InvalidArgument(
+                    "Quiet percentage must be between 0 and 100.".into(),
+                ));
+            }
+            let mut energies = Vec::new();
+            analyze_frames(input, self.fft_size, |i, _, spectrum| {
+                if i == energies.len() {
+                    energies.push(0.0);
+                }
+                energies[i] +=
+                    spectrum.iter().map(|x| x.norm_sqr()).sum::<f64>();
+            })?;
+            input.seek(0)?;
+
+            // Digital silence does not contain any noise.
+            let mut sorted: Vec<f64> =
+                energies.iter().copied().filter(|x| *x > 0.0).collect();
+            sorted.sort_by(|x, y| {
+                x.partial_cmp(y).unwrap_or(std::cmp::Ordering::Equal)
+            });
+            let count = (sorted.len() as f64 * self.quiet / 100.0).ceil();
+            let threshold = match sorted.get((count as usize).max(1) - 1) {
+                Some(x) => *x,
+                None => 0.0,
+            };
+            energies
+                .iter()
+                .map(|x| *x > 0.0 && *x <= threshold)
+                .collect()
+        };
+
+        let mut powers = vec![vec![0.0; bins]; channels];
+        analyze_frames(input, self.fft_size, |i, channel, spectrum| {
+            if selected.get(i).copied().unwrap_or(false) {
+                for (power, x) in powers[channel].iter_mut().zip(spectrum) {
+                    *power += x.norm_sqr();
+                }
+            }
+        })?;
+
+        let count = selected.iter().filter(|x| **x).count();
+        if count == 0 {
+            return Err(Error::InvalidArgument(
+                "No noise frames were found, the noise region must be longer \
+                 than the FFT size."
+                    .into(),
+            ));
+        }
+        for power in powers.iter_mut().flatten() {
+            *power /= count as f64;
+        }
+
+        Ok(Profile {
+            sample_rate: spec.sample_rate,
+            fft_size: self.fft_size,
+            powers,
+        })
+    }
+}
+
+/// Passes the frame index, channel index and spectrum of every complete
+/// STFT frame of the input to "frame".
+fn analyze_frames<R, F>(
+    input: &mut WavReader<R>,
+    fft_size: usize,
+    mut frame: F,
+) -> Result<(), Error>
+where
+    R: std::io::Read,
+    F: FnMut(usize, usize, &[Complex]),
+{
+    let spec = input.spec();
+    let duration = input.duration();
+    let stft = Stft::new(fft_size, fft_size / 4, Window::Hann)?;
+    let mut stft = vec![stft; spec.channels as usize];
+
+    let mut progress = Progress::new(duration as usize, "Analyzing sample");
+    let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+    while let Some(block) = frames.next() {
+        match block {
+            Ok(block) => {
+                progress.advance(block.len());
+                for (channel, (stft, samples)) in
+                    stft.iter_mut().zip(block.channels()).enumerate()
+                {
+                    let mut i = stft.frames();
+                    stft.process(samples, |x| {
+                        frame(i, channel, x);
+                        i += 1;
+                    });
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Method {
+    /// Subtract the noise power from the signal power
+    Subtraction,
+    /// Wiener filter with the estimated signal to noise ratio
+    Wiener,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ApplySettings {
+    /// Noise profile filename
+    profile: String,
+    /// Noise reduction method
+    #[arg(short, long, value_enum, default_value_t = Method::Wiener)]
+    method: Method,
+    /// Maximum noise reduction in dB. Lower values reduce artifacts.
+    #[arg(short, long, default_value_t = 12.0)]
+    reduction_db: f64,
+    /// Noise profile multiplier. Higher values remove more noise
+    /// but also more signal.
+    #[arg(short, long, default_value_t = 2.0)]
+    factor: f64,
+    /// Fraction of the previous gain which is kept when the gain falls,
+    /// between 0 and 1. Reduces fluttering artifacts.
+    #[arg(short, long, default_value_t = 0.5)]
+    smoothing: f64,
+    /// Number of neighbouring bins on each side which are averaged
+    /// into the gain. Reduces musical noise.
+    #[arg(short, long, default_value_t = 1)]
+    width: usize,
+}
+
+impl ApplySettings {
+    pub fn denoise<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+
+        let profile = Profile::load(&self.profile)?;
+        if profile.sample_rate != spec.sample_rate {
+            return Err(Error::InvalidArgument(format!(
+                "Noise profile was learned at {} Hz but the input has {} Hz.",
+                profile.sample_rate, spec.sample_rate
+            )));
+        }
+        let mut denoiser =
+            Denoiser::new(&profile, spec.channels as usize, self)?;
+
+        let latency = denoiser.latency();
+        let mut progress = Progress::new(duration as usize, "Denoising sample");
+        FrameIterator::new(input.samples_f32(), spec.channels)
+            .process_delayed(output, latency, &mut progress, |block, _| {
+                denoiser.process(block)
+            })?;
+
+        Ok(())
+    }
+}
+
+/// Average noise power spectrum of every channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub sample_rate: u32,
+    pub fft_size: usize,
+    /// Power of every bin from DC to the Nyquist frequency per channel
+    pub powers: Vec<Vec<f64>>,
+}
+
+impl Profile {
+    /// Writes the profile as text file.
+    pub fn save(&self, filename: &str) -> Result<(), Error> {
+        std::fs::write(filename, self.to_string())?;
+        Ok(())
+    }
+
+    /// Reads a profile from a text file.
+    pub fn load(filename: &str) -> Result<Self, Error> {
+        std::fs::read_to_string(filename)?.parse()
+    }
+}
+
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", PROFILE_HEADER)?;
+        writeln!(f, "sample_rate {}", self.sample_rate)?;
+        writeln!(f, "fft_size {}", self.fft_size)?;
+        writeln!(f, "channels {}", self.powers.len())?;
+        for k in 0..self.fft_size / 2 + 1 {
+            let line: Vec<String> =
+                self.powers.iter().map(|x| format!("{:e}", x[k])).collect();
+            writeln!(f, "{}", line.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Profile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let error = || Error::InvalidArgument("Invalid noise profile.".into());
+        let mut lines = s.lines();
+        if lines.next() != Some(PROFILE_HEADER) {
+            return Err(error());
+        }
+        let mut value = |key: &str| -> Result<usize, Error> {
+            lines
+                .next()
+                .and_then(|x| x.strip_prefix(key))
+                .and_then(|x| x.trim().parse().ok())
+                .ok_or_else(error)
+        };
+        let sample_rate = value("sample_rate")? as u32;
+        let fft_size = value("fft_size")?;
+        let channels = value("channels")?;
+        if !fft_size.is_power_of_two() || fft_size < 4 || channels == 0 {
+            return Err(error());
+        }
+
+        let mut powers = vec![Vec::new(); channels];
+        for line in lines.filter(|x| !x.trim().is_empty()) {
+            let values = line
+                .split_whitespace()
+                .map(|x| x.parse::<f64>().map_err(|_| error()))
+                .collect::<Result<Vec<f64>, Error>>()?;
+            if values.len() != channels {
+                return Err(error());
+            }
+            for (power, x) in powers.iter_mut().zip(values) {
+                power.push(x);
+            }
+        }
+        if powers[0].len() != fft_size / 2 + 1 {
+            return Err(error());
+        }
+
+        Ok(Self {
+            sample_rate,
+            fft_size,
+            powers,
+        })
+    }
+}
+
+/// Processing state of one channel
+#[derive(Debug, Clone)]
+struct Channel {
+    stft: Stft,
+    istft: Istft,
+    /// Scaled noise power per bin
+    noise: Vec<f64>,
+    /// Smoothed gains of the previous frame
+    gains: Vec<f64>,
+    /// Synthesized samples which were not written yet
+    output: VecDeque<f64>,
+}
+
+/// STFT noise reduction with overlap-add resynthesis
+#[derive(Debug, Clone)]
+pub struct Denoiser {
+    channels: Vec<Channel>,
+    method: Method,
+    /// Minimum linear gain
+    floor: f64,
+    smoothing: f64,
+    width: usize,
+    fft_size: usize,
+}
+
+impl Denoiser {
+    pub fn new(
+        profile: &Profile,
+        channels: usize,
+        settings: &ApplySettings,
+    ) -> Result<Self, Error> {
+        if profile.powers.len() != channels {
+            return Err(Error::InvalidArgument(format!(
+                "Noise profile has {} channels but the input has {}.",
+                profile.powers.len(),
+                channels
+            )));
+        }
+        if settings.reduction_db < 0.0 || settings.factor <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "Reduction must not be negative and factor must be greater \
+                 than zero."
+                    .into(),
+            ));
+        }
+        if !(0.0..1.0).contains(&settings.smoothing) {
+            return Err(Error::InvalidArgument(
+                "Smoothing must be at least 0 and less than 1.".into(),
+            ));
+        }
+
+        let fft_size = profile.fft_size;
+        let hop = fft_size / 4;
+        let mut stft = Stft::new(fft_size, hop, Window::Hann)?;
+        // Leading zeros complete the window overlap of the first samples.
+        stft.process(&vec![0.0; fft_size - hop], |_| ());
+        let istft = Istft::new(fft_size, hop, Window::Hann)?;
+
+        Ok(Self {
+            channels: profile
+                .powers
+                .iter()
+                .map(|noise| Channel {
+                    stft: stft.clone(),
+                    istft: istft.clone(),
+                    noise: noise.iter().map(|x| settings.factor * x).collect(),
+                    gains: vec![1.0; noise.len()],
+                    output: vec![0.0; hop].into(),
+                })
+                .collect(),
+            method: settings.method,
+            floor: 10.0_f64.powf(-settings.reduction_db / 20.0),
+            smoothing: settings.smoothing,
+            width: settings.width,
+            fft_size,
+        })
+    }
+
+    /// Latency in frames
+    pub fn latency(&self) -> usize {
+        self.fft_size
+    }
+
+    /// Reduces noise in a block of frames in place.
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        if block.channel_count() != self.channels.len() {
+            return Err(Error::InvalidFrame);
+        }
+
+        let mut spectrum = Vec::with_capacity(self.fft_size / 2 + 1);
+        let mut gains = Vec::with_capacity(self.fft_size / 2 + 1);
+        let mut synthesized = Vec::with_capacity(self.fft_size / 4);
+        for (channel, samples) in
+            self.channels.iter_mut().zip(block.channels_mut())
+        {
+            let Channel {
+                stft,
+                istft,
+                noise,
+                gains: previous,
+                output,
+            } = channel;
+            stft.process(samples, |x| {
+                gains.clear();
+                gains.extend(
+                    x.iter().zip(noise.iter()).map(|(x, noise)| {
+                        gain(self.method, x.norm_sqr(), *noise)
+                    }),
+                );
+
+                // Average the gains of neighbouring bins. Gains fall
+                // slowly and rise immediately.
+                for (k, previous) in previous.iter_mut().enumerate() {
+                    let first = k.saturating_sub(self.width);
+                    let last = (k + self.width).min(gains.len() - 1);
+                    let gain = gains[first..=last].iter().sum::<f64>()
+                        / (last - first + 1) as f64;
+                    let gain = gain.max(self.smoothing * *previous);
+                    *previous = gain.max(self.floor);
+                }
+
+                spectrum.clear();
+                spectrum.extend(
+                    x.iter().zip(previous.iter()).map(|(x, g)| x.scale(*g)),
+                );
+                synthesized.clear();
+                istft.process(&spectrum, &mut synthesized);
+                output.extend(synthesized.iter());
+            });
+
+            // The output contains at least one hop more than the input.
+            let len = samples.len();
+            for (x, y) in samples.iter_mut().zip(output.drain(..len)) {
+                *x = y as f32;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Gain of a bin with signal power "power" and noise power "noise".
+fn gain(method: Method, power: f64, noise: f64) -> f64 {
+    if noise <= 0.0 {
+        return 1.0;
+    }
+    if power <= 0.0 {
+        return 0.0;
+    }
+    match method {
+        Method::Subtraction => (1.0 - noise / power).max(0.0).sqrt(),
+        Method::Wiener => {
+            // Maximum likelihood estimate of the signal to noise ratio
+            let snr = (power / noise - 1.0).max(0.0);
+            snr / (1.0 + snr)
+        }
+    }
+}
+
+#[test]
+fn test_denoiser() {
+    let fs = 48000;
+    let fft_size = 1024;
+
+    // White noise from a linear congruential generator and a sine
+    // which starts after one second.
+    let mut seed = 1_u32;
+    let mut noise = || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        0.01 * ((seed >> 16) as f64 / 32768.0 - 1.0)
+    };
+    let tone = |n: usize| {
+        let phi = 2.0 * std::f64::consts::PI * 1000.0 * n as f64 / fs as f64;
+        if n >= fs {
+            0.5 * phi.sin()
+        } else {
+            0.0
+        }
+    };
+    let signal: Vec<f32> =
+        (0..2 * fs).map(|n| (tone(n) + noise()) as f32).collect();
+
+    let mut stft = Stft::new(fft_size, fft_size / 4, Window::Hann).unwrap();
+    let mut powers = vec![0.0; fft_size / 2 + 1];
+    stft.process(&signal[..fs], |x| {
+        for (power, x) in powers.iter_mut().zip(x) {
+            *power += x.norm_sqr();
+        }
+    });
+    let frames = stft.frames() as f64;
+    let profile = Profile {
+        sample_rate: fs as u32,
+        fft_size,
+        powers: vec![powers.iter().map(|x| x / frames).collect()],
+    };
+    let restored: Profile = profile.to_string().parse().unwrap();
+    assert_eq!(restored, profile);
+
+    let settings = ApplySettings {
+        profile: String::new(),
+        method: Method::Wiener,
+        reduction_db: 20.0,
+        factor: 2.0,
+        smoothing: 0.5,
+        width: 1,
+    };
+    let mut denoiser = Denoiser::new(&profile, 1, &settings).unwrap();
+    assert!(Denoiser::new(&profile, 2, &settings).is_err());
+    let latency = denoiser.latency();
+
+    let mut output = Vec::new();
+    for chunk in signal.chunks(1000) {
+        let mut block = AudioBuffer::new(1, chunk.len());
+        for x in chunk {
+            block.push_frame([*x]).unwrap();
+        }
+        denoiser.process(&mut block).unwrap();
+        output.extend_from_slice(block.channel(0));
+    }
+    let output: Vec<f64> =
+        output[latency..].iter().map(|x| *x as f64).collect();
+
+    // Noise is reduced and the tone passes almost unchanged.
+    let power = |x: &[f64]| x.iter().map(|x| x * x).sum::<f64>();
+    let noise_power = power(&output[fs / 4..3 * fs / 4])
+        / power(
+            &signal[fs / 4..3 * fs / 4]
+                .iter()
+                .map(|x| *x as f64)
+                .collect::<Vec<f64>>(),
+        );
+    assert!(10.0 * noise_power.log10() < -12.0);
+    let range = fs + fs / 4..output.len();
+    let error: Vec<f64> = range.clone().map(|n| output[n] - tone(n)).collect();
+    let tone_power: Vec<f64> = range.map(tone).collect();
+    assert!(10.0 * (power(&error) / power(&tone_power)).log10() < -40.0);
+}
@@ -0,0 +1,330 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::frame::FrameIterator;
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+
+/// Dynamic range of the exponential and logarithmic fade shapes in dB.
+const FADE_RANGE_DB: f64 = 60.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FadeShape {
+    /// Gain changes linearly.
+    Linear,
+    /// Level in dB changes linearly, slow start and fast end.
+    Exponential,
+    /// Mirrored exponential shape, fast start and slow end.
+    Logarithmic,
+    /// Raised cosine, slow start and slow end.
+    SCurve,
+}
+
+impl FadeShape {
+    /// Gain of a fade-in at the relative position "x" between zero and one.
+    /// A fade-out uses "1 - x".
+    pub fn gain(&self, x: f64) -> f64 {
+        let x = x.clamp(0.0, 1.0);
+        let exponential =
+            |x: f64| 10.0_f64.powf(FADE_RANGE_DB * (x - 1.0) / 20.0);
+        match self {
+            Self::Linear => x,
+            Self::Exponential if x == 0.0 => 0.0,
+            Self::Exponential => exponential(x),
+            Self::Logarithmic if x == 1.0 => 1.0,
+            Self::Logarithmic => 1.0 - exponential(1.0 - x),
+            Self::SCurve => 0.5 - 0.5 * (std::f64::consts::PI * x).cos(),
+        }
+    }
+}
+
+/// Gain envelope point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Breakpoint {
+    /// Time in seconds
+    time: f64,
+    /// Gain in dB
+    gain_db: f64,
+}
+
+impl std::str::FromStr for Breakpoint {
+    type Err = String;
+
+    /// Parses "time:gain_db", for example "1.5:-6".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (time, gain_db) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid breakpoint '{}'", s))?;
+        match (time.trim().parse::<f64>(), gain_db.trim().parse::<f64>()) {
+            (Ok(time), Ok(gain_db)) if time >= 0.0 => {
+                Ok(Self { time, gain_db })
+            }
+            _ => Err(format!("Invalid breakpoint '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Fade-in duration in seconds.
+    #[arg(long, default_value_t = 0.0)]
+    fade_in: f64,
+    /// Fade-out duration in seconds.
+    #[arg(long, default_value_t = 0.0)]
+    fade_out: f64,
+    /// Shape of the fade-in and fade-out.
+    #[arg(short, long, value_enum, default_value_t = FadeShape::Linear)]
+    shape: FadeShape,
+    /// Gain envelope breakpoints as "time:gain_db" separated by commas.
+    /// The gain in dB is interpolated linearly between breakpoints.
+    #[arg(short, long, value_delimiter = ',', allow_hyphen_values = true)]
+    point: Vec<Breakpoint>,
+    /// File with gain envelope breakpoints, one "time:gain_db" per line.
+    /// Empty lines and lines starting with '#' are ignored.
+    #[arg(short, long)]
+    envelope_file: Option<String>,
+}
+
+impl Settings {
+    pub fn fade<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+
+        let mut points = self.point.clone();
+        if let Some(filename) = &self.envelope_file {
+            points
+                .extend(read_breakpoints(&std::fs::read_to_string(filename)?)?);
+        }
+        let mut envelope = Envelope::new(
+            spec.sample_rate as f64,
+            duration as usize,
+            self,
+            points,
+        )?;
+
+        let mut progress = Progress::new(duration as usize, "Fading sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    envelope.process(block);
+                    block.write(output)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses breakpoints from the content of an envelope file.
+fn read_breakpoints(content: &str) -> Result<Vec<Breakpoint>, Error> {
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.parse().map_err(Error::InvalidArgument))
+        .collect()
+}
+
+/// Per sample gain automation with fades
+#[derive(Debug)]
+pub struct Envelope {
+    /// Sampling frequency
+    fs: f64,
+    /// Total signal length in frames
+    length: usize,
+    /// Fade-in length in frames
+    fade_in: usize,
+    /// Fade-out length in frames
+    fade_out: usize,
+    /// Fade shape
+    shape: FadeShape,
+    /// Breakpoints sorted by time
+    points: Vec<Breakpoint>,
+    /// Index of the next breakpoint after the current position.
+    next_point: usize,
+    /// Current position in frames
+    pos: usize,
+}
+
+impl Envelope {
+    pub fn new(
+        fs: f64,
+        length: usize,
+        settings: &Settings,
+        mut points: Vec<Breakpoint>,
+    ) -> Result<Self, Error> {
+        if settings.fade_in < 0.0 || settings.fade_out < 0.0 {
+            return Err(Error::InvalidArgument(
+                "Fade durations must not be negative.".into(),
+            ));
+        }
+        points.sort_by(|a, b| {
+            a.time
+                .partial_cmp(&b.time)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        Ok(Self {
+            points,
+            ..Self::from_fades(
+                fs,
+                length,
+                (settings.fade_in * fs).round() as usize,
+                (settings.fade_out * fs).round() as usize,
+                settings.shape,
+            )
+        })
+    }
+
+    /// Constructs an envelope without breakpoints. The fade lengths
+    /// are given in frames.
+    pub fn from_fades(
+        fs: f64,
+        length: usize,
+        fade_in: usize,
+        fade_out: usize,
+        shape: FadeShape,
+    ) -> Self {
+        Self {
+            fs,
+            length,
+            fade_in,
+            fade_out,
+            shape,
+            points: Vec::new(),
+            next_point: 0,
+            pos: 0,
+        }
+    }
+
+    /// Gain of the current frame.
+    fn gain(&mut self) -> f64 {
+        let mut gain = 1.0;
+        if self.pos < self.fade_in {
+            gain *= self.shape.gain(self.pos as f64 / self.fade_in as f64);
+        }
+        let remaining = self.length.saturating_sub(self.pos + 1);
+        if remaining < self.fade_out {
+            gain *= self.shape.gain(remaining as f64 / self.fade_out as f64);
+        }
+
+        let t = self.pos as f64 / self.fs;
+        while self.next_point < self.points.len()
+            && self.points[self.next_point].time <= t
+        {
+            self.next_point += 1;
+        }
+        let gain_db = match (
+            self.points.get(self.next_point.wrapping_sub(1)),
+            self.points.get(self.next_point),
+        ) {
+            (None, None) => 0.0,
+            (Some(a), None) => a.gain_db,
+            (None, Some(b)) => b.gain_db,
+            (Some(a), Some(b)) => {
+                a.gain_db
+                    + (b.gain_db - a.gain_db) * (t - a.time) / (b.time - a.time)
+            }
+        };
+
+        gain * 10.0_f64.powf(gain_db / 20.0)
+    }
+
+    /// Applies the envelope to a block of frames in place.
+    pub fn process(&mut self, block: &mut AudioBuffer) {
+        for i in 0..block.len() {
+            let gain = self.gain();
+            for channel in block.channels_mut() {
+                channel[i] = (channel[i] as f64 * gain) as f32;
+            }
+            self.pos += 1;
+        }
+    }
+}
+
+#[test]
+fn test_fade_shapes() {
+    for shape in [
+        FadeShape::Linear,
+        FadeShape::Exponential,
+        FadeShape::Logarithmic,
+        FadeShape::SCurve,
+    ] {
+        assert_eq!(shape.gain(0.0), 0.0);
+        assert_eq!(shape.gain(1.0), 1.0);
+        let mut last = 0.0;
+        for i in 1..=100 {
+            let gain = shape.gain(i as f64 / 100.0);
+            assert!(gain > last);
+            last = gain;
+        }
+    }
+    assert!(FadeShape::Exponential.gain(0.5) < 0.5);
+    assert!(FadeShape::Logarithmic.gain(0.5) > 0.5);
+    assert!((FadeShape::SCurve.gain(0.5) - 0.5).abs() < 1e-12);
+}
+
+#[test]
+fn test_envelope() {
+    let settings = Settings {
+        fade_in: 0.01,
+        fade_out: 0.01,
+        shape: FadeShape::Linear,
+        point: Vec::new(),
+        envelope_file: None,
+    };
+    let points =
+        read_breakpoints("# time:gain_db\n0.05:0\n\n0.03:-20\n").unwrap();
+    assert!(read_breakpoints("1.0 -6").is_err());
+
+    let mut block = AudioBuffer::new(2, 100);
+    for _ in 0..100 {
+        block.push_frame([1.0, -1.0]).unwrap();
+    }
+    let mut envelope = Envelope::new(1000.0, 100, &settings, points).unwrap();
+    envelope.process(&mut block);
+
+    let expected = |i: usize| {
+        let fade = (i as f64 / 10.0).min(1.0).min((99 - i) as f64 / 10.0);
+        let gain_db = match i {
+            0..=30 => -20.0,
+            31..=49 => -20.0 + (i - 30) as f64,
+            _ => 0.0,
+        };
+        fade * 10.0_f64.powf(gain_db / 20.0)
+    };
+    for i in 0..100 {
+        assert!((block.channel(0)[i] as f64 - expected(i)).abs() < 1e-6);
+        assert!((block.channel(1)[i] as f64 + expected(i)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,295 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::compressor::{delay_lines, PeakDetector};
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::filters::{lag1::Lag1, Filter};
+use crate::frame::FrameIterator;
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Peak detector to use
+    detector: PeakDetector,
+    /// Gate threshold in dB
+    threshold_db: f64,
+    /// Maximum attenuation in dB.
+    #[arg(long, default_value_t = 80.0)]
+    range_db: f64,
+    /// Expansion ratio. Must be greater than one.
+    /// The gate acts as a downward expander if given.
+    #[arg(long)]
+    ratio: Option<f64>,
+    /// Gate attack time in seconds.
+    #[arg(long, default_value_t = 0.001)]
+    attack_time: f64,
+    /// Gate hold time in seconds.
+    #[arg(long, default_value_t = 0.05)]
+    hold_time: f64,
+    /// Gate release time in seconds.
+    #[arg(long, default_value_t = 0.1)]
+    release_time: f64,
+    /// Gate lookahead time in seconds.
+    #[arg(long, default_value_t = 0.005)]
+    lookahead_time: f64,
+    /// Distance between opening and closing threshold in dB.
+    #[arg(long, default_value_t = 6.0)]
+    hysteresis_db: f64,
+}
+
+impl Settings {
+    pub fn gate<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+
+        let mut gate =
+            Gate::new(spec.sample_rate as f64, spec.channels as usize, self)?;
+
+        let latency = gate.latency();
+        let mut progress = Progress::new(duration as usize, "Gating sample");
+        FrameIterator::new(input.samples_f32(), spec.channels)
+            .process_delayed(output, latency, &mut progress, |block, _| {
+                gate.process(block)
+            })?;
+
+        Ok(())
+    }
+}
+
+/// Noise gate and downward expander
+#[derive(Debug)]
+pub struct Gate {
+    /// Number of channels
+    channels: usize,
+    /// Envelope detector preprocessing filter
+    preprocessor: Box<dyn Filter>,
+    /// Preprocessing filter working buffer with interleaved samples.
+    preproc_buffer: Vec<f64>,
+    /// Lookahead in samples, this is also the filter latency.
+    lookahead: usize,
+    /// Gain smoothing filter, rises with attack and falls with release time.
+    smoothing: Lag1,
+    /// Envelope and gain working buffer, one value per frame.
+    gain_buffer: Vec<f64>,
+    /// Filter input data delay lines, one for each channel.
+    delay: Vec<VecDeque<f32>>,
+    /// Gate state, true if the gate is open.
+    open: bool,
+    /// True if the gain smoothing filter is initialized.
+    initialized: bool,
+    /// Gate opening threshold in dB.
+    threshold_db: f64,
+    /// Maximum attenuation in dB.
+    range_db: f64,
+    /// Expansion ratio, hard gate if none.
+    ratio: Option<f64>,
+    /// Distance between opening and closing threshold in dB.
+    hysteresis_db: f64,
+}
+
+impl Gate {
+    pub fn new(
+        fs: f64,
+        channels: usize,
+        settings: &Settings,
+    ) -> Result<Self, Error> {
+        if settings.ratio.map_or(false, |x| x <= 1.0) {
+            return Err(Error::InvalidArgument(
+                "Ratio must be greater than one.".into(),
+            ));
+        }
+        if settings.range_db < 0.0 || settings.hysteresis_db < 0.0 {
+            return Err(Error::InvalidArgument(
+                "Range and hysteresis must not be negative.".into(),
+            ));
+        }
+
+        let lookahead = (settings.lookahead_time * fs) as usize;
+        let hold = (settings.hold_time * fs) as usize;
+        let preprocessor = settings
+            .detector
+            .filter((lookahead + hold).max(1) * channels);
+
+        Ok(Self {
+            channels,
+            preprocessor,
+            preproc_buffer: Vec::new(),
+            lookahead,
+            smoothing: Lag1::new(
+                1.0,
+                settings.attack_time,
+                settings.release_time,
+                fs,
+            ),
+            gain_buffer: Vec::new(),
+            delay: delay_lines(channels, lookahead),
+            open: false,
+            initialized: false,
+            threshold_db: settings.threshold_db,
+            range_db: settings.range_db,
+            ratio: settings.ratio,
+            hysteresis_db: settings.hysteresis_db,
+        })
+    }
+
+    pub fn latency(&self) -> usize {
+        self.lookahead
+    }
+
+    /// Gates a block of frames in place. The output is delayed
+    /// by "latency()" frames.
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        if block.channel_count() != self.channels {
+            return Err(Error::InvalidFrame);
+        }
+
+        // All samples of a frame are fed into the preprocessing filter
+        // and its last output value is used for the frame.
+        self.preproc_buffer.clear();
+        self.preproc_buffer
+            .extend(block.interleaved().map(|x| x as f64));
+        self.preprocessor.process_block(&mut self.preproc_buffer);
+
+        self.gain_buffer.clear();
+        for i in 0..block.len() {
+            let env = self.preproc_buffer[(i + 1) * self.channels - 1];
+            let gain = self.gain(env);
+            self.gain_buffer.push(gain);
+        }
+        if !self.initialized && !self.gain_buffer.is_empty() {
+            // Start with settled gain instead of fading in.
+            self.smoothing.reset(self.gain_buffer[0]);
+            self.initialized = true;
+        }
+        self.smoothing.process_block(&mut self.gain_buffer);
+
+        for (channel, delay) in block.channels_mut().zip(self.delay.iter_mut())
+        {
+            for (x, gain) in channel.iter_mut().zip(&self.gain_buffer) {
+                delay.push_back(*x);
+                *x = delay.pop_front().unwrap_or(0.0) * (*gain as f32);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates the gate state and calculates the target gain
+    /// for the given envelope value.
+    fn gain(&mut self, env: f64) -> f64 {
+        // Prevent infinite values with a very low dB value
+        // if envelope is zero.
+        let env_db = (20.0 * env.log10()).max(-200.0);
+
+        if self.open && env_db < self.threshold_db - self.hysteresis_db {
+            self.open = false;
+        } else if !self.open && env_db >= self.threshold_db {
+            self.open = true;
+        }
+
+        if self.open {
+            return 1.0;
+        }
+        let gain_db = match self.ratio {
+            Some(ratio) => ((env_db - self.threshold_db) * (ratio - 1.0))
+                .clamp(-self.range_db, 0.0),
+            None => -self.range_db,
+        };
+        10.0_f64.powf(gain_db / 20.0)
+    }
+}
+
+#[test]
+fn test_gate() {
+    let settings = Settings {
+        detector: PeakDetector::Peak,
+        threshold_db: -40.0,
+        range_db: 80.0,
+        ratio: None,
+        attack_time: 0.001,
+        hold_time: 0.01,
+        release_time: 0.01,
+        lookahead_time: 0.001,
+        hysteresis_db: 6.0,
+    };
+
+    // Loud tone followed by a tone at -44 dB and -60 dB.
+    let fs = 48000;
+    let mut input = AudioBuffer::new(1, 3 * fs);
+    for (i, amplitude) in [0.5, 0.0063, 0.001]
+        .iter()
+        .flat_map(|x| vec![x; fs])
+        .enumerate()
+    {
+        let phi = 2.0 * std::f32::consts::PI * 1000.0 * i as f32 / fs as f32;
+        input.push_frame([amplitude * phi.sin()]).unwrap();
+    }
+    let peak = |block: &AudioBuffer, start: f64, end: f64| {
+        block.channel(0)
+            [(start * fs as f64) as usize..(end * fs as f64) as usize]
+            .iter()
+            .fold(0.0_f32, |acc, x| acc.max(x.abs()))
+    };
+
+    // Hysteresis keeps the gate open for the -44 dB tone.
+    let mut output = input.clone();
+    let mut gate = Gate::new(fs as f64, 1, &settings).unwrap();
+    gate.process(&mut output).unwrap();
+    assert!((peak(&output, 0.1, 0.9) - 0.5).abs() < 1e-3);
+    assert!((peak(&output, 1.1, 1.9) - 0.0063).abs() < 1e-4);
+    assert!(peak(&output, 2.2, 2.9) < 1e-6);
+
+    // Without hysteresis, the gate closes.
+    let mut output = input.clone();
+    let mut gate = Gate::new(
+        fs as f64,
+        1,
+        &Settings {
+            hysteresis_db: 0.0,
+            ..settings.clone()
+        },
+    )
+    .unwrap();
+    gate.process(&mut output).unwrap();
+    assert!(peak(&output, 1.2, 1.9) < 1e-6);
+
+    // The expander attenuates -20 dB below threshold by 20 dB.
+    let mut output = input.clone();
+    let mut gate = Gate::new(
+        fs as f64,
+        1,
+        &Settings {
+            ratio: Some(2.0),
+            ..settings.clone()
+        },
+    )
+    .unwrap();
+    gate.process(&mut output).unwrap();
+    assert!((peak(&output, 2.2, 2.9) - 1e-4).abs() < 1e-5);
+}
@@ -17,3 +17,16 @@
 \******************************************************************************/
 pub mod amplify;
 pub mod compressor;
+pub mod declick;
+pub mod declip;
+pub mod deesser;
+pub mod dehum;
+pub mod delay;
+pub mod denoise;
+pub mod fade;
+pub mod gate;
+pub mod modulation;
+pub mod multiband;
+pub mod reverb;
+pub mod saturation;
+pub mod stereo;
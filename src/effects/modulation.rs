@@ -0,0 +1,436 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::frame::FrameIterator;
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+
+/// Lowest phaser sweep frequency in Hz
+const PHASER_MIN_FREQUENCY: f64 = 200.0;
+/// Highest phaser sweep frequency in Hz
+const PHASER_MAX_FREQUENCY: f64 = 6000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Effect {
+    /// Modulated delay of 10 to 25 ms
+    Chorus,
+    /// Modulated delay of 0.5 to 5 ms
+    Flanger,
+    /// Modulated cascade of first order allpass filters
+    Phaser,
+}
+
+impl Effect {
+    /// Default rate in Hz, depth, feedback and mix.
+    fn defaults(self) -> (f64, f64, f64, f64) {
+        match self {
+            Self::Chorus => (0.8, 0.5, 0.0, 0.5),
+            Self::Flanger => (0.25, 0.7, 0.5, 0.5),
+            Self::Phaser => (0.5, 0.8, 0.3, 0.5),
+        }
+    }
+
+    /// Base delay and maximum delay swing in seconds.
+    fn delay_range(self) -> (f64, f64) {
+        match self {
+            Self::Chorus => (0.010, 0.015),
+            Self::Flanger => (0.0005, 0.0045),
+            Self::Phaser => (0.0, 0.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Modulation effect type
+    #[arg(value_enum)]
+    effect: Effect,
+    /// LFO rate in Hz.
+    #[arg(short, long)]
+    rate: Option<f64>,
+    /// Modulation depth between zero and one.
+    #[arg(short, long)]
+    depth: Option<f64>,
+    /// Feedback gain, must be less than one.
+    #[arg(short, long)]
+    feedback: Option<f64>,
+    /// LFO phase offset between adjacent channels in degrees.
+    #[arg(short, long, default_value_t = 90.0)]
+    phase_offset: f64,
+    /// Dry/wet mix, 0 is dry only and 1 is wet only.
+    #[arg(short, long)]
+    mix: Option<f64>,
+    /// Number of phaser allpass stages.
+    #[arg(short, long, default_value_t = 4)]
+    stages: usize,
+}
+
+impl Settings {
+    pub fn modulate<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+
+        let mut modulation = Modulation::new(
+            spec.sample_rate as f64,
+            spec.channels as usize,
+            self,
+        )?;
+
+        let mut progress =
+            Progress::new(duration as usize, "Modulating sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    modulation.process(block)?;
+                    block.write(output)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sine low frequency oscillator
+#[derive(Clone, Debug)]
+pub struct Lfo {
+    /// Current phase in periods
+    phase: f64,
+    /// Phase increment per sample in periods
+    increment: f64,
+}
+
+impl Lfo {
+    /// Creates an oscillator with frequency "rate" in Hz and
+    /// initial phase "phase" in periods.
+    pub fn new(fs: f64, rate: f64, phase: f64) -> Self {
+        Self {
+            phase: phase.rem_euclid(1.0),
+            increment: rate / fs,
+        }
+    }
+
+    /// Returns the next oscillator value between -1 and 1.
+    pub fn next_value(&mut self) -> f64 {
+        let value = (2.0 * std::f64::consts::PI * self.phase).sin();
+        self.phase = (self.phase + self.increment).fract();
+        value
+    }
+}
+
+/// Delay line with fractional delay read-out using
+/// 4-point cubic Hermite interpolation.
+#[derive(Clone, Debug)]
+pub struct FractionalDelay {
+    /// Ring buffer with past input samples
+    buffer: Vec<f64>,
+    /// Write position of the next sample
+    pos: usize,
+}
+
+impl FractionalDelay {
+    /// Creates a delay line for delays up to "max_delay" samples.
+    pub fn new(max_delay: usize) -> Self {
+        Self {
+            buffer: vec![0.0; max_delay + 3],
+            pos: 0,
+        }
+    }
+
+    /// Appends a sample to the delay line.
+    pub fn push(&mut self, x: f64) {
+        self.buffer[self.pos] = x;
+        self.pos = (self.pos + 1) % self.buffer.len();
+    }
+
+    /// Returns the input "delay" samples before the next pushed sample,
+    /// i.e. a delay of 1 returns the last pushed sample. Delays are clamped
+    /// to the interpolation support between 2 and "max_delay".
+    pub fn read(&self, delay: f64) -> f64 {
+        let len = self.buffer.len();
+        let delay = delay.clamp(2.0, (len - 3) as f64);
+        let whole = delay.floor() as usize;
+        let frac = delay - whole as f64;
+        if frac == 0.0 {
+            return self.buffer[(self.pos + len - whole) % len];
+        }
+        let sample = |d: usize| self.buffer[(self.pos + len - d) % len];
+
+        // Samples around the requested position, ordered by time.
+        let (y0, y1, y2, y3) = (
+            sample(whole + 2),
+            sample(whole + 1),
+            sample(whole),
+            sample(whole - 1),
+        );
+        // Interpolation runs forward in time, so the position between
+        // y1 and y2 is 1 - frac.
+        let t = 1.0 - frac;
+        let c1 = 0.5 * (y2 - y0);
+        let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+        let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+        ((c3 * t + c2) * t + c1) * t + y1
+    }
+}
+
+/// First order allpass filter with variable coefficient.
+#[derive(Clone, Debug, Default)]
+struct Allpass1 {
+    /// Previous input value
+    input: f64,
+    /// Previous output value
+    output: f64,
+}
+
+impl Allpass1 {
+    fn process(&mut self, x: f64, a: f64) -> f64 {
+        let y = a * x + self.input - a * self.output;
+        self.input = x;
+        self.output = y;
+        y
+    }
+}
+
+/// Processing state of a single channel.
+#[derive(Clone, Debug)]
+struct Channel {
+    /// Modulation oscillator
+    lfo: Lfo,
+    /// Modulated delay line
+    delay: FractionalDelay,
+    /// Phaser allpass stages
+    allpass: Vec<Allpass1>,
+    /// Last wet output for the feedback path.
+    last: f64,
+}
+
+/// Chorus, flanger and phaser effects
+#[derive(Debug)]
+pub struct Modulation {
+    /// Effect type
+    effect: Effect,
+    /// Sampling frequency in Hz
+    fs: f64,
+    /// Channel states
+    channels: Vec<Channel>,
+    /// Base delay in samples
+    base_delay: f64,
+    /// Maximum delay swing in samples
+    swing: f64,
+    /// Modulation depth
+    depth: f64,
+    /// Feedback gain
+    feedback: f64,
+    /// Dry/wet mix
+    mix: f64,
+}
+
+impl Modulation {
+    pub fn new(
+        fs: f64,
+        channels: usize,
+        settings: &Settings,
+    ) -> Result<Self, Error> {
+        let (rate, depth, feedback, mix) = settings.effect.defaults();
+        let rate = settings.rate.unwrap_or(rate);
+        let depth = settings.depth.unwrap_or(depth);
+        let feedback = settings.feedback.unwrap_or(feedback);
+        let mix = settings.mix.unwrap_or(mix);
+
+        if rate <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "Rate must be greater than zero.".into(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&depth) || !(0.0..=1.0).contains(&mix) {
+            return Err(Error::InvalidArgument(
+                "Depth and mix must be between zero and one.".into(),
+            ));
+        }
+        if feedback.abs() >= 1.0 {
+            return Err(Error::InvalidArgument(
+                "Feedback must be less than one.".into(),
+            ));
+        }
+        if settings.effect == Effect::Phaser && settings.stages == 0 {
+            return Err(Error::InvalidArgument(
+                "Phaser needs at least one stage.".into(),
+            ));
+        }
+
+        let (base_delay, swing) = settings.effect.delay_range();
+        let (base_delay, swing) = (base_delay * fs, swing * fs);
+        let channels = (0..channels)
+            .map(|i| Channel {
+                lfo: Lfo::new(
+                    fs,
+                    rate,
+                    i as f64 * settings.phase_offset / 360.0,
+                ),
+                delay: FractionalDelay::new(
+                    (base_delay + swing).ceil() as usize + 2,
+                ),
+                allpass: vec![Allpass1::default(); settings.stages],
+                last: 0.0,
+            })
+            .collect();
+
+        Ok(Self {
+            effect: settings.effect,
+            fs,
+            channels,
+            base_delay,
+            swing,
+            depth,
+            feedback,
+            mix,
+        })
+    }
+
+    /// Applies the effect to a block of frames in place.
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        if block.channel_count() != self.channels.len() {
+            return Err(Error::InvalidFrame);
+        }
+
+        for (samples, state) in
+            block.channels_mut().zip(self.channels.iter_mut())
+        {
+            for x in samples.iter_mut() {
+                let input = *x as f64;
+                let modulation = 0.5 * (1.0 + state.lfo.next_value());
+                let wet = match self.effect {
+                    Effect::Chorus | Effect::Flanger => {
+                        let delay = self.base_delay
+                            + self.swing * self.depth * modulation;
+                        let wet = state.delay.read(delay);
+                        state.delay.push(input + self.feedback * wet);
+                        wet
+                    }
+                    Effect::Phaser => {
+                        let frequency = PHASER_MIN_FREQUENCY
+                            * (PHASER_MAX_FREQUENCY / PHASER_MIN_FREQUENCY)
+                                .powf(self.depth * modulation);
+                        let tan = (std::f64::consts::PI
+                            * frequency.min(0.45 * self.fs)
+                            / self.fs)
+                            .tan();
+                        let a = (tan - 1.0) / (tan + 1.0);
+                        state.allpass.iter_mut().fold(
+                            input + self.feedback * state.last,
+                            |acc, stage| stage.process(acc, a),
+                        )
+                    }
+                };
+                state.last = wet;
+                *x = ((1.0 - self.mix) * input + self.mix * wet) as f32;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_fractional_delay() {
+    let mut delay = FractionalDelay::new(16);
+    for i in 0..20 {
+        delay.push(i as f64);
+    }
+
+    // Cubic interpolation reproduces a linear ramp exactly.
+    // Delays below the interpolation support are clamped.
+    assert_eq!(delay.read(1.0), 18.0);
+    assert_eq!(delay.read(5.0), 15.0);
+    assert!((delay.read(4.25) - 15.75).abs() < 1e-12);
+    assert!((delay.read(10.5) - 9.5).abs() < 1e-12);
+
+    let mut lfo = Lfo::new(8.0, 1.0, 0.25);
+    assert!((lfo.next_value() - 1.0).abs() < 1e-12);
+    for _ in 0..3 {
+        lfo.next_value();
+    }
+    assert!((lfo.next_value() + 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_modulation() {
+    let fs = 8000;
+    let settings = Settings {
+        effect: Effect::Chorus,
+        rate: Some(1.0),
+        depth: Some(0.0),
+        feedback: Some(0.0),
+        phase_offset: 90.0,
+        mix: Some(1.0),
+        stages: 4,
+    };
+    let mut input = AudioBuffer::new(1, fs);
+    for i in 0..fs {
+        let t = i as f32 / fs as f32;
+        input
+            .push_frame([(2.0 * std::f32::consts::PI * 83.0 * t).sin()])
+            .unwrap();
+    }
+
+    // Unmodulated chorus is a plain delay by the base delay.
+    let mut output = input.clone();
+    let mut chorus = Modulation::new(fs as f64, 1, &settings).unwrap();
+    chorus.process(&mut output).unwrap();
+    for (x, y) in input.channel(0).iter().zip(&output.channel(0)[80..]) {
+        assert_eq!(x, y);
+    }
+
+    // A four stage phaser mixed with the dry signal cancels the frequency
+    // where each stage shifts the phase by 45 degrees.
+    let tan = (std::f64::consts::PI * PHASER_MIN_FREQUENCY / fs as f64).tan();
+    let notch = fs as f64 / std::f64::consts::PI
+        * (tan * (std::f64::consts::PI / 8.0).tan()).atan();
+    let mut input = AudioBuffer::new(1, fs);
+    for i in 0..fs {
+        let t = i as f64 / fs as f64;
+        input
+            .push_frame([(2.0 * std::f64::consts::PI * notch * t).sin() as f32])
+            .unwrap();
+    }
+    let mut phaser = Modulation::new(
+        fs as f64,
+        1,
+        &Settings {
+            effect: Effect::Phaser,
+            mix: Some(0.5),
+            ..settings
+        },
+    )
+    .unwrap();
+    phaser.process(&mut input).unwrap();
+    assert!(input.channel(0)[fs / 2..].iter().all(|x| x.abs() < 1e-3));
+}
@@ -0,0 +1,459 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::compressor::{self, Compressor};
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::filters::{biquad::Biquad, lag1::Lag1, Filter};
+use crate::frame::{FrameIterator, BLOCK_SIZE};
+use crate::progress::Progress;
+use clap::Parser;
+use hound::{WavReader, WavWriter};
+use std::collections::VecDeque;
+
+/// Quality factor of the Butterworth sections of a Linkwitz-Riley filter
+const BUTTERWORTH_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+/// Processing of a single band
+#[derive(Debug, Clone)]
+pub enum Band {
+    /// Pass the band unchanged
+    Bypass,
+    /// Compress the band
+    Compress(compressor::Settings),
+}
+
+/// Parser for compressor settings of a single band.
+#[derive(Debug, Parser)]
+#[command(no_binary_name = true, allow_negative_numbers = true)]
+struct BandArgs {
+    #[command(flatten)]
+    settings: compressor::Settings,
+}
+
+impl std::str::FromStr for Band {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("bypass") {
+            return Ok(Self::Bypass);
+        }
+        BandArgs::try_parse_from(s.split_whitespace())
+            .map(|x| Self::Compress(x.settings))
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Settings {
+    /// Crossover frequencies in Hz in ascending order, separated by commas.
+    #[arg(short, long, value_delimiter = ',', required = true)]
+    crossovers: Vec<f64>,
+    /// Band settings, lowest band first. Either "bypass" or the
+    /// compressor arguments, e.g. "peak -20 4 6 0.01 0.1 0.005 0.05 0".
+    /// Must be given once per band.
+    #[arg(short, long, required = true)]
+    band: Vec<Band>,
+}
+
+impl Settings {
+    pub fn compress<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+
+        let mut multiband = Multiband::new(
+            spec.sample_rate as f64,
+            spec.channels as usize,
+            self,
+        )?;
+
+        let attack_time = self
+            .band
+            .iter()
+            .map(|x| match x {
+                Band::Bypass => 0.0,
+                Band::Compress(x) => x.attack_time(),
+            })
+            .fold(0.0, f64::max);
+        Self::compensate_initial_condition(
+            &mut multiband,
+            input,
+            spec.channels,
+            spec.sample_rate as f64,
+            attack_time,
+        )?;
+        input.seek(0)?;
+
+        let latency = multiband.latency();
+        let mut progress =
+            Progress::new(duration as usize, "Compressing sample");
+        FrameIterator::new(input.samples_f32(), spec.channels)
+            .process_delayed(output, latency, &mut progress, |block, _| {
+                multiband.process(block)
+            })?;
+
+        Ok(())
+    }
+
+    fn compensate_initial_condition<R>(
+        multiband: &mut Multiband,
+        input: &mut WavReader<R>,
+        channels: u16,
+        fs: f64,
+        attack_time: f64,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let mut remaining = Lag1::settling_len(fs, attack_time);
+        let mut frames = FrameIterator::new(input.samples_f32(), channels);
+        while let Some(block) = frames.next() {
+            if remaining == 0 {
+                break;
+            }
+            match block {
+                Ok(block) => {
+                    block.truncate(remaining);
+                    multiband.process_initial(block)?;
+                    remaining -= block.len();
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        multiband.crossover.reset();
+
+        Ok(())
+    }
+}
+
+/// Crossover point of a Linkwitz-Riley crossover network for one channel.
+#[derive(Clone, Debug)]
+struct Split {
+    /// 4th order Linkwitz-Riley lowpass filter
+    lowpass: [Biquad; 2],
+    /// 4th order Linkwitz-Riley highpass filter
+    highpass: [Biquad; 2],
+    /// Allpass filters which match the phase of the low band to the
+    /// higher crossover points.
+    allpass: Vec<Biquad>,
+}
+
+/// Linkwitz-Riley crossover network. The sum of all bands has a flat
+/// magnitude response.
+#[derive(Clone, Debug)]
+pub struct Crossover {
+    /// Crossover points, one set for each channel.
+    splits: Vec<Vec<Split>>,
+    /// Remaining high frequency signal working buffer.
+    rest: Vec<f64>,
+    /// Band signal working buffer.
+    band: Vec<f64>,
+}
+
+impl Crossover {
+    pub fn new(
+        fs: f64,
+        channels: usize,
+        frequencies: &[f64],
+    ) -> Result<Self, Error> {
+        if frequencies.iter().any(|x| *x <= 0.0 || *x >= fs / 2.0)
+            || frequencies.windows(2).any(|x| x[0] >= x[1])
+        {
+            return Err(Error::InvalidArgument(
+                "Crossover frequencies must be ascending and \
+                 below the Nyquist frequency."
+                    .into(),
+            ));
+        }
+
+        let splits: Vec<Split> = frequencies
+            .iter()
+            .enumerate()
+            .map(|(i, f0)| Split {
+                lowpass: [
+                    Biquad::lowpass(fs, *f0, BUTTERWORTH_Q),
+                    Biquad::lowpass(fs, *f0, BUTTERWORTH_Q),
+                ],
+                highpass: [
+                    Biquad::highpass(fs, *f0, BUTTERWORTH_Q),
+                    Biquad::highpass(fs, *f0, BUTTERWORTH_Q),
+                ],
+                allpass: frequencies[i + 1..]
+                    .iter()
+                    .map(|f| Biquad::allpass(fs, *f, BUTTERWORTH_Q))
+                    .collect(),
+            })
+            .collect();
+
+        Ok(Self {
+            splits: vec![splits; channels],
+            rest: Vec::new(),
+            band: Vec::new(),
+        })
+    }
+
+    /// Number of output bands.
+    pub fn band_count(&self) -> usize {
+        self.splits.first().map_or(0, |x| x.len()) + 1
+    }
+
+    /// Resets all filter states to zero.
+    pub fn reset(&mut self) {
+        for split in self.splits.iter_mut().flatten() {
+            split
+                .lowpass
+                .iter_mut()
+                .chain(split.highpass.iter_mut())
+                .chain(split.allpass.iter_mut())
+                .for_each(|x| x.reset());
+        }
+    }
+
+    /// Splits a block of frames into bands, lowest band first.
+    pub fn process(
+        &mut self,
+        input: &AudioBuffer,
+        bands: &mut [AudioBuffer],
+    ) -> Result<(), Error> {
+        if input.channel_count() != self.splits.len()
+            || bands.len() != self.band_count()
+            || bands.iter().any(|x| x.channel_count() != self.splits.len())
+        {
+            return Err(Error::InvalidFrame);
+        }
+
+        for band in bands.iter_mut() {
+            band.clear();
+            band.resize(input.len());
+        }
+
+        for (idx, (samples, splits)) in input
+            .channels()
+            .iter()
+            .zip(self.splits.iter_mut())
+            .enumerate()
+        {
+            self.rest.clear();
+            self.rest.extend(samples.iter().map(|x| *x as f64));
+
+            for (split, band) in splits.iter_mut().zip(bands.iter_mut()) {
+                self.band.clone_from(&self.rest);
+                for filter in split.lowpass.iter_mut().chain(&mut split.allpass)
+                {
+                    filter.process_block(&mut self.band);
+                }
+                for filter in split.highpass.iter_mut() {
+                    filter.process_block(&mut self.rest);
+                }
+                for (y, x) in band.channel_mut(idx).iter_mut().zip(&self.band) {
+                    *y = *x as f32;
+                }
+            }
+
+            if let Some(band) = bands.last_mut() {
+                for (y, x) in band.channel_mut(idx).iter_mut().zip(&self.rest) {
+                    *y = *x as f32;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Multiband dynamic range compressor
+#[derive(Debug)]
+pub struct Multiband {
+    /// Number of channels
+    channels: usize,
+    /// Band splitting filter network
+    crossover: Crossover,
+    /// Compressor for each band, none if the band is bypassed.
+    compressors: Vec<Option<Compressor>>,
+    /// Latency compensation delay lines for each band and channel.
+    delay: Vec<Vec<VecDeque<f32>>>,
+    /// Band signal working buffers.
+    bands: Vec<AudioBuffer>,
+    /// Latency of the slowest band in samples.
+    latency: usize,
+}
+
+impl Multiband {
+    pub fn new(
+        fs: f64,
+        channels: usize,
+        settings: &Settings,
+    ) -> Result<Self, Error> {
+        if settings.crossovers.is_empty() || settings.crossovers.len() > 4 {
+            return Err(Error::InvalidArgument(
+                "Between one and four crossover frequencies are supported."
+                    .into(),
+            ));
+        }
+        let crossover = Crossover::new(fs, channels, &settings.crossovers)?;
+        if settings.band.len() != crossover.band_count() {
+            return Err(Error::InvalidArgument(format!(
+                "Expected {} band settings.",
+                crossover.band_count()
+            )));
+        }
+
+        let compressors: Vec<Option<Compressor>> = settings
+            .band
+            .iter()
+            .map(|x| match x {
+                Band::Bypass => None,
+                Band::Compress(x) => Some(Compressor::new(fs, channels, x)),
+            })
+            .collect();
+        let latencies: Vec<usize> = compressors
+            .iter()
+            .map(|x| x.as_ref().map_or(0, |x| x.latency()))
+            .collect();
+        let latency = latencies.iter().copied().max().unwrap_or(0);
+
+        // Delay faster bands so that all bands are aligned.
+        let delay = latencies
+            .iter()
+            .map(|x| compressor::delay_lines(channels, latency - x))
+            .collect();
+
+        Ok(Self {
+            channels,
+            bands: vec![
+                AudioBuffer::new(channels, BLOCK_SIZE);
+                crossover.band_count()
+            ],
+            crossover,
+            compressors,
+            delay,
+            latency,
+        })
+    }
+
+    pub fn latency(&self) -> usize {
+        self.latency
+    }
+
+    /// Compresses a block of frames in place. The output is delayed
+    /// by "latency()" frames.
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        if block.channel_count() != self.channels {
+            return Err(Error::InvalidFrame);
+        }
+        self.crossover.process(block, &mut self.bands)?;
+
+        for ((band, compressor), delay) in self
+            .bands
+            .iter_mut()
+            .zip(self.compressors.iter_mut())
+            .zip(self.delay.iter_mut())
+        {
+            if let Some(compressor) = compressor {
+                compressor.process(band)?;
+            }
+            for (channel, delay) in band.channels_mut().zip(delay.iter_mut()) {
+                for x in channel.iter_mut() {
+                    delay.push_back(*x);
+                    *x = delay.pop_front().unwrap_or(0.0);
+                }
+            }
+        }
+
+        for (idx, channel) in block.channels_mut().enumerate() {
+            for (i, x) in channel.iter_mut().enumerate() {
+                *x = self.bands.iter().map(|band| band.channel(idx)[i]).sum();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates the envelope detector states without producing output.
+    pub fn process_initial(
+        &mut self,
+        block: &AudioBuffer,
+    ) -> Result<(), Error> {
+        self.crossover.process(block, &mut self.bands)?;
+        for (band, compressor) in
+            self.bands.iter().zip(self.compressors.iter_mut())
+        {
+            if let Some(compressor) = compressor {
+                compressor.process_initial(band)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_crossover_flat_sum() {
+    let fs = 48000.0;
+    let len = 16384;
+    let settings = Settings {
+        crossovers: vec![120.0, 800.0, 3000.0, 9000.0],
+        band: vec![Band::Bypass; 5],
+    };
+    let mut multiband = Multiband::new(fs, 1, &settings).unwrap();
+
+    let mut block = AudioBuffer::new(1, len);
+    block.resize(len);
+    block.channel_mut(0)[0] = 1.0;
+    multiband.process(&mut block).unwrap();
+
+    // The impulse response of the summed bands is an allpass.
+    for f in [20.0, 120.0, 500.0, 800.0, 2000.0, 3000.0, 9000.0, 20000.0] {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (n, x) in block.channel(0).iter().enumerate() {
+            let phi = 2.0 * std::f64::consts::PI * f / fs * n as f64;
+            re += *x as f64 * phi.cos();
+            im -= *x as f64 * phi.sin();
+        }
+        let magnitude_db = 10.0 * (re * re + im * im).log10();
+        assert!(magnitude_db.abs() < 0.01, "{} Hz: {} dB", f, magnitude_db);
+    }
+
+    // Each band is actually band limited.
+    let mut crossover = Crossover::new(fs, 1, &settings.crossovers).unwrap();
+    let mut input = AudioBuffer::new(1, len);
+    let mut bands = vec![AudioBuffer::new(1, len); 5];
+    for i in 0..len {
+        let phi = 2.0 * std::f32::consts::PI * 5000.0 * i as f32 / fs as f32;
+        input.push_frame([phi.sin()]).unwrap();
+    }
+    crossover.process(&input, &mut bands).unwrap();
+    let peak: Vec<f32> = bands
+        .iter()
+        .map(|x| {
+            x.channel(0)[len / 2..]
+                .iter()
+                .fold(0.0_f32, |acc, x| acc.max(x.abs()))
+        })
+        .collect();
+    assert!(peak[0] < 1e-3 && peak[1] < 1e-2);
+    assert!(peak[3] > 0.5);
+}
@@ -0,0 +1,494 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::filters::{lag1::Lag1, Filter};
+use crate::frame::{ChannelLayout, ChannelMap, FrameIterator, BLOCK_SIZE};
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+use std::collections::VecDeque;
+
+/// Lower limit of the tail threshold in dB which keeps the drain finite.
+const MIN_TAIL_THRESHOLD_DB: f64 = -200.0;
+/// Headroom in dB for level build up in the feedback network when the
+/// drain length is estimated.
+const TAIL_MARGIN_DB: f64 = 20.0;
+
+/// Feedback delay network line lengths in ms. The values are chosen so that
+/// the echo patterns of the lines do not coincide.
+const LINE_MS: [f64; 8] = [29.7, 37.1, 41.1, 43.7, 53.0, 59.3, 67.1, 73.3];
+/// Early reflection tap times in ms and gains for the left output.
+const EARLY_LEFT: [(f64, f64); 6] = [
+    (4.3, 0.84),
+    (10.9, 0.62),
+    (17.3, -0.51),
+    (23.9, 0.39),
+    (31.1, -0.28),
+    (41.3, 0.17),
+];
+/// Early reflection tap times in ms and gains for the right output.
+const EARLY_RIGHT: [(f64, f64); 6] = [
+    (5.7, 0.81),
+    (12.1, -0.59),
+    (19.7, 0.48),
+    (26.3, -0.36),
+    (34.7, 0.25),
+    (44.9, 0.14),
+];
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Time in seconds in which the reverb tail decays by 60 dB.
+    #[arg(short, long, default_value_t = 2.0)]
+    decay_time: f64,
+    /// Cutoff frequency of the damping filter in the feedback loop in Hz.
+    #[arg(long, default_value_t = 6000.0)]
+    damping: f64,
+    /// Pre-delay in ms.
+    #[arg(short, long, default_value_t = 20.0)]
+    pre_delay: f64,
+    /// Early reflections level
+    #[arg(short, long, default_value_t = 0.5)]
+    early_level: f64,
+    /// Stereo width, 0 is mono and 1 is full width.
+    #[arg(short, long, default_value_t = 1.0)]
+    width: f64,
+    /// Dry/wet mix, 0 is dry only and 1 is wet only.
+    #[arg(short, long, default_value_t = 0.3)]
+    mix: f64,
+    /// The tail is drained until it decays below this level in dB.
+    #[arg(long, default_value_t = -90.0)]
+    tail_threshold_db: f64,
+}
+
+impl Settings {
+    /// Reverberates mono or stereo input. Only the left and right channels
+    /// of multi-channel layouts are reverberated, the remaining channels
+    /// pass unchanged.
+    pub fn reverb<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+        layout: &ChannelLayout,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+
+        let mut reverb = Reverb::new(spec.sample_rate as f64, layout, self)?;
+
+        let mut progress =
+            Progress::new(duration as usize, "Reverberating sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    reverb.process(block)?;
+                    block.write(output)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // Drain the reverb tail until it has decayed.
+        let threshold_db = self.tail_threshold_db.max(MIN_TAIL_THRESHOLD_DB);
+        let threshold = 10.0_f64.powf(threshold_db / 20.0);
+        let mut blocks = reverb.max_tail_len(reverb.tail_level(), threshold)
+            / BLOCK_SIZE
+            + 1;
+        let mut padding = AudioBuffer::new(spec.channels as usize, BLOCK_SIZE);
+        while reverb.tail_level() > threshold && blocks > 0 {
+            padding.clear();
+            padding.resize(BLOCK_SIZE);
+            reverb.process(&mut padding)?;
+            padding.write(output)?;
+            blocks -= 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Delay line of the feedback delay network.
+#[derive(Clone, Debug)]
+struct Line {
+    /// Ring buffer which holds one delay period.
+    buffer: Vec<f64>,
+    /// Current read and write position
+    pos: usize,
+    /// Loop gain for the requested decay time.
+    gain: f64,
+    /// Damping filter
+    damping: Lag1,
+}
+
+/// Feedback delay network reverb with early reflections
+#[derive(Debug)]
+pub struct Reverb {
+    /// Number of channels
+    channels: usize,
+    /// Wet output of each channel, 0 is left, 1 is right and None passes
+    /// the channel unchanged. Mono input receives the mid signal.
+    sends: Vec<Option<usize>>,
+    /// Pre-delay line of the mono input signal.
+    pre_delay: VecDeque<f64>,
+    /// History of the pre-delayed signal for the early reflections.
+    early: Vec<f64>,
+    /// Write position in the early reflections history
+    early_pos: usize,
+    /// Early reflection taps in samples and gains, left and right.
+    early_taps: [Vec<(usize, f64)>; 2],
+    /// Early reflections level
+    early_level: f64,
+    /// Feedback delay network lines
+    lines: Vec<Line>,
+    /// Feedback matrix working buffer.
+    feedback: [f64; 8],
+    /// Stereo width
+    width: f64,
+    /// Dry/wet mix
+    mix: f64,
+    /// Time in frames in which the tail decays by 60 dB.
+    decay_len: f64,
+}
+
+impl Reverb {
+    pub fn new(
+        fs: f64,
+        layout: &ChannelLayout,
+        settings: &Settings,
+    ) -> Result<Self, Error> {
+        let channels = layout.len();
+        let sends: Vec<Option<usize>> = match channels {
+            1 => vec![Some(0)],
+            2 => vec![Some(0), Some(1)],
+            _ => layout
+                .positions()
+                .iter()
+                .map(|x| match x {
+                    ChannelMap::Left => Some(0),
+                    ChannelMap::Right => Some(1),
+                    _ => None,
+                })
+                .collect(),
+        };
+        if !sends.contains(&Some(0)) {
+            return Err(Error::InvalidArgument(
+                "Reverb requires mono input or left and right channels.".into(),
+            ));
+        }
+        if settings.decay_time <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "Decay time must be greater than zero.".into(),
+            ));
+        }
+        if settings.damping <= 0.0 || settings.damping >= fs / 2.0 {
+            return Err(Error::InvalidArgument(
+                "Damping frequency must be below the Nyquist frequency.".into(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&settings.mix)
+            || !(0.0..=1.0).contains(&settings.width)
+        {
+            return Err(Error::InvalidArgument(
+                "Mix and width must be between zero and one.".into(),
+            ));
+        }
+
+        let samples = |ms: f64| (ms * fs / 1000.0).round() as usize;
+        let pre_delay_len = samples(settings.pre_delay.max(0.0));
+        let mut pre_delay = VecDeque::with_capacity(pre_delay_len + 1);
+        pre_delay.resize(pre_delay_len, 0.0);
+
+        let early_taps = [EARLY_LEFT, EARLY_RIGHT]
+            .map(|taps| taps.iter().map(|(t, g)| (samples(*t), *g)).collect());
+        let early_len = EARLY_LEFT
+            .iter()
+            .chain(EARLY_RIGHT.iter())
+            .map(|(t, _)| samples(*t) + 1)
+            .max()
+            .unwrap_or(1);
+
+        // Lag1 with equal time constants is a first order lowpass.
+        // Its time constant is matched to the discrete pole location.
+        let pole = (-2.0 * std::f64::consts::PI * settings.damping / fs).exp();
+        let tau = 1.0 / ((1.0 - pole) * fs);
+        let lines = LINE_MS
+            .iter()
+            .map(|ms| {
+                let len = samples(*ms).max(1);
+                Line {
+                    buffer: vec![0.0; len],
+                    pos: 0,
+                    gain: 10.0_f64
+                        .powf(-3.0 * len as f64 / (fs * settings.decay_time)),
+                    damping: Lag1::new(1.0, tau, tau, fs),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            channels,
+            sends,
+            pre_delay,
+            early: vec![0.0; early_len],
+            early_pos: 0,
+            early_taps,
+            early_level: settings.early_level,
+            lines,
+            feedback: [0.0; 8],
+            width: settings.width,
+            mix: settings.mix,
+            decay_len: settings.decay_time * fs,
+        })
+    }
+
+    /// Adds reverberation to a block of frames in place.
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        if block.channel_count() != self.channels {
+            return Err(Error::InvalidFrame);
+        }
+
+        let inputs = self.sends.iter().filter(|x| x.is_some()).count();
+        for i in 0..block.len() {
+            let input = block
+                .frame(i)
+                .zip(&self.sends)
+                .filter(|(_, send)| send.is_some())
+                .map(|(x, _)| x as f64)
+                .sum::<f64>()
+                / inputs as f64;
+            self.pre_delay.push_back(input);
+            let input = self.pre_delay.pop_front().unwrap_or(0.0);
+
+            // Early reflections
+            let len = self.early.len();
+            self.early[self.early_pos] = input;
+            let mut reflections = [0.0; 2];
+            for (reflection, taps) in
+                reflections.iter_mut().zip(&self.early_taps)
+            {
+                *reflection = taps
+                    .iter()
+                    .map(|(delay, gain)| {
+                        gain * self.early[(self.early_pos + len - delay) % len]
+                    })
+                    .sum();
+            }
+            self.early_pos = (self.early_pos + 1) % len;
+
+            // Late reverberation
+            let mut late = [0.0; 2];
+            for (idx, (line, feedback)) in self
+                .lines
+                .iter_mut()
+                .zip(self.feedback.iter_mut())
+                .enumerate()
+            {
+                let x = line.buffer[line.pos];
+                late[idx % 2] += if idx % 4 < 2 { x } else { -x };
+                *feedback = line.damping.process(x) * line.gain;
+            }
+            hadamard(&mut self.feedback);
+            for (idx, (line, feedback)) in
+                self.lines.iter_mut().zip(&self.feedback).enumerate()
+            {
+                let sign = if idx % 3 == 0 { -1.0 } else { 1.0 };
+                line.buffer[line.pos] = sign * input + feedback;
+                line.pos = (line.pos + 1) % line.buffer.len();
+            }
+
+            let left = self.early_level * reflections[0] + 0.5 * late[0];
+            let right = self.early_level * reflections[1] + 0.5 * late[1];
+            let mid = 0.5 * (left + right);
+            let side = 0.5 * (left - right) * self.width;
+
+            for (channel, send) in block.channels_mut().zip(&self.sends) {
+                let wet = match send {
+                    _ if self.channels == 1 => mid,
+                    Some(0) => mid + side,
+                    Some(_) => mid - side,
+                    None => continue,
+                };
+                let x = &mut channel[i];
+                *x = ((1.0 - self.mix) * *x as f64 + self.mix * wet) as f32;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Peak level of the wet signal which is still stored in the
+    /// delay lines.
+    pub fn tail_level(&self) -> f64 {
+        self.lines
+            .iter()
+            .flat_map(|x| x.buffer.iter())
+            .chain(self.pre_delay.iter())
+            .chain(self.early.iter())
+            .fold(0.0_f64, |acc, x| acc.max(x.abs()))
+            * self.mix
+    }
+
+    /// Upper bound for the number of frames until a tail at "level"
+    /// decays below "threshold".
+    pub fn max_tail_len(&self, level: f64, threshold: f64) -> usize {
+        if level <= threshold {
+            return 0;
+        }
+        let delays = self.pre_delay.len()
+            + self.early.len()
+            + self.lines.iter().map(|x| x.buffer.len()).max().unwrap_or(0);
+        let decay_db = 20.0 * (level / threshold).log10() + TAIL_MARGIN_DB;
+        delays + (decay_db / 60.0 * self.decay_len).ceil() as usize
+    }
+}
+
+/// Multiplies the vector with a normalized 8x8 Hadamard matrix in place.
+fn hadamard(x: &mut [f64; 8]) {
+    let mut step = 1;
+    while step < x.len() {
+        for i in (0..x.len()).step_by(2 * step) {
+            for j in i..i + step {
+                let (a, b) = (x[j], x[j + step]);
+                x[j] = a + b;
+                x[j + step] = a - b;
+            }
+        }
+        step *= 2;
+    }
+    for x in x.iter_mut() {
+        *x /= 8.0_f64.sqrt();
+    }
+}
+
+#[test]
+fn test_reverb_impulse_response() {
+    let settings = Settings {
+        decay_time: 0.5,
+        damping: 3000.0,
+        pre_delay: 10.0,
+        early_level: 0.5,
+        width: 1.0,
+        mix: 1.0,
+        tail_threshold_db: -90.0,
+    };
+
+    let mut block = AudioBuffer::new(2, 8000);
+    block.resize(8000);
+    block.channel_mut(0)[0] = 1.0;
+    block.channel_mut(1)[0] = 1.0;
+    let mut reverb =
+        Reverb::new(8000.0, &ChannelLayout::from_channels(2), &settings)
+            .unwrap();
+    reverb.process(&mut block).unwrap();
+
+    // Every 7th sample of the first 2800 samples of the stereo impulse response.
+    let golden: Vec<(f32, f32)> =
+        include_str!("../../testdata/reverb_impulse.txt")
+            .lines()
+            .map(|line| {
+                let (left, right) = line.split_once(' ').unwrap();
+                (left.parse().unwrap(), right.parse().unwrap())
+            })
+            .collect();
+    assert_eq!(golden.len(), 400);
+    for (i, (left, right)) in golden.iter().enumerate() {
+        assert!((block.channel(0)[7 * i] - left).abs() < 1e-6);
+        assert!((block.channel(1)[7 * i] - right).abs() < 1e-6);
+    }
+
+    // The tail decays by about 60 dB within the decay time.
+    let rms = |start: usize| {
+        (block.channel(0)[start..start + 400]
+            .iter()
+            .map(|x| (*x as f64).powi(2))
+            .sum::<f64>()
+            / 400.0)
+            .sqrt()
+    };
+    let decay_db = 20.0 * (rms(800) / rms(800 + 4000)).log10();
+    assert!(decay_db > 55.0);
+
+    // Only the front channels of 5.1 are reverberated.
+    let layout = ChannelLayout::from_channels(6);
+    let mut block = AudioBuffer::new(6, 2000);
+    block.resize(2000);
+    block.channel_mut(0)[0] = 1.0;
+    block.channel_mut(2)[0] = 1.0;
+    let mut reverb = Reverb::new(8000.0, &layout, &settings).unwrap();
+    reverb.process(&mut block).unwrap();
+    for idx in 0..2 {
+        assert!(block.channel(idx)[1..].iter().any(|x| *x != 0.0));
+    }
+    assert_eq!(block.channel(2)[0], 1.0);
+    for idx in 2..6 {
+        assert!(block.channel(idx)[1..].iter().all(|x| *x == 0.0));
+    }
+    let layout = ChannelLayout::from_mask(0x4, 3);
+    assert!(Reverb::new(8000.0, &layout, &settings).is_err());
+}
+
+#[test]
+fn test_reverb_tail() {
+    use hound::{SampleFormat, WavSpec};
+
+    let settings = Settings {
+        decay_time: 0.1,
+        damping: 3000.0,
+        pre_delay: 10.0,
+        early_level: 0.5,
+        width: 1.0,
+        mix: 1.0,
+        tail_threshold_db: -10000.0,
+    };
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate: 8000,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut input = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut input, spec).unwrap();
+    writer.write_sample(1.0_f32).unwrap();
+    writer.write_sample(1.0_f32).unwrap();
+    writer.finalize().unwrap();
+    input.set_position(0);
+
+    // A threshold below the smallest float terminates nevertheless.
+    let mut output = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut output, spec).unwrap();
+    settings
+        .reverb(
+            &mut WavReader::new(&mut input).unwrap(),
+            &mut writer,
+            &ChannelLayout::from_channels(2),
+        )
+        .unwrap();
+    let reverb =
+        Reverb::new(8000.0, &ChannelLayout::from_channels(2), &settings)
+            .unwrap();
+    let threshold = 10.0_f64.powf(MIN_TAIL_THRESHOLD_DB / 20.0);
+    let max_len = reverb.max_tail_len(1.0, threshold) + 2 * BLOCK_SIZE;
+    assert!(writer.len() as usize <= 2 * max_len);
+}
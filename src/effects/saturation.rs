@@ -0,0 +1,336 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::filters::biquad::Biquad;
+use crate::filters::fir::{
+    kaiser_beta, kaiser_sinc, kaiser_transition_width, Fir,
+};
+use crate::filters::Filter;
+use crate::frame::FrameIterator;
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+
+/// Stopband attenuation of the resampling filters in dB
+const ATTENUATION_DB: f64 = 90.0;
+/// Cutoff frequency of the tube curve DC blocking filter in Hz
+const DC_BLOCK_FREQUENCY: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Curve {
+    /// Hyperbolic tangent
+    Tanh,
+    /// Cubic soft clipper
+    SoftClip,
+    /// Hard clipper at full scale
+    HardClip,
+    /// Asymmetric tube-style curve which adds even harmonics
+    Tube,
+    /// Bit depth and sample rate reduction
+    Bitcrush,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Transfer curve
+    #[arg(value_enum)]
+    curve: Curve,
+    /// Input gain into the transfer curve in dB.
+    #[arg(short, long, default_value_t = 6.0)]
+    drive_db: f64,
+    /// Output gain in dB.
+    #[arg(short, long, default_value_t = 0.0)]
+    output_gain_db: f64,
+    /// Oversampling factor, one of 1, 2, 4 or 8.
+    #[arg(short = 'f', long, default_value_t = 4)]
+    oversampling: usize,
+    /// Asymmetry of the tube curve.
+    #[arg(long, default_value_t = 0.3)]
+    bias: f64,
+    /// Bit depth of the bitcrush curve.
+    #[arg(short, long, default_value_t = 8)]
+    bits: u32,
+    /// Sample rate reduction factor of the bitcrush curve.
+    #[arg(short, long, default_value_t = 1)]
+    reduction: usize,
+}
+
+impl Settings {
+    pub fn saturate<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+
+        let mut saturation = Saturation::new(
+            spec.sample_rate as f64,
+            spec.channels as usize,
+            self,
+        )?;
+
+        let latency = saturation.latency();
+        let mut progress =
+            Progress::new(duration as usize, "Saturating sample");
+        FrameIterator::new(input.samples_f32(), spec.channels)
+            .process_delayed(output, latency, &mut progress, |block, _| {
+                saturation.process(block)
+            })?;
+
+        Ok(())
+    }
+}
+
+/// Oversampled waveshaping distortion
+#[derive(Debug)]
+pub struct Saturation {
+    /// Transfer curve
+    curve: Curve,
+    /// Oversampling factor
+    factor: usize,
+    /// Upsampling filters, one for each channel.
+    upsampler: Vec<Fir>,
+    /// Decimation filters, one for each channel.
+    decimator: Vec<Fir>,
+    /// DC blocking filters for asymmetric curves, one for each channel.
+    dc_block: Vec<Biquad>,
+    /// Oversampled working buffer
+    buffer: Vec<f64>,
+    /// Decimated working buffer
+    decimated: Vec<f64>,
+    /// Sample and hold state of each channel, value and remaining samples.
+    hold: Vec<(f64, usize)>,
+    /// Latency in frames
+    latency: usize,
+    /// Linear drive gain
+    drive: f64,
+    /// Linear output gain
+    output_gain: f64,
+    /// Tube curve asymmetry
+    bias: f64,
+    /// Bitcrush quantization steps per unit
+    steps: f64,
+    /// Bitcrush hold length in oversampled samples
+    hold_len: usize,
+}
+
+impl Saturation {
+    pub fn new(
+        fs: f64,
+        channels: usize,
+        settings: &Settings,
+    ) -> Result<Self, Error> {
+        let factor = settings.oversampling;
+        if ![1, 2, 4, 8].contains(&factor) {
+            return Err(Error::InvalidArgument(
+                "Oversampling factor must be 1, 2, 4 or 8.".into(),
+            ));
+        }
+        if settings.bits == 0 || settings.bits > 24 || settings.reduction == 0 {
+            return Err(Error::InvalidArgument(
+                "Bit depth must be between 1 and 24 and reduction \
+                 must be greater than zero."
+                    .into(),
+            ));
+        }
+
+        // Upsampling uses zero stuffing and an interpolation filter like
+        // "Fir::lanczos". The Lanczos kernel does not attenuate the spectral
+        // images enough, they would intermodulate in the transfer curve.
+        // Therefore, a Kaiser windowed lowpass with the passband ending at
+        // 80 % of the base rate Nyquist frequency is used for interpolation
+        // and decimation. Its length is chosen so that the delay is a
+        // multiple of the oversampling factor.
+        let (filter, delay) = if factor == 1 {
+            (vec![1.0], 1)
+        } else {
+            let transition = 0.1 / factor as f64;
+            let min_len = (ATTENUATION_DB - 7.95) / (14.36 * transition);
+            let delay = (min_len / (2.0 * factor as f64)).ceil() as usize;
+            let len = 2 * delay * factor - 1;
+            let transition = kaiser_transition_width(ATTENUATION_DB, len);
+            let cutoff = 0.5 / factor as f64 - transition / 2.0;
+            (kaiser_sinc(cutoff, len, kaiser_beta(ATTENUATION_DB)), delay)
+        };
+        // Zero stuffing reduces the signal level by the oversampling factor.
+        let interpolation = filter.iter().map(|x| x * factor as f64).collect();
+
+        Ok(Self {
+            curve: settings.curve,
+            factor,
+            upsampler: vec![Fir::new(interpolation); channels],
+            decimator: vec![Fir::new(filter); channels],
+            dc_block: vec![
+                Biquad::highpass(
+                    fs,
+                    DC_BLOCK_FREQUENCY,
+                    std::f64::consts::FRAC_1_SQRT_2
+                );
+                channels
+            ],
+            buffer: Vec::new(),
+            decimated: Vec::new(),
+            hold: vec![(0.0, 0); channels],
+            latency: 2 * delay,
+            drive: 10.0_f64.powf(settings.drive_db / 20.0),
+            output_gain: 10.0_f64.powf(settings.output_gain_db / 20.0),
+            bias: settings.bias,
+            steps: 2.0_f64.powi(settings.bits as i32 - 1),
+            hold_len: settings.reduction * factor,
+        })
+    }
+
+    pub fn latency(&self) -> usize {
+        self.latency
+    }
+
+    /// Saturates a block of frames in place. The output is delayed
+    /// by "latency()" frames.
+    pub fn process(&mut self, block: &mut AudioBuffer) -> Result<(), Error> {
+        if block.channel_count() != self.upsampler.len() {
+            return Err(Error::InvalidFrame);
+        }
+
+        for (idx, channel) in block.channels_mut().enumerate() {
+            // Upsample with zero stuffing and interpolation filter.
+            self.buffer.clear();
+            for sample in channel.iter() {
+                self.buffer.push(*sample as f64);
+                self.buffer.extend((1..self.factor).map(|_| 0.0));
+            }
+            self.upsampler[idx].process_block(&mut self.buffer);
+
+            let hold = &mut self.hold[idx];
+            for x in self.buffer.iter_mut() {
+                let u = *x * self.drive;
+                *x = match self.curve {
+                    Curve::Tanh => u.tanh(),
+                    Curve::SoftClip => {
+                        if u.abs() >= 1.0 {
+                            u.signum()
+                        } else {
+                            1.5 * u - 0.5 * u * u * u
+                        }
+                    }
+                    Curve::HardClip => u.clamp(-1.0, 1.0),
+                    Curve::Tube => {
+                        // Positive half waves saturate earlier than
+                        // negative ones.
+                        (u + self.bias).tanh() - self.bias.tanh()
+                    }
+                    Curve::Bitcrush => {
+                        if hold.1 == 0 {
+                            hold.0 = (u.clamp(-1.0, 1.0) * self.steps).round()
+                                / self.steps;
+                            hold.1 = self.hold_len;
+                        }
+                        hold.1 -= 1;
+                        hold.0
+                    }
+                };
+            }
+
+            self.decimated.clear();
+            self.decimator[idx].process_decimate(
+                &self.buffer,
+                self.factor,
+                &mut self.decimated,
+            );
+            if self.curve == Curve::Tube {
+                self.dc_block[idx].process_block(&mut self.decimated);
+            }
+
+            for (y, x) in channel.iter_mut().zip(&self.decimated) {
+                *y = (*x * self.output_gain) as f32;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_saturation() {
+    let fs = 48000;
+    let settings = Settings {
+        curve: Curve::HardClip,
+        drive_db: 0.0,
+        output_gain_db: 0.0,
+        oversampling: 8,
+        bias: 0.3,
+        bits: 8,
+        reduction: 1,
+    };
+    let sine = |f: f64, amplitude: f64| {
+        let mut buffer = AudioBuffer::new(1, fs);
+        for i in 0..fs {
+            let phi = 2.0 * std::f64::consts::PI * f * i as f64 / fs as f64;
+            buffer.push_frame([(amplitude * phi.sin()) as f32]).unwrap();
+        }
+        buffer
+    };
+    let amplitude = |block: &AudioBuffer, f: f64| {
+        let (mut re, mut im) = (0.0, 0.0);
+        let samples = &block.channel(0)[fs / 2..];
+        for (n, x) in samples.iter().enumerate() {
+            let phi = 2.0 * std::f64::consts::PI * f / fs as f64 * n as f64;
+            re += *x as f64 * phi.cos();
+            im -= *x as f64 * phi.sin();
+        }
+        2.0 * (re * re + im * im).sqrt() / samples.len() as f64
+    };
+
+    // Below the clipping level, the output is the delayed input.
+    let input = sine(1000.0, 0.5);
+    let mut output = input.clone();
+    let mut saturation = Saturation::new(fs as f64, 1, &settings).unwrap();
+    saturation.process(&mut output).unwrap();
+    let latency = saturation.latency();
+    for (x, y) in input.channel(0).iter().zip(&output.channel(0)[latency..]) {
+        assert!((x - y).abs() < 5e-3);
+    }
+
+    // The third harmonic of a 15 kHz tone aliases to 3 kHz
+    // without oversampling.
+    let mut alias = Vec::new();
+    for oversampling in [1, 8] {
+        let mut output = sine(15000.0, 1.0);
+        let mut saturation = Saturation::new(
+            fs as f64,
+            1,
+            &Settings {
+                curve: Curve::Tanh,
+                drive_db: 12.0,
+                oversampling,
+                ..settings.clone()
+            },
+        )
+        .unwrap();
+        saturation.process(&mut output).unwrap();
+        assert!(amplitude(&output, 15000.0) > 0.9);
+        alias.push(amplitude(&output, 3000.0));
+    }
+    assert!(alias[0] > 0.1);
+    assert!(alias[1] < 1e-4);
+}
@@ -0,0 +1,279 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::frame::FrameIterator;
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+use std::f64::consts::FRAC_PI_4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PanLaw {
+    /// -3 dB at the center, constant power
+    #[value(name = "3db")]
+    ConstantPower,
+    /// -4.5 dB at the center, compromise between power and amplitude
+    #[value(name = "4.5db")]
+    Compromise,
+    /// -6 dB at the center, constant amplitude
+    #[value(name = "6db")]
+    Linear,
+}
+
+impl PanLaw {
+    /// Left and right gain at "position" from -1 (left) to 1 (right).
+    pub fn gains(self, position: f64) -> [f64; 2] {
+        let angle = (position + 1.0) * FRAC_PI_4;
+        let power = [angle.cos(), angle.sin()];
+        let amplitude = [(1.0 - position) / 2.0, (1.0 + position) / 2.0];
+        match self {
+            Self::ConstantPower => power,
+            Self::Compromise => {
+                [0, 1].map(|i| (power[i] * amplitude[i]).max(0.0).sqrt())
+            }
+            Self::Linear => amplitude,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Side {
+    Left,
+    Right,
+    Both,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum Operation {
+    /// Convert left and right to mid and side channels
+    MsEncode,
+    /// Convert mid and side to left and right channels
+    MsDecode,
+    /// Scale the side signal. 0 is mono, 1 is unchanged and values
+    /// above 1 widen the stereo image.
+    Width { width: f64 },
+    /// Attenuate one channel. -1 is left only, 0 is unchanged and 1 is
+    /// right only.
+    Balance {
+        #[arg(allow_hyphen_values = true)]
+        balance: f64,
+    },
+    /// Position the mono sum of both channels from -1 (left) to 1 (right).
+    Pan {
+        #[arg(allow_hyphen_values = true)]
+        position: f64,
+        /// Attenuation at the center
+        #[arg(short, long, value_enum, default_value_t = PanLaw::ConstantPower)]
+        law: PanLaw,
+    },
+    /// Swap left and right channels
+    Swap,
+    /// Invert the polarity
+    Invert {
+        /// Channels to invert
+        #[arg(short, long, value_enum, default_value_t = Side::Both)]
+        channel: Side,
+    },
+}
+
+impl Operation {
+    /// Matrix which maps the left and right input samples to the left and
+    /// right output samples.
+    pub fn matrix(&self) -> Result<[[f64; 2]; 2], Error> {
+        let range = |x: f64, name: &str| {
+            if (-1.0..=1.0).contains(&x) {
+                Ok(x)
+            } else {
+                Err(Error::InvalidArgument(format!(
+                    "{} must be between -1 and 1.",
+                    name
+                )))
+            }
+        };
+
+        Ok(match self {
+            Self::MsEncode => [[0.5, 0.5], [0.5, -0.5]],
+            Self::MsDecode => [[1.0, 1.0], [1.0, -1.0]],
+            Self::Width { width } => {
+                if *width < 0.0 {
+                    return Err(Error::InvalidArgument(
+                        "Width must not be negative.".into(),
+                    ));
+                }
+                // Decoded mid and scaled side
+                let (mid, side) = (0.5, 0.5 * width);
+                [[mid + side, mid - side], [mid - side, mid + side]]
+            }
+            Self::Balance { balance } => {
+                let balance = range(*balance, "Balance")?;
+                [
+                    [(1.0 - balance).min(1.0), 0.0],
+                    [0.0, (1.0 + balance).min(1.0)],
+                ]
+            }
+            Self::Pan { position, law } => {
+                let [left, right] = law.gains(range(*position, "Position")?);
+                // Pan the mid signal so that dual mono keeps its level.
+                [[0.5 * left, 0.5 * left], [0.5 * right, 0.5 * right]]
+            }
+            Self::Swap => [[0.0, 1.0], [1.0, 0.0]],
+            Self::Invert { channel } => {
+                let left = if *channel == Side::Right { 1.0 } else { -1.0 };
+                let right = if *channel == Side::Left { 1.0 } else { -1.0 };
+                [[left, 0.0], [0.0, right]]
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    #[command(subcommand)]
+    operation: Operation,
+}
+
+/// Fails unless the input has exactly two channels.
+pub fn check_channels(channels: u16) -> Result<(), Error> {
+    if channels == 2 {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument(format!(
+            "Stereo tools require a stereo input but the input has {} \
+             channels.",
+            channels
+        )))
+    }
+}
+
+impl Settings {
+    pub fn process<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+        check_channels(spec.channels)?;
+        let matrix = self.operation.matrix()?;
+
+        let mut progress =
+            Progress::new(duration as usize, "Processing sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    for i in 0..block.len() {
+                        let left = block.channel(0)[i] as f64;
+                        let right = block.channel(1)[i] as f64;
+                        for (channel, row) in block.channels_mut().zip(matrix) {
+                            channel[i] =
+                                (row[0] * left + row[1] * right) as f32;
+                        }
+                    }
+                    block.write(output)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_stereo() {
+    use hound::{SampleFormat, WavSpec};
+
+    let apply = |operation: Operation, x: [f64; 2]| {
+        let m = operation.matrix().unwrap();
+        [0, 1].map(|i| m[i][0] * x[0] + m[i][1] * x[1])
+    };
+    let encoded = apply(Operation::MsEncode, [0.75, 0.25]);
+    assert_eq!(encoded, [0.5, 0.25]);
+    assert_eq!(apply(Operation::MsDecode, encoded), [0.75, 0.25]);
+    assert_eq!(apply(Operation::Width { width: 0.0 }, [1.0, 0.0]), [0.5; 2]);
+    assert_eq!(
+        apply(Operation::Width { width: 1.0 }, [1.0, 0.0]),
+        [1.0, 0.0]
+    );
+    assert_eq!(
+        apply(Operation::Balance { balance: -0.5 }, [1.0, 1.0]),
+        [1.0, 0.5]
+    );
+    assert_eq!(apply(Operation::Swap, [1.0, 0.0]), [0.0, 1.0]);
+    assert_eq!(
+        apply(
+            Operation::Invert {
+                channel: Side::Left
+            },
+            [1.0, 1.0]
+        ),
+        [-1.0, 1.0]
+    );
+    assert!(Operation::Width { width: -1.0 }.matrix().is_err());
+    assert!(Operation::Balance { balance: 1.5 }.matrix().is_err());
+
+    // Center attenuation and hard left of the pan laws
+    for (law, center_db) in [
+        (PanLaw::ConstantPower, -3.01),
+        (PanLaw::Compromise, -4.52),
+        (PanLaw::Linear, -6.02),
+    ] {
+        let [left, right] = law.gains(0.0);
+        assert!((left - right).abs() < 1e-12);
+        assert!((20.0 * left.log10() - center_db).abs() < 0.01);
+        let [left, right] = law.gains(-1.0);
+        assert!((left - 1.0).abs() < 1e-12 && right.abs() < 1e-12);
+    }
+
+    // Dual mono keeps its level when panned hard left.
+    let [left, right] = apply(
+        Operation::Pan {
+            position: -1.0,
+            law: PanLaw::ConstantPower,
+        },
+        [0.5, 0.5],
+    );
+    assert!((left - 0.5).abs() < 1e-12 && right.abs() < 1e-12);
+
+    // Mono input is rejected.
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: 48000,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut input = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut input, spec).unwrap();
+    writer.write_sample(0.5_f32).unwrap();
+    writer.finalize().unwrap();
+    input.set_position(0);
+    let settings = Settings {
+        operation: Operation::Swap,
+    };
+    let mut output = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut output, spec).unwrap();
+    assert!(settings
+        .process(&mut WavReader::new(&mut input).unwrap(), &mut writer)
+        .is_err());
+}
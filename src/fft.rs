@@ -0,0 +1,261 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::error::Error;
+use std::f64::consts::PI;
+
+/// Complex number
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    /// Unit vector with angle "phi".
+    pub fn from_angle(phi: f64) -> Self {
+        Self::new(phi.cos(), phi.sin())
+    }
+
+    /// Squared magnitude
+    pub fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// Complex conjugate
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    /// Multiplies with the real factor "x".
+    pub fn scale(self, x: f64) -> Self {
+        Self::new(self.re * x, self.im * x)
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+/// Iterative radix-2 fast Fourier transform.
+#[derive(Debug, Clone)]
+pub struct Fft {
+    /// Twiddle factors exp(-2*pi*i*k/len) for the first half circle.
+    twiddles: Vec<Complex>,
+    /// Bit reversed index of every position
+    reversed: Vec<usize>,
+}
+
+impl Fft {
+    /// Prepares a transform of size "len" which must be a power of two.
+    pub fn new(len: usize) -> Result<Self, Error> {
+        if len < 2 || !len.is_power_of_two() {
+            return Err(Error::InvalidArgument(
+                "FFT size must be a power of two.".into(),
+            ));
+        }
+
+        let bits = len.trailing_zeros();
+        Ok(Self {
+            twiddles: (0..len / 2)
+                .map(|k| Complex::from_angle(-2.0 * PI * k as f64 / len as f64))
+                .collect(),
+            reversed: (0..len)
+                .map(|i| i.reverse_bits() >> (usize::BITS - bits))
+                .collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.reversed.len()
+    }
+
+    /// Forward transform in place without normalization.
+    pub fn forward(&self, data: &mut [Complex]) -> Result<(), Error> {
+        let len = self.len();
+        if data.len() != len {
+            return Err(Error::InvalidFrame);
+        }
+
+        for (i, j) in self.reversed.iter().copied().enumerate() {
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+
+        let mut size = 2;
+        while size <= len {
+            let half = size / 2;
+            let stride = len / size;
+            for start in (0..len).step_by(size) {
+                for k in 0..half {
+                    let twiddle = self.twiddles[k * stride];
+                    let a = data[start + k];
+                    let b = data[start + k + half] * twiddle;
+                    data[start + k] = a + b;
+                    data[start + k + half] = a - b;
+                }
+            }
+            size *= 2;
+        }
+
+        Ok(())
+    }
+
+    /// Forward transform of a real signal. Returns the "len/2+1" bins
+    /// from DC to the Nyquist frequency in "output".
+    pub fn forward_real(
+        &self,
+        input: &[f64],
+        output: &mut Vec<Complex>,
+    ) -> Result<(), Error> {
+        if input.len() != self.len() {
+            return Err(Error::InvalidFrame);
+        }
+        output.clear();
+        output.extend(input.iter().map(|x| Complex::new(*x, 0.0)));
+        self.forward(output)?;
+        output.truncate(self.len() / 2 + 1);
+        Ok(())
+    }
+
+    /// Inverse transform of the "len/2+1" bins of a one sided spectrum
+    /// to a real signal, normalized by the transform size.
+    pub fn inverse_real(
+        &self,
+        input: &[Complex],
+        output: &mut Vec<f64>,
+    ) -> Result<(), Error> {
+        let len = self.len();
+        if input.len() != len / 2 + 1 {
+            return Err(Error::InvalidFrame);
+        }
+
+        // The inverse transform is the conjugated forward transform of the
+        // conjugated Hermitian spectrum.
+        let mut data: Vec<Complex> = input.iter().map(|x| x.conj()).collect();
+        data.extend(input[1..len / 2].iter().rev());
+        self.forward(&mut data)?;
+
+        output.clear();
+        output.extend(data.iter().map(|x| x.re / len as f64));
+        Ok(())
+    }
+}
+
+/// Analysis window functions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Window {
+    /// Hann window, good frequency resolution
+    Hann,
+    /// 4-term Blackman-Harris window, 92 dB sidelobe attenuation
+    BlackmanHarris,
+    /// Flat-top window, accurate amplitudes of sinusoids
+    FlatTop,
+}
+
+impl Window {
+    /// Periodic window coefficients of length "len".
+    pub fn coefficients(self, len: usize) -> Vec<f64> {
+        let a: &[f64] = match self {
+            Self::Hann => &[0.5, 0.5],
+            Self::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            Self::FlatTop => &[
+                0.21557895,
+                0.41663158,
+                0.277263158,
+                0.083578947,
+                0.006947368,
+            ],
+        };
+
+        (0..len)
+            .map(|n| {
+                let phi = 2.0 * PI * n as f64 / len as f64;
+                a.iter().enumerate().fold(0.0, |acc, (k, a)| {
+                    let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                    acc + sign * a * (k as f64 * phi).cos()
+                })
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_fft() {
+    let len = 64;
+    let fft = Fft::new(len).unwrap();
+    assert!(Fft::new(48).is_err());
+
+    let signal: Vec<Complex> = (0..len)
+        .map(|n| Complex::new((n as f64 * 0.7).sin(), (n as f64 * 0.3).cos()))
+        .collect();
+    let mut spectrum = signal.clone();
+    fft.forward(&mut spectrum).unwrap();
+
+    // Compare with the direct DFT.
+    for (k, x) in spectrum.iter().enumerate() {
+        let dft = signal.iter().enumerate().fold(
+            Complex::default(),
+            |acc, (n, x)| {
+                acc + *x
+                    * Complex::from_angle(
+                        -2.0 * PI * (k * n) as f64 / len as f64,
+                    )
+            },
+        );
+        assert!((*x - dft).norm_sqr() < 1e-18);
+    }
+
+    let real: Vec<f64> = signal.iter().map(|x| x.re).collect();
+    let mut spectrum = Vec::new();
+    let mut restored = Vec::new();
+    fft.forward_real(&real, &mut spectrum).unwrap();
+    fft.inverse_real(&spectrum, &mut restored).unwrap();
+    for (x, y) in restored.iter().zip(&real) {
+        assert!((x - y).abs() < 1e-12);
+    }
+
+    // Periodic windows sum to len times their first coefficient.
+    let window = Window::Hann.coefficients(len);
+    assert_eq!(window[0], 0.0);
+    assert!((window.iter().sum::<f64>() - 0.5 * len as f64).abs() < 1e-9);
+}
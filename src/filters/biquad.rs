@@ -38,6 +38,77 @@ impl Biquad {
             output: [0.0; 2],
         }
     }
+
+    /// Second order lowpass filter with cutoff frequency "f0" and quality
+    /// factor "q". Coefficients follow the RBJ audio EQ cookbook.
+    pub fn lowpass(fs: f64, f0: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::rbj_params(fs, f0, q);
+        Self::normalized(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Second order highpass filter with cutoff frequency "f0" and quality
+    /// factor "q".
+    pub fn highpass(fs: f64, f0: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::rbj_params(fs, f0, q);
+        Self::normalized(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Second order bandpass filter with center frequency "f0", quality
+    /// factor "q" and 0 dB peak gain.
+    pub fn bandpass(fs: f64, f0: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::rbj_params(fs, f0, q);
+        Self::normalized(
+            [alpha, 0.0, -alpha],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Second order notch filter with center frequency "f0" and quality
+    /// factor "q".
+    pub fn notch(fs: f64, f0: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::rbj_params(fs, f0, q);
+        Self::normalized(
+            [1.0, -2.0 * cos, 1.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Second order allpass filter with center frequency "f0" and quality
+    /// factor "q".
+    pub fn allpass(fs: f64, f0: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::rbj_params(fs, f0, q);
+        Self::normalized(
+            [1.0 - alpha, -2.0 * cos, 1.0 + alpha],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Resets the filter state to zero.
+    pub fn reset(&mut self) {
+        self.input = [0.0; 2];
+        self.output = [0.0; 2];
+    }
+
+    /// Cosine of the normalized center frequency and bandwidth parameter
+    /// alpha.
+    fn rbj_params(fs: f64, f0: f64, q: f64) -> (f64, f64) {
+        let omega = 2.0 * std::f64::consts::PI * f0 / fs;
+        (omega.cos(), omega.sin() / (2.0 * q))
+    }
+
+    /// Creates a filter from coefficients with denominator A0, A1, A2.
+    fn normalized(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self::new(
+            [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            [a[1] / a[0], a[2] / a[0]],
+        )
+    }
 }
 
 impl Filter for Biquad {
@@ -56,4 +127,23 @@ impl Filter for Biquad {
         self.output[0] = output;
         output
     }
+
+    fn process_block(&mut self, block: &mut [f64]) {
+        let [b0, b1, b2] = self.b;
+        let [a1, a2] = self.a;
+        let [mut x1, mut x2] = self.input;
+        let [mut y1, mut y2] = self.output;
+
+        for x in block.iter_mut() {
+            let y = b0 * *x + b1 * x1 + b2 * x2 - a1 * y1 - a2 * y2;
+            x2 = x1;
+            x1 = *x;
+            y2 = y1;
+            y1 = y;
+            *x = y;
+        }
+
+        self.input = [x1, x2];
+        self.output = [y1, y2];
+    }
 }
@@ -0,0 +1,82 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::Filter;
+
+/// 1st order DC blocking highpass filter
+#[derive(Clone, Debug)]
+pub struct DcBlock {
+    /// Pole radius derived from the cutoff frequency
+    pole: f64,
+    /// Previous input value
+    input: f64,
+    /// Previous output value
+    output: f64,
+}
+
+impl DcBlock {
+    /// DC blocker with -3 dB cutoff frequency "f0".
+    pub fn new(fs: f64, f0: f64) -> Self {
+        Self {
+            pole: (-2.0 * std::f64::consts::PI * f0 / fs).exp(),
+            input: 0.0,
+            output: 0.0,
+        }
+    }
+}
+
+impl Filter for DcBlock {
+    fn process(&mut self, input: f64) -> f64 {
+        self.output = input - self.input + self.pole * self.output;
+        self.input = input;
+        self.output
+    }
+
+    fn process_block(&mut self, block: &mut [f64]) {
+        let pole = self.pole;
+        let mut x1 = self.input;
+        let mut y1 = self.output;
+        for x in block.iter_mut() {
+            let y = *x - x1 + pole * y1;
+            x1 = *x;
+            y1 = y;
+            *x = y;
+        }
+        self.input = x1;
+        self.output = y1;
+    }
+}
+
+#[test]
+fn test_dc_block() {
+    let fs = 48000.0;
+    let mut filter = DcBlock::new(fs, 5.0);
+
+    // The offset decays within one second while 1 kHz passes.
+    let mut output: Vec<f64> = (0..48000)
+        .map(|n| {
+            let phi = 2.0 * std::f64::consts::PI * 1000.0 * n as f64 / fs;
+            0.25 + 0.5 * phi.sin()
+        })
+        .collect();
+    filter.process_block(&mut output);
+    let tail = &output[47952..];
+    let mean = tail.iter().sum::<f64>() / tail.len() as f64;
+    let peak = tail.iter().fold(0.0_f64, |acc, x| acc.max(x.abs()));
+    assert!(mean.abs() < 1e-3);
+    assert!((peak - 0.5).abs() < 1e-3);
+}
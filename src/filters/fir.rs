@@ -27,6 +27,15 @@ pub struct Fir {
 }
 
 impl Fir {
+    /// Construct a FIR filter with impulse response "b".
+    pub fn new(b: Vec<f64>) -> Fir {
+        let n = b.len();
+        Self {
+            b,
+            buf: VecDeque::from(vec![0.0; n + 1]),
+        }
+    }
+
     /// Construct and Lanczos upsampling FIR filter with
     /// upsampling factor "p" and Lanczos parameter "a".
     pub fn lanczos(p: usize, a: usize) -> Fir {
@@ -50,6 +59,92 @@ impl Fir {
             buf: VecDeque::from(vec![0.0; n as usize + 1]),
         }
     }
+
+    /// Filters a block and appends every "factor"-th output sample,
+    /// starting with the first one, to "output". Skipped output samples
+    /// are not calculated.
+    pub fn process_decimate(
+        &mut self,
+        block: &[f64],
+        factor: usize,
+        output: &mut Vec<f64>,
+    ) {
+        let n = self.b.len();
+        let offset = self.buf.len();
+        self.buf.extend(block.iter());
+        let buf = self.buf.make_contiguous();
+
+        for i in (0..block.len()).step_by(factor.max(1)) {
+            let start = i + offset - n;
+            output.push(
+                buf[start..start + n]
+                    .iter()
+                    .zip(self.b.iter())
+                    .fold(0.0, |acc, (x, b)| acc + x * b),
+            );
+        }
+
+        self.buf.drain(..block.len());
+    }
+}
+
+/// Zeroth order modified Bessel function of the first kind.
+fn bessel_i0(x: f64) -> f64 {
+    // Power series, converges quickly for the arguments used in
+    // Kaiser windows.
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-17 {
+        term *= (x / (2.0 * k)).powi(2);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// Kaiser window parameter beta for the given stopband attenuation in dB.
+pub fn kaiser_beta(attenuation_db: f64) -> f64 {
+    if attenuation_db > 50.0 {
+        0.1102 * (attenuation_db - 8.7)
+    } else if attenuation_db >= 21.0 {
+        0.5842 * (attenuation_db - 21.0).powf(0.4)
+            + 0.07886 * (attenuation_db - 21.0)
+    } else {
+        0.0
+    }
+}
+
+/// Transition band width relative to the sampling frequency which a Kaiser
+/// windowed filter with "len" taps achieves for the given attenuation.
+pub fn kaiser_transition_width(attenuation_db: f64, len: usize) -> f64 {
+    (attenuation_db - 7.95) / (14.36 * len as f64)
+}
+
+/// Impulse response of a Kaiser windowed sinc lowpass filter with unity DC
+/// gain. The cutoff frequency is relative to the sampling frequency.
+pub fn kaiser_sinc(cutoff: f64, len: usize, beta: f64) -> Vec<f64> {
+    let center = (len as f64 - 1.0) / 2.0;
+    let i0_beta = bessel_i0(beta);
+
+    let b: Vec<f64> = (0..len)
+        .map(|n| {
+            let t = n as f64 - center;
+            let sinc = if t == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * std::f64::consts::PI * cutoff * t).sin()
+                    / (std::f64::consts::PI * t)
+            };
+            let r = if center > 0.0 { t / center } else { 0.0 };
+            let window =
+                bessel_i0(beta * (1.0 - r * r).max(0.0).sqrt()) / i0_beta;
+            sinc * window
+        })
+        .collect();
+
+    let sum: f64 = b.iter().sum();
+    b.into_iter().map(|x| x / sum).collect()
 }
 
 impl Filter for Fir {
@@ -68,4 +163,23 @@ impl Filter for Fir {
             .zip(self.b.iter())
             .fold(0.0, |acc, (x, b)| acc + x * b)
     }
+
+    fn process_block(&mut self, block: &mut [f64]) {
+        let n = self.b.len();
+        let offset = self.buf.len();
+        self.buf.extend(block.iter());
+        let buf = self.buf.make_contiguous();
+
+        // Each output sample uses the oldest "n" samples of the window
+        // which "process" would have after inserting the input sample.
+        for (i, y) in block.iter_mut().enumerate() {
+            let start = i + offset - n;
+            *y = buf[start..start + n]
+                .iter()
+                .zip(self.b.iter())
+                .fold(0.0, |acc, (x, b)| acc + x * b);
+        }
+
+        self.buf.drain(..block.len());
+    }
 }
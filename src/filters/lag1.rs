@@ -74,4 +74,17 @@ impl Filter for Lag1 {
         }
         self.level * self.gain
     }
+
+    fn process_block(&mut self, block: &mut [f64]) {
+        let mut level = self.level;
+        for x in block.iter_mut() {
+            if *x >= level {
+                level += self.rise_factor * (*x - level);
+            } else {
+                level += self.fall_factor * (*x - level);
+            }
+            *x = level * self.gain;
+        }
+        self.level = level;
+    }
 }
@@ -16,6 +16,7 @@
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 \******************************************************************************/
 pub mod biquad;
+pub mod dc_block;
 pub mod fir;
 pub mod lag1;
 pub mod mov_max;
@@ -23,4 +24,101 @@ pub mod mov_rms;
 
 pub trait Filter: std::fmt::Debug {
     fn process(&mut self, input: f64) -> f64;
+
+    /// Filters a block of samples in place.
+    ///
+    /// This is equivalent to calling `process` for every sample but
+    /// requires only one dynamic dispatch per block. Filters should
+    /// override this with a specialized implementation if possible.
+    fn process_block(&mut self, block: &mut [f64]) {
+        for x in block.iter_mut() {
+            *x = self.process(*x);
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_filters() -> Vec<(&'static str, Box<dyn Filter>)> {
+    vec![
+        (
+            "Biquad",
+            Box::new(biquad::Biquad::new(
+                [0.2066, 0.4131, 0.2066],
+                [-0.3695, 0.1958],
+            )),
+        ),
+        ("DcBlock", Box::new(dc_block::DcBlock::new(48000.0, 5.0))),
+        ("Fir", Box::new(fir::Fir::lanczos(4, 3))),
+        ("Lag1", Box::new(lag1::Lag1::new(1.5, 0.01, 0.1, 48000.0))),
+        ("MovMax", Box::new(mov_max::MovMax::new(64))),
+        ("MovRms", Box::new(mov_rms::MovRms::new(2.0, 64))),
+    ]
+}
+
+#[cfg(test)]
+fn test_signal(len: usize) -> Vec<f64> {
+    // Deterministic pseudo random noise from a linear congruential generator.
+    let mut state: u32 = 0x1234_5678;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state as f64) / (u32::MAX as f64) * 2.0 - 1.0
+        })
+        .collect()
+}
+
+#[test]
+fn test_process_block() {
+    let input = test_signal(1000);
+
+    for ((name, mut sample_filter), (_, mut block_filter)) in
+        test_filters().into_iter().zip(test_filters())
+    {
+        let expected: Vec<f64> =
+            input.iter().map(|x| sample_filter.process(*x)).collect();
+        let mut output = input.clone();
+        // Use odd block sizes to test state handling across blocks.
+        for block in output.chunks_mut(77) {
+            block_filter.process_block(block);
+        }
+        assert_eq!(output, expected, "{}", name);
+    }
+}
+
+/// Compares per sample and block based processing speed on a five minute
+/// stereo signal at 48 kHz.
+/// Run with "cargo test --release -- --ignored --nocapture bench".
+#[test]
+#[ignore]
+fn bench_process_block() {
+    const BLOCK_SIZE: usize = 4096;
+    let input = test_signal(5 * 60 * 48000 * 2);
+
+    for ((name, mut sample_filter), (_, mut block_filter)) in
+        test_filters().into_iter().zip(test_filters())
+    {
+        let mut output = input.clone();
+        let start = std::time::Instant::now();
+        for x in output.iter_mut() {
+            *x = sample_filter.process(*x);
+        }
+        let sample_time = start.elapsed();
+        let check = output.iter().sum::<f64>();
+
+        let mut output = input.clone();
+        let start = std::time::Instant::now();
+        for block in output.chunks_mut(BLOCK_SIZE) {
+            block_filter.process_block(block);
+        }
+        let block_time = start.elapsed();
+        assert_eq!(output.iter().sum::<f64>(), check);
+
+        eprintln!(
+            "{}\n    per sample: {:?}, per block: {:?}, speedup: {:.2}",
+            name,
+            sample_time,
+            block_time,
+            sample_time.as_secs_f64() / block_time.as_secs_f64(),
+        );
+    }
 }
@@ -23,37 +23,64 @@ use std::collections::VecDeque;
 pub struct MovMax {
     /// Sliding window length
     window_length: usize,
-    /// Sliding window buffer
-    window: VecDeque<f64>,
-    /// Intermediate maxima values
-    maxes: VecDeque<f64>,
+    /// Insertion counter
+    position: usize,
+    /// Intermediate maxima values with their insertion position
+    maxes: VecDeque<(usize, f64)>,
 }
 
 impl MovMax {
     pub fn new(window_length: usize) -> Self {
         Self {
             window_length,
-            window: VecDeque::with_capacity(window_length),
-            maxes: VecDeque::with_capacity(window_length),
+            position: 0,
+            maxes: VecDeque::with_capacity(window_length + 1),
         }
     }
 }
 
 impl Filter for MovMax {
     fn process(&mut self, input: f64) -> f64 {
-        while self.maxes.back().map_or(false, |x| x < &input) {
+        while self.maxes.back().map_or(false, |x| x.1 < input) {
             self.maxes.pop_back();
         }
-        self.window.push_back(input);
-        self.maxes.push_back(input);
+        self.maxes.push_back((self.position, input));
 
-        if self.window.len() > self.window_length {
-            if self.maxes.front() == self.window.front() {
-                self.maxes.pop_front();
+        // Drop the maximum when it leaves the sliding window.
+        if self.maxes.front().map_or(false, |x| {
+            self.position.wrapping_sub(x.0) >= self.window_length
+        }) {
+            self.maxes.pop_front();
+        }
+        self.position = self.position.wrapping_add(1);
+        self.maxes.front().map_or(0.0, |x| x.1)
+    }
+
+    fn process_block(&mut self, block: &mut [f64]) {
+        let maxes = &mut self.maxes;
+        let mut position = self.position;
+
+        for x in block.iter_mut() {
+            let input = *x;
+            while let Some(&(_, back)) = maxes.back() {
+                if back >= input {
+                    break;
+                }
+                maxes.pop_back();
+            }
+            maxes.push_back((position, input));
+
+            // The deque is never empty after the push above.
+            let (front_position, front) = maxes[0];
+            if position.wrapping_sub(front_position) >= self.window_length {
+                maxes.pop_front();
+                *x = maxes.front().map_or(0.0, |x| x.1);
+            } else {
+                *x = front;
             }
-            self.window.pop_front();
+            position = position.wrapping_add(1);
         }
-        *self.maxes.front().unwrap_or(&0.0)
+        self.position = position;
     }
 }
 
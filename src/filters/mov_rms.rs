@@ -16,7 +16,6 @@
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 \******************************************************************************/
 use super::Filter;
-use std::collections::VecDeque;
 
 /// Moving RMS filter
 #[derive(Clone, Debug)]
@@ -27,8 +26,10 @@ pub struct MovRms {
     acc: f64,
     /// Insertion counter
     counter: usize,
-    /// Window buffer for refreshing
-    buffer: VecDeque<f64>,
+    /// Window ring buffer for refreshing
+    buffer: Vec<f64>,
+    /// Position of the oldest sample in the ring buffer
+    position: usize,
 }
 
 impl MovRms {
@@ -40,7 +41,8 @@ impl MovRms {
             gain,
             acc: 0.0,
             counter: 0,
-            buffer: VecDeque::from(vec![0.0; window_length]),
+            buffer: vec![0.0; window_length.max(1)],
+            position: 0,
         }
     }
 
@@ -53,8 +55,9 @@ impl MovRms {
 impl Filter for MovRms {
     fn process(&mut self, input: f64) -> f64 {
         let sq_input = input * input;
-        let old_input = self.buffer.pop_front().unwrap_or(0.0);
-        self.buffer.push_back(sq_input);
+        let old_input =
+            std::mem::replace(&mut self.buffer[self.position], sq_input);
+        self.position = (self.position + 1) % self.buffer.len();
 
         if self.counter > Self::REFRESH_INTERVAL {
             // Recalculate sum from window buffer to avoid accumulation of
@@ -62,8 +65,7 @@ impl Filter for MovRms {
             self.refresh();
         } else {
             self.counter += 1;
-            self.acc -= old_input;
-            self.acc += sq_input;
+            self.acc += sq_input - old_input;
         }
 
         // Refresh if there are severe rounding errors so that acc is negative.
@@ -73,6 +75,55 @@ impl Filter for MovRms {
 
         self.gain * (self.acc / (self.buffer.len() as f64)).sqrt()
     }
+
+    fn process_block(&mut self, mut block: &mut [f64]) {
+        let len = self.buffer.len();
+
+        while !block.is_empty() {
+            if self.counter > Self::REFRESH_INTERVAL {
+                block[0] = self.process(block[0]);
+                block = &mut block[1..];
+                continue;
+            }
+
+            // Neither the refresh interval nor the end of the ring buffer is
+            // reached within this chunk, so both checks can be skipped
+            // inside the loop.
+            let chunk_len = block
+                .len()
+                .min(Self::REFRESH_INTERVAL + 1 - self.counter)
+                .min(len - self.position);
+            // Store the accumulator first and take the square roots in a
+            // separate loop which can be vectorized.
+            let window = &mut self.buffer[self.position..];
+            let mut acc = self.acc;
+            let mut processed = 0;
+            for (x, old) in block[..chunk_len].iter_mut().zip(window) {
+                let sq_input = *x * *x;
+                acc += sq_input - std::mem::replace(old, sq_input);
+                *x = acc;
+                processed += 1;
+                if acc < 0.0 {
+                    break;
+                }
+            }
+            for x in block[..processed].iter_mut() {
+                *x = self.gain * (*x / len as f64).sqrt();
+            }
+            self.acc = acc;
+            self.counter += processed;
+            self.position = (self.position + processed) % len;
+
+            // Refresh if there are severe rounding errors so that acc is
+            // negative.
+            if self.acc < 0.0 {
+                self.refresh();
+                block[processed - 1] =
+                    self.gain * (self.acc / len as f64).sqrt();
+            }
+            block = &mut std::mem::take(&mut block)[processed..];
+        }
+    }
 }
 
 #[test]
@@ -15,8 +15,14 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 \******************************************************************************/
-use hound::{Error, Sample};
+use crate::buffer::AudioBuffer;
+use crate::progress::Progress;
+use hound::{Error, WavWriter};
 
+/// Speaker position of a channel.
+/// The order of the variants up to "TopRearRight" follows the bits of the
+/// WAVE_FORMAT_EXTENSIBLE channel mask.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ChannelMap {
     Left,
     Right,
@@ -24,41 +30,302 @@ pub enum ChannelMap {
     Lfe,
     RearLeft,
     RearRight,
+    LeftOfCenter,
+    RightOfCenter,
+    RearCenter,
     SideLeft,
     SideRight,
+    TopCenter,
+    TopFrontLeft,
+    TopFrontCenter,
+    TopFrontRight,
+    TopRearLeft,
+    TopRearCenter,
+    TopRearRight,
+    /// Channel without speaker position
+    Unassigned,
 }
 
-pub struct FrameIterator<S, T> {
+impl ChannelMap {
+    /// Speaker positions in channel mask bit order.
+    const MASK_ORDER: [ChannelMap; 18] = [
+        Self::Left,
+        Self::Right,
+        Self::Center,
+        Self::Lfe,
+        Self::RearLeft,
+        Self::RearRight,
+        Self::LeftOfCenter,
+        Self::RightOfCenter,
+        Self::RearCenter,
+        Self::SideLeft,
+        Self::SideRight,
+        Self::TopCenter,
+        Self::TopFrontLeft,
+        Self::TopFrontCenter,
+        Self::TopFrontRight,
+        Self::TopRearLeft,
+        Self::TopRearCenter,
+        Self::TopRearRight,
+    ];
+
+    /// Channel mask bit of this speaker position.
+    pub fn mask_bit(self) -> u32 {
+        match Self::MASK_ORDER.iter().position(|x| *x == self) {
+            Some(idx) => 1 << idx,
+            None => 0,
+        }
+    }
+
+    /// Channel weight for loudness measurement according to ITU-R BS.1770.
+    pub fn loudness_weight(self) -> f64 {
+        match self {
+            Self::Lfe => 0.0,
+            Self::RearLeft
+            | Self::RearRight
+            | Self::SideLeft
+            | Self::SideRight => 1.41,
+            _ => 1.0,
+        }
+    }
+
+    /// Short speaker label.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Left => "L",
+            Self::Right => "R",
+            Self::Center => "C",
+            Self::Lfe => "LFE",
+            Self::RearLeft => "BL",
+            Self::RearRight => "BR",
+            Self::LeftOfCenter => "FLC",
+            Self::RightOfCenter => "FRC",
+            Self::RearCenter => "BC",
+            Self::SideLeft => "SL",
+            Self::SideRight => "SR",
+            Self::TopCenter => "TC",
+            Self::TopFrontLeft => "TFL",
+            Self::TopFrontCenter => "TFC",
+            Self::TopFrontRight => "TFR",
+            Self::TopRearLeft => "TBL",
+            Self::TopRearCenter => "TBC",
+            Self::TopRearRight => "TBR",
+            Self::Unassigned => "?",
+        }
+    }
+}
+
+impl std::str::FromStr for ChannelMap {
+    type Err = String;
+
+    /// Parses a short speaker label, e.g. "L" or "LFE".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::MASK_ORDER
+            .iter()
+            .find(|x| x.label().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| format!("Unknown speaker label \"{}\"", s))
+    }
+}
+
+/// Speaker positions of all channels of a stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelLayout {
+    positions: Vec<ChannelMap>,
+}
+
+impl ChannelLayout {
+    pub fn new(positions: Vec<ChannelMap>) -> Self {
+        Self { positions }
+    }
+
+    /// Constructs a layout from a WAVE_FORMAT_EXTENSIBLE channel mask.
+    /// Channels exceeding the set mask bits are unassigned.
+    pub fn from_mask(mask: u32, channels: usize) -> Self {
+        let positions = ChannelMap::MASK_ORDER
+            .iter()
+            .filter(|x| mask & x.mask_bit() != 0)
+            .copied()
+            .chain(std::iter::repeat(ChannelMap::Unassigned))
+            .take(channels)
+            .collect();
+
+        Self { positions }
+    }
+
+    /// Default layout for files without channel mask.
+    pub fn from_channels(channels: usize) -> Self {
+        let mask = match channels {
+            1 => 0x4,   // Mono
+            2 => 0x3,   // Stereo
+            3 => 0x7,   // 3.0
+            4 => 0x33,  // Quadrophonic
+            5 => 0x37,  // 5.0
+            6 => 0x3F,  // 5.1
+            8 => 0x63F, // 7.1
+            _ => (1 << channels.min(18)) - 1,
+        };
+        Self::from_mask(mask, channels)
+    }
+
+    /// WAVE_FORMAT_EXTENSIBLE channel mask of this layout. A channel mask
+    /// implies channel mask bit order, layouts in any other order cannot
+    /// be represented and have the mask 0.
+    pub fn mask(&self) -> u32 {
+        let mask = self.positions.iter().fold(0, |acc, x| acc | x.mask_bit());
+        if Self::from_mask(mask, self.len()) == *self {
+            mask
+        } else {
+            0
+        }
+    }
+
+    pub fn positions(&self) -> &[ChannelMap] {
+        &self.positions
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+}
+
+impl std::fmt::Display for ChannelLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let labels: Vec<&str> =
+            self.positions.iter().map(|x| x.label()).collect();
+        write!(f, "{}", labels.join(" "))
+    }
+}
+
+/// Default number of frames per block
+pub const BLOCK_SIZE: usize = 4096;
+
+/// Reads interleaved samples into blocks of planar audio frames.
+pub struct FrameIterator<T> {
     samples: T,
-    channels: u16,
-    buffer: Vec<S>,
+    /// Working buffer for one interleaved frame
+    frame: Vec<f32>,
+    /// Block buffer
+    buffer: AudioBuffer,
 }
 
-impl<S, T> FrameIterator<S, T>
+impl<T> FrameIterator<T>
 where
-    S: Sample,
-    T: Iterator<Item = Result<S, Error>>,
+    T: Iterator<Item = Result<f32, Error>>,
 {
     pub fn new(samples: T, channels: u16) -> Self {
+        Self::with_block_size(samples, channels, BLOCK_SIZE)
+    }
+
+    pub fn with_block_size(
+        samples: T,
+        channels: u16,
+        block_size: usize,
+    ) -> Self {
         Self {
             samples,
-            channels,
-            buffer: Vec::with_capacity(channels as usize),
+            frame: Vec::with_capacity(channels as usize),
+            buffer: AudioBuffer::new(channels as usize, block_size),
         }
     }
 
-    pub fn next(&mut self) -> Option<Result<&Vec<S>, Error>> {
+    /// Returns the next block of complete frames. The last block may be
+    /// shorter than the block size. Incomplete trailing frames are dropped.
+    pub fn next(&mut self) -> Option<Result<&mut AudioBuffer, Error>> {
         self.buffer.clear();
+        if self.buffer.channel_count() == 0 {
+            return None;
+        }
 
-        for _ in 0..self.channels {
-            match self.samples.next() {
-                None => return None,
-                Some(x) => match x {
-                    Ok(x) => self.buffer.push(x),
-                    Err(e) => return Some(Err(e)),
-                },
+        while self.buffer.len() < self.buffer.block_size() {
+            self.frame.clear();
+            for _ in 0..self.buffer.channel_count() {
+                match self.samples.next() {
+                    None => break,
+                    Some(Ok(x)) => self.frame.push(x),
+                    Some(Err(e)) => return Some(Err(e)),
+                }
+            }
+            if self.frame.len() != self.buffer.channel_count() {
+                break;
             }
+            // Frame length was checked above.
+            let _ = self.buffer.push_frame(self.frame.iter().copied());
+        }
+
+        if self.buffer.is_empty() {
+            None
+        } else {
+            Some(Ok(&mut self.buffer))
+        }
+    }
+
+    /// Passes all blocks through "process" and writes the result to
+    /// "output". The leading "latency" frames of the processed signal are
+    /// discarded and the delayed end is drained with "latency" frames of
+    /// zero padding. The second argument of "process" is true for the
+    /// padding block.
+    pub fn process_delayed<W, F>(
+        &mut self,
+        output: &mut WavWriter<W>,
+        latency: usize,
+        progress: &mut Progress,
+        mut process: F,
+    ) -> Result<(), crate::error::Error>
+    where
+        W: std::io::Write + std::io::Seek,
+        F: FnMut(&mut AudioBuffer, bool) -> Result<(), crate::error::Error>,
+    {
+        // Number of leading frames which must be discarded
+        // to compensate the latency.
+        let mut skip = latency;
+        let channels = self.buffer.channel_count();
+        while let Some(block) = self.next() {
+            let block = block?;
+            progress.advance(block.len());
+            process(block, false)?;
+            let count = skip.min(block.len());
+            block.discard_front(count);
+            skip -= count;
+            block.write(output)?;
         }
-        Some(Ok(&self.buffer))
+
+        // Drain processing pipeline
+        let mut padding = AudioBuffer::new(channels, latency);
+        padding.resize(latency);
+        process(&mut padding, true)?;
+        padding.discard_front(skip);
+        padding.write(output)?;
+
+        Ok(())
     }
 }
+
+#[test]
+fn test_channel_layout() {
+    let layout = ChannelLayout::from_mask(0x60F, 6);
+    assert_eq!(
+        layout.positions(),
+        &[
+            ChannelMap::Left,
+            ChannelMap::Right,
+            ChannelMap::Center,
+            ChannelMap::Lfe,
+            ChannelMap::SideLeft,
+            ChannelMap::SideRight,
+        ]
+    );
+    assert_eq!(layout.mask(), 0x60F);
+
+    let layout = ChannelLayout::from_mask(0x3, 3);
+    assert_eq!(layout.positions()[2], ChannelMap::Unassigned);
+    assert_eq!(layout.mask(), 0x3);
+    assert_eq!(ChannelLayout::from_channels(6).mask(), 0x3F);
+    let film = ChannelLayout::new(vec![
+        ChannelMap::Left,
+        ChannelMap::Center,
+        ChannelMap::Right,
+    ]);
+    assert_eq!(film.mask(), 0);
+    assert!(ChannelLayout::new(Vec::new()).len() == 0);
+}
@@ -30,6 +30,27 @@ use super::widgets::OpRow;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+/// Operations offered by the sidebar menu, label and action name.
+const OPERATIONS: [(&str, &str); 17] = [
+    ("Amplify", "add_amplify"),
+    ("Compressor", "add_compressor"),
+    ("Multiband Compressor", "add_multiband"),
+    ("Noise Gate", "add_gate"),
+    ("De-esser", "add_deesser"),
+    ("Delay", "add_delay"),
+    ("Reverb", "add_reverb"),
+    ("Chorus", "add_chorus"),
+    ("Flanger", "add_flanger"),
+    ("Phaser", "add_phaser"),
+    ("Saturation", "add_saturation"),
+    ("Fade", "add_fade"),
+    ("Declipper", "add_declip"),
+    ("Declicker", "add_declick"),
+    ("Noise Reduction", "add_denoise"),
+    ("Hum Removal", "add_dehum"),
+    ("Stereo Tools", "add_stereo"),
+];
+
 #[derive(Default)]
 pub struct WavehackerSidebarImpl {
     op_list: Rc<RefCell<ListBox>>,
@@ -56,8 +77,9 @@ impl ObjectImpl for WavehackerSidebarImpl {
         inner_bx.append(&label);
 
         let menu_items = Menu::new();
-        menu_items.append(Some("Amplify"), Some("win.add_amplify"));
-        menu_items.append(Some("Compressor"), Some("win.add_compressor"));
+        for (label, action) in OPERATIONS {
+            menu_items.append(Some(label), Some(&format!("win.{}", action)));
+        }
 
         let menu_button = MenuButton::builder()
             .child(&inner_bx)
@@ -102,29 +124,19 @@ impl WavehackerSidebar {
         let window = root.downcast::<ApplicationWindow>().unwrap();
         let op_list = WavehackerSidebarImpl::from_obj(self).op_list.clone();
 
-        let action_amplify = SimpleAction::new("add_amplify", None);
-        action_amplify.connect_activate(
-            glib::clone!(@strong op_list => move |_, _| {
-                // TODO: update model
-                let op_row = OpRow::new("Amplify");
-                let dummy = Label::builder().label("dummy").build();
-                op_row.set_child(Some(dummy));
-                op_list.borrow_mut().append(&op_row);
-            }),
-        );
-        window.add_action(&action_amplify);
-
-        let action_compressor = SimpleAction::new("add_compressor", None);
-        action_compressor.connect_activate(
-            glib::clone!(@strong op_list => move |_, _| {
-                // TODO: update model
-                let op_row = OpRow::new("Compressor");
-                let dummy = Label::builder().label("dummy").build();
-                op_row.set_child(Some(dummy));
-                op_list.borrow_mut().append(&op_row);
-            }),
-        );
-        window.add_action(&action_compressor);
+        for (label, action) in OPERATIONS {
+            let action = SimpleAction::new(action, None);
+            action.connect_activate(
+                glib::clone!(@strong op_list => move |_, _| {
+                    // TODO: update model
+                    let op_row = OpRow::new(label);
+                    let dummy = Label::builder().label("dummy").build();
+                    op_row.set_child(Some(dummy));
+                    op_list.borrow_mut().append(&op_row);
+                }),
+            );
+            window.add_action(&action);
+        }
     }
 }
 
@@ -0,0 +1,162 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::error::Error;
+use std::ops::Range;
+
+/// Estimates the prediction error filter "a" of an autoregressive model
+/// of order "order" from the given signal segments with the covariance
+/// method. The first coefficient is always 1. Returns None for silent
+/// segments or segments which are shorter than the order.
+pub fn estimate(segments: &[&[f64]], order: usize) -> Option<Vec<f64>> {
+    // Covariance of the delayed samples over all predicted samples
+    // which lie completely within a segment.
+    let mut covariance = vec![vec![0.0; order + 1]; order + 1];
+    for segment in segments {
+        for t in order..segment.len() {
+            for (i, row) in covariance.iter_mut().enumerate() {
+                for (j, value) in row.iter_mut().enumerate() {
+                    *value += segment[t - i] * segment[t - j];
+                }
+            }
+        }
+    }
+    if covariance[0][0] <= 0.0 {
+        return None;
+    }
+
+    // Slight white noise correction keeps the model stable.
+    let mut matrix: Vec<Vec<f64>> = covariance[1..]
+        .iter()
+        .map(|row| row[1..].to_vec())
+        .collect();
+    for (i, row) in matrix.iter_mut().enumerate() {
+        row[i] += 1e-9 * covariance[0][0];
+    }
+    let rhs = covariance[1..].iter().map(|row| -row[0]).collect();
+
+    let mut a = vec![1.0];
+    a.extend(cholesky_solve(matrix, rhs)?);
+    Some(a)
+}
+
+/// Replaces the samples of "x" within "gap" with the least squares AR
+/// interpolation for the prediction error filter "a". At least
+/// "a.len() - 1" known samples are required on both sides of the gap.
+pub fn interpolate(
+    x: &mut [f64],
+    gap: Range<usize>,
+    a: &[f64],
+) -> Result<(), Error> {
+    let order = a.len().saturating_sub(1);
+    if gap.start < order || gap.end + order > x.len() || gap.is_empty() {
+        return Err(Error::InvalidArgument(
+            "Interpolation requires known samples around the gap.".into(),
+        ));
+    }
+    let len = gap.len();
+
+    // The prediction error e[t] = sum(a[i] * x[t - i]) is minimized over
+    // all t which depend on a missing sample. Its derivative yields
+    // Toeplitz normal equations with the autocorrelation of "a".
+    let r: Vec<f64> = (0..=order)
+        .map(|d| (0..=order - d).fold(0.0, |acc, i| acc + a[i] * a[i + d]))
+        .collect();
+    let mut matrix = vec![vec![0.0; len]; len];
+    for (j, row) in matrix.iter_mut().enumerate() {
+        for (l, value) in row.iter_mut().enumerate() {
+            let d = if j > l { j - l } else { l - j };
+            *value = r.get(d).copied().unwrap_or(0.0);
+        }
+    }
+
+    // Prediction error of the known samples only
+    for value in x[gap.clone()].iter_mut() {
+        *value = 0.0;
+    }
+    let errors: Vec<f64> = (gap.start..gap.end + order)
+        .map(|t| (0..=order).fold(0.0, |acc, i| acc + a[i] * x[t - i]))
+        .collect();
+    let rhs: Vec<f64> = (0..len)
+        .map(|j| -(0..=order).fold(0.0, |acc, i| acc + a[i] * errors[j + i]))
+        .collect();
+
+    let solution = cholesky_solve(matrix, rhs).ok_or_else(|| {
+        Error::InvalidArgument("AR model is not positive definite.".into())
+    })?;
+    x[gap].copy_from_slice(&solution);
+
+    Ok(())
+}
+
+/// Solves "matrix * x = rhs" for a symmetric positive definite matrix.
+fn cholesky_solve(
+    mut matrix: Vec<Vec<f64>>,
+    mut rhs: Vec<f64>,
+) -> Option<Vec<f64>> {
+    let len = rhs.len();
+    // Lower triangular factor in place
+    for j in 0..len {
+        let diagonal = matrix[j][j]
+            - (0..j).fold(0.0, |acc, k| acc + matrix[j][k].powi(2));
+        if diagonal <= 0.0 {
+            return None;
+        }
+        let diagonal = diagonal.sqrt();
+        matrix[j][j] = diagonal;
+        for i in j + 1..len {
+            let sum =
+                (0..j).fold(0.0, |acc, k| acc + matrix[i][k] * matrix[j][k]);
+            matrix[i][j] = (matrix[i][j] - sum) / diagonal;
+        }
+    }
+
+    // Forward and backward substitution
+    for i in 0..len {
+        let sum = (0..i).fold(0.0, |acc, k| acc + matrix[i][k] * rhs[k]);
+        rhs[i] = (rhs[i] - sum) / matrix[i][i];
+    }
+    for i in (0..len).rev() {
+        let sum = (i + 1..len).fold(0.0, |acc, k| acc + matrix[k][i] * rhs[k]);
+        rhs[i] = (rhs[i] - sum) / matrix[i][i];
+    }
+
+    Some(rhs)
+}
+
+#[test]
+fn test_lpc() {
+    // Two sinusoids are predicted exactly by an AR(4) model.
+    let w: f64 = 0.1;
+    let signal: Vec<f64> = (0..400)
+        .map(|n| (w * n as f64).sin() + 0.5 * (2.7 * w * n as f64).cos())
+        .collect();
+    let a = estimate(&[&signal], 8).unwrap();
+    assert_eq!(a[0], 1.0);
+
+    let mut damaged = signal.clone();
+    for x in damaged[180..220].iter_mut() {
+        *x = 0.0;
+    }
+    interpolate(&mut damaged, 180..220, &a).unwrap();
+    for (x, y) in damaged.iter().zip(&signal) {
+        assert!((x - y).abs() < 1e-3);
+    }
+
+    assert!(interpolate(&mut damaged, 2..10, &a).is_err());
+    assert!(estimate(&[&[0.0; 16]], 4).is_none());
+}
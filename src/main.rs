@@ -18,17 +18,27 @@
 #![forbid(unsafe_code)]
 
 use clap::{Parser, Subcommand};
-use hound::{WavReader, WavWriter};
+use frame::ChannelLayout;
+use hound::{WavReader, WavSpec, WavWriter};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
 
 mod analyzer;
+mod buffer;
 mod conversion;
 mod effects;
 mod error;
+mod fft;
 mod filters;
 mod frame;
 mod gui;
+mod lpc;
 mod operations;
+mod png;
 mod progress;
+mod riff;
+mod stft;
+mod time;
 
 #[derive(Debug, Parser)]
 #[command(name = "audio-effects")]
@@ -50,28 +60,139 @@ enum Commands {
     Amplify(effects::amplify::Settings),
     /// Dynamic compression
     Compressor(effects::compressor::Settings),
+    /// Multiband dynamic compression
+    Multiband(effects::multiband::Settings),
+    /// Noise gate and downward expander
+    Gate(effects::gate::Settings),
+    /// Reduce sibilance
+    Deesser(effects::deesser::Settings),
+    /// Echo with filtered feedback
+    Delay(effects::delay::Settings),
+    /// Chorus, flanger and phaser
+    Modulation(effects::modulation::Settings),
+    /// Algorithmic reverb
+    Reverb(effects::reverb::Settings),
+    /// Saturation and waveshaping distortion
+    Saturation(effects::saturation::Settings),
+    /// Repair clipped samples
+    Declip(effects::declip::Settings),
+    /// Remove clicks and pops
+    Declick(effects::declick::Settings),
+    /// Learn a noise profile or reduce noise with it
+    Denoise(effects::denoise::Settings),
+    /// Remove mains hum and DC offset
+    Dehum(effects::dehum::Settings),
+    /// Mid/side, width, balance, pan, swap and polarity tools
+    Stereo(effects::stereo::Settings),
+    /// Fades and gain envelopes
+    Fade(effects::fade::Settings),
     /// Normalize audio loudness
     Normalize(operations::normalize::Settings),
+    /// Downmix, upmix or reorder channels
+    Remix(operations::remix::Settings),
+    /// Resample audio
+    Resample(operations::resample::Settings),
+    /// Concatenate files with optional crossfades
+    Concat(operations::concat::Settings),
+    /// Mix multiple files with individual gains and offsets
+    Mix(operations::mix::Settings),
+    /// Split into multiple files at silence or markers
+    Split(operations::split::Settings),
+    /// Keep or remove a time range
+    Trim(operations::trim::Settings),
+    /// Remove or shorten silence
+    StripSilence(operations::silence::Settings),
     /// Analyze audio true peak
     TruePeak(analyzer::true_peak::Settings),
+    /// Detect clipped samples and true peak overs
+    Clipping(analyzer::clipping::Settings),
     /// Analyze audio loudness
     Loudness(analyzer::loudness::Settings),
     /// Analyze audio RMS
     Rms(analyzer::rms::Settings),
+    /// Analyze DC offset
+    DcOffset(analyzer::dc::Settings),
+    /// Detect silent regions
+    Silence(analyzer::silence::Settings),
+    /// Analyze the averaged power spectrum
+    Spectrum(analyzer::spectrum::Settings),
+    /// Render a spectrogram image
+    Spectrogram(analyzer::spectrogram::Settings),
 }
 
 fn open_input(
     input_filename: Option<String>,
-) -> WavReader<std::io::BufReader<std::fs::File>> {
-    let input = WavReader::open(input_filename.unwrap()).unwrap();
+) -> (WavReader<BufReader<File>>, ChannelLayout) {
+    let input_filename = input_filename.unwrap();
+    let input = WavReader::open(&input_filename).unwrap();
     let spec = input.spec();
     let duration = input.duration();
+    let layout = riff::read_channel_layout(
+        &mut File::open(&input_filename).unwrap(),
+        spec.channels,
+    )
+    .unwrap();
     eprintln!(
-        "channels: {}, sample_rate: {}, length: {}",
-        spec.channels, spec.sample_rate, duration,
+        "channels: {}, sample_rate: {}, length: {}, layout: {}",
+        spec.channels, spec.sample_rate, duration, layout,
     );
 
-    input
+    (input, layout)
+}
+
+fn finalize_output(
+    output: WavWriter<BufWriter<File>>,
+    output_filename: &str,
+    layout: &ChannelLayout,
+) {
+    if let Err(e) = output.finalize() {
+        println!("Finalizing wav file failed: {}", e.to_string());
+        return;
+    }
+    let result = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(output_filename)
+        .map_err(error::Error::from)
+        .and_then(|mut file| riff::write_channel_layout(&mut file, layout));
+    if let Err(e) = result {
+        println!("Writing channel layout failed: {}", e.to_string());
+    }
+}
+
+/// Formats per channel analysis results with speaker labels.
+fn format_channels(values: &[f64], layout: &ChannelLayout) -> String {
+    format_labeled(
+        &values
+            .iter()
+            .map(|x| format!("{:?}", x))
+            .collect::<Vec<_>>(),
+        layout,
+    )
+}
+
+/// Formats per channel strings with speaker labels.
+fn format_labeled(values: &[String], layout: &ChannelLayout) -> String {
+    if values.len() == layout.len() && values.len() > 1 {
+        values
+            .iter()
+            .zip(layout.positions())
+            .map(|(x, position)| format!("{}: {}", position.label(), x))
+            .collect::<Vec<String>>()
+            .join(", ")
+    } else {
+        values.join(", ")
+    }
+}
+
+/// Reports the gain which was applied to avoid clipping.
+fn print_attenuation(gain: f32) {
+    if gain < 1.0 {
+        println!(
+            "\nAttenuated by {:.2} dB to avoid clipping",
+            -20.0 * gain.log10()
+        );
+    }
 }
 
 fn main() {
@@ -81,77 +202,545 @@ fn main() {
         None => gui::run(),
         Some(x) => match x {
             Commands::Amplify(x) => {
-                let mut input = open_input(cli.input_filename);
-                let mut output = match &cli.output_filename {
-                    Some(filename) => {
-                        WavWriter::create(filename, input.spec()).unwrap()
-                    }
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
                     None => {
                         println!("No output filename was given!");
                         return;
                     }
                 };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
                 if let Err(e) = x.amplify(&mut input, &mut output) {
                     println!("\nAmplifying failed: {}", e.to_string());
                 }
-                if let Err(e) = output.finalize() {
-                    println!("Finalizing wav file failed: {}", e.to_string());
-                }
+                finalize_output(output, output_filename, &layout);
             }
             Commands::Compressor(x) => {
-                let mut input = open_input(cli.input_filename);
-                let mut output = match &cli.output_filename {
-                    Some(filename) => {
-                        WavWriter::create(filename, input.spec()).unwrap()
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
                     }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.compress(&mut input, &mut output) {
+                    println!("\nCompressing failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Multiband(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
                     None => {
                         println!("No output filename was given!");
                         return;
                     }
                 };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
                 if let Err(e) = x.compress(&mut input, &mut output) {
                     println!("\nCompressing failed: {}", e.to_string());
                 }
-                if let Err(e) = output.finalize() {
-                    println!("Finalizing wav file failed: {}", e.to_string());
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Deesser(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.deess(&mut input, &mut output) {
+                    println!("\nDe-essing failed: {}", e.to_string());
                 }
+                finalize_output(output, output_filename, &layout);
             }
-            Commands::Normalize(x) => {
-                let mut input = open_input(cli.input_filename);
-                let mut output = match &cli.output_filename {
-                    Some(filename) => {
-                        WavWriter::create(filename, input.spec()).unwrap()
+            Commands::Gate(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.gate(&mut input, &mut output) {
+                    println!("\nGating failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Delay(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.delay(&mut input, &mut output) {
+                    println!("\nDelaying failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Modulation(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.modulate(&mut input, &mut output) {
+                    println!("\nModulating failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Reverb(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.reverb(&mut input, &mut output, &layout) {
+                    println!("\nReverberating failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Saturation(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.saturate(&mut input, &mut output) {
+                    println!("\nSaturating failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Declip(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.declip(&mut input, &mut output) {
+                    println!("\nDeclipping failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Declick(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                if x.report() {
+                    let fs = input.spec().sample_rate as f64;
+                    match x.analyze(&mut input) {
+                        Ok(clicks) => {
+                            println!("\nInput has {} clicks", clicks.len());
+                            for click in &clicks {
+                                println!(
+                                    "{} click at {} for {} samples",
+                                    layout.positions()[click.channel].label(),
+                                    time::format_frames(click.start, fs),
+                                    click.len
+                                );
+                            }
+                        }
+                        Err(e) => {
+                            println!(
+                                "\nClick analysis failed: {}",
+                                e.to_string()
+                            )
+                        }
+                    }
+                    return;
+                }
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                match x.declick(&mut input, &mut output) {
+                    Ok(count) => println!("\nRemoved {} clicks", count),
+                    Err(e) => {
+                        println!("\nDeclicking failed: {}", e.to_string())
+                    }
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Denoise(x) => match x.action() {
+                effects::denoise::Action::Learn(x) => {
+                    let (mut input, _) = open_input(cli.input_filename);
+                    match x
+                        .learn(&mut input)
+                        .and_then(|profile| profile.save(x.profile()))
+                    {
+                        Ok(()) => {
+                            println!("\nSaved noise profile to {}", x.profile())
+                        }
+                        Err(e) => println!(
+                            "\nLearning noise profile failed: {}",
+                            e.to_string()
+                        ),
+                    }
+                }
+                effects::denoise::Action::Apply(x) => {
+                    let (mut input, layout) = open_input(cli.input_filename);
+                    let output_filename = match &cli.output_filename {
+                        Some(filename) => filename,
+                        None => {
+                            println!("No output filename was given!");
+                            return;
+                        }
+                    };
+                    let mut output =
+                        WavWriter::create(output_filename, input.spec())
+                            .unwrap();
+                    if let Err(e) = x.denoise(&mut input, &mut output) {
+                        println!("\nDenoising failed: {}", e.to_string());
+                    }
+                    finalize_output(output, output_filename, &layout);
+                }
+            },
+            Commands::Dehum(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                match x.dehum(&mut input, &mut output) {
+                    Ok(frequency) => {
+                        println!("\nRemoved hum at {} Hz", frequency)
+                    }
+                    Err(e) => {
+                        println!("\nDehumming failed: {}", e.to_string())
+                    }
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Stereo(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                if let Err(e) =
+                    effects::stereo::check_channels(input.spec().channels)
+                {
+                    println!("Stereo processing failed: {}", e.to_string());
+                    return;
+                }
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.process(&mut input, &mut output) {
+                    println!("\nStereo processing failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Fade(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
                     }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.fade(&mut input, &mut output) {
+                    println!("\nFading failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Normalize(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
                     None => {
                         println!("No output filename was given!");
                         return;
                     }
                 };
-                if let Err(e) = x.normalize(&mut input, &mut output) {
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.normalize(&mut input, &mut output, &layout) {
                     println!("\nNormalizing failed: {}", e.to_string());
                 }
-                if let Err(e) = output.finalize() {
-                    println!("Finalizing wav file failed: {}", e.to_string());
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Remix(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_layout = match x.remixer(&layout) {
+                    Ok(remix) => remix.layout().clone(),
+                    Err(e) => {
+                        println!("Remixing failed: {}", e.to_string());
+                        return;
+                    }
+                };
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let spec = WavSpec {
+                    channels: output_layout.len() as u16,
+                    ..input.spec()
+                };
+                let mut output =
+                    WavWriter::create(output_filename, spec).unwrap();
+                if let Err(e) = x.remix(&mut input, &mut output, &layout) {
+                    println!("\nRemixing failed: {}", e.to_string());
                 }
+                finalize_output(output, output_filename, &output_layout);
             }
-            Commands::TruePeak(x) => {
-                match x.analyze(&mut open_input(cli.input_filename)) {
-                    Ok(true_peak) => {
-                        println!("Input has true peak at {:?}", true_peak)
+            Commands::Resample(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let spec = WavSpec {
+                    sample_rate: x.sample_rate(),
+                    ..input.spec()
+                };
+                let mut output =
+                    WavWriter::create(output_filename, spec).unwrap();
+                if let Err(e) = x.resample(&mut input, &mut output) {
+                    println!("\nResampling failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Concat(x) => {
+                let (input, layout) = open_input(x.inputs().first().cloned());
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                match x.concat(&mut output, &layout) {
+                    Ok(gain) => print_attenuation(gain),
+                    Err(e) => {
+                        println!("\nConcatenating failed: {}", e.to_string())
+                    }
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Mix(x) => {
+                let (input, layout) = open_input(x.inputs().first().cloned());
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                match x.mix(&mut output, &layout) {
+                    Ok(gain) => print_attenuation(gain),
+                    Err(e) => println!("\nMixing failed: {}", e.to_string()),
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::Split(x) => {
+                let input_filename = cli.input_filename.clone().unwrap();
+                let (mut input, layout) = open_input(cli.input_filename);
+                let template = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename template was given!");
+                        return;
+                    }
+                };
+                let cue_points = match x.source() {
+                    operations::split::Source::Cue => riff::read_cue_points(
+                        &mut File::open(&input_filename).unwrap(),
+                    ),
+                    _ => Ok(Vec::new()),
+                };
+                let result = cue_points.and_then(|cue_points| {
+                    x.split(&mut input, &layout, &cue_points, template)
+                });
+                match result {
+                    Ok(filenames) => {
+                        println!("\nWrote {} files", filenames.len());
+                        for filename in filenames {
+                            println!("{}", filename);
+                        }
                     }
+                    Err(e) => println!("\nSplitting failed: {}", e.to_string()),
+                }
+            }
+            Commands::Trim(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.trim(&mut input, &mut output) {
+                    println!("\nTrimming failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::StripSilence(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let mut output =
+                    WavWriter::create(output_filename, input.spec()).unwrap();
+                if let Err(e) = x.strip(&mut input, &mut output, &layout) {
+                    println!("\nStripping silence failed: {}", e.to_string());
+                }
+                finalize_output(output, output_filename, &layout);
+            }
+            Commands::TruePeak(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                match x.analyze(&mut input) {
+                    Ok(true_peak) => println!(
+                        "Input has true peak at [{}]",
+                        format_channels(&true_peak, &layout)
+                    ),
                     Err(e) => {
                         println!("True peak analyses failed: {}", e.to_string())
                     }
                 }
             }
+            Commands::Clipping(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let fs = input.spec().sample_rate as f64;
+                match x.analyze(&mut input) {
+                    Ok(events) => {
+                        let clips = events
+                            .iter()
+                            .filter(|x| {
+                                x.kind == analyzer::clipping::Kind::Clip
+                            })
+                            .count();
+                        println!(
+                            "\nInput has {} clipped runs and {} true peak overs",
+                            clips,
+                            events.len() - clips
+                        );
+                        for event in &events {
+                            let (name, unit) = match event.kind {
+                                analyzer::clipping::Kind::Clip => {
+                                    ("clipped", "dBFS")
+                                }
+                                analyzer::clipping::Kind::Over => {
+                                    ("over", "dBTP")
+                                }
+                            };
+                            println!(
+                                "{} {} at {} for {} samples, peak {:.2} {}",
+                                layout.positions()[event.channel].label(),
+                                name,
+                                time::format_frames(event.start, fs),
+                                event.len,
+                                20.0 * event.peak.log10(),
+                                unit
+                            );
+                        }
+                        if x.summary() {
+                            for (idx, position) in
+                                layout.positions().iter().enumerate()
+                            {
+                                let count = |kind| {
+                                    events
+                                        .iter()
+                                        .filter(|x| {
+                                            x.channel == idx && x.kind == kind
+                                        })
+                                        .count()
+                                };
+                                println!(
+                                    "{}: {} clipped runs, {} true peak overs",
+                                    position.label(),
+                                    count(analyzer::clipping::Kind::Clip),
+                                    count(analyzer::clipping::Kind::Over)
+                                );
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        println!("Clipping analysis failed: {}", e.to_string())
+                    }
+                }
+            }
             Commands::Loudness(x) => {
-                match x.analyze(&mut open_input(cli.input_filename)) {
+                let (mut input, layout) = open_input(cli.input_filename);
+                match x.analyze(&mut input, &layout) {
                     Ok(loudness) => println!(
-                        "Input has integrative loudness of {:?} LUFS",
-                        loudness
-                            .iter()
-                            .map(|x| 10.0 * x.log10())
-                            .collect::<Vec<f64>>(),
+                        "Input has integrative loudness of [{}] LUFS",
+                        format_channels(
+                            &loudness
+                                .iter()
+                                .map(|x| 10.0 * x.log10())
+                                .collect::<Vec<f64>>(),
+                            &layout
+                        ),
                     ),
                     Err(e) => {
                         println!("Loudness analysis failed: {}", e.to_string())
@@ -159,18 +748,114 @@ fn main() {
                 }
             }
             Commands::Rms(x) => {
-                match x.analyze(&mut open_input(cli.input_filename)) {
+                let (mut input, layout) = open_input(cli.input_filename);
+                match x.analyze(&mut input) {
                     Ok(rms) => println!(
-                        "Input has RMS of {:?} dB",
-                        rms.iter()
-                            .map(|x| 20.0 * x.log10())
-                            .collect::<Vec<f64>>()
+                        "Input has RMS of [{}] dB",
+                        format_channels(
+                            &rms.iter()
+                                .map(|x| 20.0 * x.log10())
+                                .collect::<Vec<f64>>(),
+                            &layout
+                        )
                     ),
                     Err(e) => {
                         println!("RMS analysis failed: {}", e.to_string())
                     }
                 }
             }
+            Commands::DcOffset(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                match x.analyze(&mut input) {
+                    Ok(offsets) => println!(
+                        "Input has DC offset of [{}]",
+                        format_labeled(
+                            &offsets
+                                .iter()
+                                .map(|x| if *x == 0.0 {
+                                    format!("{:+.6}", x)
+                                } else {
+                                    format!(
+                                        "{:+.6} ({:.2} dBFS)",
+                                        x,
+                                        20.0 * x.abs().log10()
+                                    )
+                                })
+                                .collect::<Vec<String>>(),
+                            &layout
+                        )
+                    ),
+                    Err(e) => {
+                        println!("DC offset analysis failed: {}", e.to_string())
+                    }
+                }
+            }
+            Commands::Silence(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let fs = input.spec().sample_rate as f64;
+                match x.analyze(&mut input, &layout) {
+                    Ok(regions) => {
+                        println!(
+                            "\nInput has {} silent regions",
+                            regions.len()
+                        );
+                        for (start, end) in regions {
+                            println!(
+                                "{} - {}",
+                                time::format_frames(start, fs),
+                                time::format_frames(end, fs)
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        println!("Silence analysis failed: {}", e.to_string())
+                    }
+                }
+            }
+            Commands::Spectrum(x) => {
+                let (mut input, layout) = open_input(cli.input_filename);
+                let result = x.analyze(&mut input).and_then(|spectrum| {
+                    match &cli.output_filename {
+                        Some(filename) => x.write(
+                            &spectrum,
+                            &layout,
+                            &mut BufWriter::new(File::create(filename)?),
+                        ),
+                        None => x.write(
+                            &spectrum,
+                            &layout,
+                            &mut std::io::stdout().lock(),
+                        ),
+                    }
+                });
+                if let Err(e) = result {
+                    println!("Spectrum analysis failed: {}", e.to_string())
+                }
+            }
+            Commands::Spectrogram(x) => {
+                let (mut input, _) = open_input(cli.input_filename);
+                let output_filename = match &cli.output_filename {
+                    Some(filename) => filename,
+                    None => {
+                        println!("No output filename was given!");
+                        return;
+                    }
+                };
+                let result = File::create(output_filename)
+                    .map_err(error::Error::from)
+                    .and_then(|file| {
+                        x.render(&mut input, &mut BufWriter::new(file))
+                    });
+                match result {
+                    Ok((width, height)) => {
+                        println!("\nWrote {}x{} image", width, height)
+                    }
+                    Err(e) => println!(
+                        "\nSpectrogram rendering failed: {}",
+                        e.to_string()
+                    ),
+                }
+            }
         },
     };
 }
@@ -0,0 +1,122 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::mix::{converted_len, OutputSettings, Track};
+use crate::effects::fade::FadeShape;
+use crate::error::Error;
+use crate::frame::ChannelLayout;
+use hound::WavWriter;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Input wav filenames
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Length of the crossfades between the inputs in seconds.
+    #[arg(short, long, default_value_t = 0.0)]
+    crossfade: f64,
+    /// Shape of the crossfades
+    #[arg(short, long, value_enum, default_value_t = FadeShape::Linear)]
+    shape: FadeShape,
+    #[command(flatten)]
+    output: OutputSettings,
+}
+
+impl Settings {
+    /// Input wav filenames. The first input determines the output format.
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    /// Concatenates the inputs into the output. Returns the gain which was
+    /// applied to avoid clipping.
+    pub fn concat<W>(
+        &self,
+        output: &mut WavWriter<W>,
+        layout: &ChannelLayout,
+    ) -> Result<f32, Error>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        if self.crossfade < 0.0 {
+            return Err(Error::InvalidArgument(
+                "Crossfade length must not be negative.".into(),
+            ));
+        }
+
+        let fs = output.spec().sample_rate;
+        let lengths = self
+            .inputs
+            .iter()
+            .map(|x| converted_len(x, fs))
+            .collect::<Result<Vec<usize>, Error>>()?;
+        let crossfade = (self.crossfade * fs as f64).round() as usize;
+
+        self.output
+            .render(&self.tracks(&lengths, crossfade), output, layout)
+    }
+
+    /// Places the inputs one after another, overlapping by the crossfade
+    /// length. The crossfade is limited to the lengths of both neighbours.
+    fn tracks(&self, lengths: &[usize], crossfade: usize) -> Vec<Track> {
+        let overlaps = lengths
+            .windows(2)
+            .map(|x| crossfade.min(x[0]).min(x[1]))
+            .collect::<Vec<usize>>();
+
+        let mut offset = 0;
+        let mut tracks = Vec::with_capacity(lengths.len());
+        for (i, (filename, len)) in self.inputs.iter().zip(lengths).enumerate()
+        {
+            let fade_in = if i > 0 { overlaps[i - 1] } else { 0 };
+            let fade_out = overlaps.get(i).copied().unwrap_or(0);
+            offset -= fade_in;
+            tracks.push(Track {
+                filename: filename.clone(),
+                offset,
+                gain: 1.0,
+                fade_in,
+                fade_out,
+                shape: self.shape,
+            });
+            offset += len;
+        }
+
+        tracks
+    }
+}
+
+#[test]
+fn test_concat_tracks() {
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Args {
+        #[command(flatten)]
+        settings: Settings,
+    }
+
+    let args = Args::parse_from(["concat", "a.wav", "b.wav", "c.wav"]);
+    let tracks = args.settings.tracks(&[1000, 50, 2000], 100);
+    assert_eq!(
+        tracks
+            .iter()
+            .map(|x| (x.offset, x.fade_in, x.fade_out))
+            .collect::<Vec<_>>(),
+        vec![(0, 0, 50), (950, 50, 50), (950, 50, 0)]
+    );
+}
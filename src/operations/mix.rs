@@ -0,0 +1,523 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::remix::{self, Remix};
+use super::resample::{Quality, Resampler};
+use crate::analyzer::true_peak::TruePeak;
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::effects::fade::{Envelope, FadeShape};
+use crate::error::Error;
+use crate::frame::{ChannelLayout, FrameIterator, BLOCK_SIZE};
+use crate::progress::Progress;
+use crate::riff;
+use crate::time::Timestamp;
+use hound::{WavReader, WavWriter};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufReader;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Input wav filenames
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Gains in dB, either one for all inputs or one per input.
+    #[arg(short, long, value_delimiter = ',', allow_hyphen_values = true)]
+    gain_db: Vec<f64>,
+    /// Start offsets as "48000smp", seconds or "hh:mm:ss.ms",
+    /// one per input.
+    #[arg(short = 't', long, value_delimiter = ',')]
+    offset: Vec<Timestamp>,
+    #[command(flatten)]
+    output: OutputSettings,
+}
+
+/// Conversion and clipping protection settings shared by all operations
+/// with multiple inputs.
+#[derive(Debug, Clone, clap::Args)]
+pub struct OutputSettings {
+    /// Resampling filter quality for inputs with a different sample rate.
+    #[arg(short, long, value_enum, default_value_t = Quality::High)]
+    quality: Quality,
+    /// The output is attenuated if its true peak exceeds this level in dB.
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    ceiling_db: f64,
+    /// Skip the true peak analysis pass and allow clipping.
+    #[arg(long)]
+    allow_clipping: bool,
+}
+
+impl Settings {
+    /// Input wav filenames. The first input determines the output format.
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    /// Mixes the inputs into the output. Returns the gain which was
+    /// applied to avoid clipping.
+    pub fn mix<W>(
+        &self,
+        output: &mut WavWriter<W>,
+        layout: &ChannelLayout,
+    ) -> Result<f32, Error>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        let count = self.inputs.len();
+        if self.gain_db.len() > 1 && self.gain_db.len() != count {
+            return Err(Error::InvalidArgument(
+                "Expected one gain or one for each input.".into(),
+            ));
+        }
+        if !self.offset.is_empty() && self.offset.len() != count {
+            return Err(Error::InvalidArgument(
+                "Expected one offset for each input.".into(),
+            ));
+        }
+
+        let fs = output.spec().sample_rate as f64;
+        let tracks = self
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, filename)| Track {
+                filename: filename.clone(),
+                offset: self.offset.get(i).map(|x| x.frames(fs)).unwrap_or(0),
+                gain: self
+                    .gain_db
+                    .get(i.min(self.gain_db.len().saturating_sub(1)))
+                    .map(|x| 10.0_f32.powf(*x as f32 / 20.0))
+                    .unwrap_or(1.0),
+                fade_in: 0,
+                fade_out: 0,
+                shape: FadeShape::Linear,
+            })
+            .collect::<Vec<Track>>();
+
+        self.output.render(&tracks, output, layout)
+    }
+}
+
+impl OutputSettings {
+    /// Mixes the tracks into the output. If clipping protection is
+    /// enabled, the true peak of the mix is analyzed in a first pass.
+    /// Returns the gain which was applied to avoid clipping.
+    pub fn render<W>(
+        &self,
+        tracks: &[Track],
+        output: &mut WavWriter<W>,
+        layout: &ChannelLayout,
+    ) -> Result<f32, Error>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        let fs = output.spec().sample_rate;
+        let mut gain = 1.0;
+        if !self.allow_clipping {
+            let mut true_peak = TruePeak::new(layout.len());
+            render(tracks, fs, layout, self.quality, "Analyzing", |block| {
+                true_peak.process(block.channels())
+            })?;
+            let ceiling = 10.0_f64.powf(self.ceiling_db / 20.0);
+            if true_peak.true_peak() > ceiling {
+                gain = (ceiling / true_peak.true_peak()) as f32;
+            }
+        }
+
+        render(tracks, fs, layout, self.quality, "Mixing", |block| {
+            if gain == 1.0 {
+                return block.write(output);
+            }
+            for x in block.interleaved() {
+                output.write_sample(x * gain)?;
+            }
+            Ok(())
+        })?;
+
+        Ok(gain)
+    }
+}
+
+/// Input file with its placement in the output.
+#[derive(Debug, Clone)]
+pub struct Track {
+    /// Input wav filename
+    pub filename: String,
+    /// Start offset in output frames
+    pub offset: usize,
+    /// Linear gain
+    pub gain: f32,
+    /// Fade-in length in output frames
+    pub fade_in: usize,
+    /// Fade-out length in output frames
+    pub fade_out: usize,
+    /// Shape of the fades
+    pub shape: FadeShape,
+}
+
+/// Opens a wave file and reads its channel layout.
+fn open(
+    filename: &str,
+) -> Result<(WavReader<BufReader<File>>, ChannelLayout), Error> {
+    let reader = WavReader::open(filename)?;
+    let layout = riff::read_channel_layout(
+        &mut File::open(filename)?,
+        reader.spec().channels,
+    )?;
+    Ok((reader, layout))
+}
+
+/// Length of a wave file in frames after conversion to sample rate "fs".
+pub fn converted_len(filename: &str, fs: u32) -> Result<usize, Error> {
+    let reader = WavReader::open(filename)?;
+    let fs_in = reader.spec().sample_rate as u64;
+    Ok(((reader.duration() as u64 * fs as u64 + fs_in - 1) / fs_in) as usize)
+}
+
+/// Mixes the tracks and passes the mix block by block to "sink".
+fn render<F>(
+    tracks: &[Track],
+    fs: u32,
+    layout: &ChannelLayout,
+    quality: Quality,
+    message: &str,
+    mut sink: F,
+) -> Result<(), Error>
+where
+    F: FnMut(&AudioBuffer) -> Result<(), Error>,
+{
+    let mut readers = tracks
+        .iter()
+        .map(|x| open(&x.filename))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let mut inputs = readers
+        .iter_mut()
+        .map(|(reader, input_layout)| {
+            ConvertedInput::new(reader, input_layout, fs, layout, quality)
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let mut envelopes = tracks
+        .iter()
+        .zip(&inputs)
+        .map(|(track, input)| {
+            Envelope::from_fades(
+                fs as f64,
+                input.len(),
+                track.fade_in,
+                track.fade_out,
+                track.shape,
+            )
+        })
+        .collect::<Vec<Envelope>>();
+    let total = tracks
+        .iter()
+        .zip(&inputs)
+        .map(|(track, input)| track.offset + input.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut progress = Progress::new(total, format!("{} sample", message));
+    let mut mix = AudioBuffer::new(layout.len(), BLOCK_SIZE);
+    let mut buffer = AudioBuffer::new(layout.len(), BLOCK_SIZE);
+    let mut pos = 0;
+    while pos < total {
+        let len = BLOCK_SIZE.min(total - pos);
+        mix.clear();
+        mix.resize(len);
+
+        for ((input, track), envelope) in
+            inputs.iter_mut().zip(tracks).zip(envelopes.iter_mut())
+        {
+            if pos + len <= track.offset {
+                continue;
+            }
+            // Frames of this block before the track starts
+            let skip = track.offset.saturating_sub(pos);
+            input.read(len - skip, &mut buffer)?;
+            envelope.process(&mut buffer);
+            for (channel, samples) in mix.channels_mut().zip(buffer.channels())
+            {
+                for (y, x) in channel[skip..].iter_mut().zip(samples) {
+                    *y += track.gain * x;
+                }
+            }
+        }
+
+        progress.advance(len);
+        sink(&mix)?;
+        pos += len;
+    }
+
+    Ok(())
+}
+
+/// Selects a channel conversion from the input to the output layout.
+/// Channels are mapped by speaker position if possible, otherwise the
+/// stereo or mono downmix presets are used.
+fn channel_conversion(
+    input: &ChannelLayout,
+    output: &ChannelLayout,
+) -> Result<Option<Remix>, Error> {
+    if input == output {
+        return Ok(None);
+    }
+
+    let positions = output.positions();
+    let mapped = input.positions().iter().all(|x| {
+        positions.iter().filter(|y| *y == x).count() == 1
+            && input.positions().iter().filter(|y| *y == x).count() == 1
+    });
+    if mapped {
+        let matrix = positions
+            .iter()
+            .map(|y| {
+                input
+                    .positions()
+                    .iter()
+                    .map(|x| if x == y { 1.0 } else { 0.0 })
+                    .collect()
+            })
+            .collect();
+        return Ok(Some(Remix::new(matrix, output.clone())));
+    }
+
+    let mode = if *output == ChannelLayout::from_channels(2) {
+        remix::Mode::Stereo
+    } else if *output == ChannelLayout::from_channels(1) {
+        remix::Mode::Mono
+    } else {
+        return Err(Error::InvalidArgument(format!(
+            "Cannot convert channel layout {} to {}, remix the input first.",
+            input, output
+        )));
+    };
+    Ok(Some(remix::Settings::new(mode).remixer(input)?))
+}
+
+/// Input stream which is converted to the output sample rate
+/// and channel layout.
+struct ConvertedInput<'a> {
+    /// Input blocks
+    frames:
+        FrameIterator<Box<dyn Iterator<Item = Result<f32, hound::Error>> + 'a>>,
+    /// Converter to the output format
+    converter: Converter,
+    /// All input frames were read.
+    finished: bool,
+}
+
+impl<'a> ConvertedInput<'a> {
+    fn new<R>(
+        reader: &'a mut WavReader<R>,
+        layout: &ChannelLayout,
+        fs: u32,
+        output_layout: &ChannelLayout,
+        quality: Quality,
+    ) -> Result<Self, Error>
+    where
+        R: std::io::Read,
+    {
+        let spec = reader.spec();
+        let converter = Converter::new(
+            spec.sample_rate,
+            reader.duration() as usize,
+            layout,
+            fs,
+            output_layout,
+            quality,
+        )?;
+        Ok(Self {
+            frames: FrameIterator::new(reader.samples_f32(), spec.channels),
+            converter,
+            finished: false,
+        })
+    }
+
+    /// Length in output frames.
+    fn len(&self) -> usize {
+        self.converter.len
+    }
+
+    /// Reads up to "count" converted frames. Fewer frames are returned
+    /// at the end of the input.
+    fn read(
+        &mut self,
+        count: usize,
+        output: &mut AudioBuffer,
+    ) -> Result<(), Error> {
+        while self.converter.available() < count && !self.finished {
+            match self.frames.next() {
+                Some(Ok(block)) => self.converter.push(block)?,
+                Some(Err(e)) => return Err(e.into()),
+                None => {
+                    self.converter.drain()?;
+                    self.finished = true;
+                }
+            }
+        }
+        self.converter.pop(count, output);
+        Ok(())
+    }
+}
+
+/// Sample rate and channel layout converter with output queue.
+struct Converter {
+    /// Resampler if the sample rates differ.
+    resampler: Option<Resampler>,
+    /// Channel conversion if the layouts differ.
+    remix: Option<Remix>,
+    /// Remix working buffer
+    remixed: AudioBuffer,
+    /// Resampling working buffer
+    resampled: AudioBuffer,
+    /// Number of input channels
+    input_channels: usize,
+    /// Converted frames which were not read yet, one queue per channel.
+    pending: Vec<VecDeque<f32>>,
+    /// Total length in output frames
+    len: usize,
+    /// Number of output frames which were not produced yet.
+    remaining: usize,
+}
+
+impl Converter {
+    fn new(
+        fs_in: u32,
+        duration: usize,
+        layout: &ChannelLayout,
+        fs: u32,
+        output_layout: &ChannelLayout,
+        quality: Quality,
+    ) -> Result<Self, Error> {
+        let channels = output_layout.len();
+        let resampler = if fs_in == fs {
+            None
+        } else {
+            Some(Resampler::new(fs_in, fs, channels, quality)?)
+        };
+        let len = match &resampler {
+            Some(x) => x.output_len(duration),
+            None => duration,
+        };
+
+        Ok(Self {
+            resampler,
+            remix: channel_conversion(layout, output_layout)?,
+            remixed: AudioBuffer::new(channels, BLOCK_SIZE),
+            resampled: AudioBuffer::new(channels, BLOCK_SIZE),
+            input_channels: layout.len(),
+            pending: vec![VecDeque::new(); channels],
+            len,
+            remaining: len,
+        })
+    }
+
+    /// Number of converted frames in the queue.
+    fn available(&self) -> usize {
+        self.pending.first().map(|x| x.len()).unwrap_or(0)
+    }
+
+    /// Converts one block of input frames and appends it to the queue.
+    fn push(&mut self, block: &AudioBuffer) -> Result<(), Error> {
+        let block = match &self.remix {
+            Some(remix) => {
+                remix.process(block, &mut self.remixed)?;
+                &self.remixed
+            }
+            None => block,
+        };
+        let block = match &mut self.resampler {
+            Some(resampler) => {
+                resampler.process(block, &mut self.resampled)?;
+                &self.resampled
+            }
+            None => block,
+        };
+
+        let len = block.len().min(self.remaining);
+        self.remaining -= len;
+        for (pending, channel) in self.pending.iter_mut().zip(block.channels())
+        {
+            pending.extend(&channel[..len]);
+        }
+        Ok(())
+    }
+
+    /// Drains the resampling filter delay line.
+    fn drain(&mut self) -> Result<(), Error> {
+        if let Some(resampler) = &self.resampler {
+            let latency = resampler.latency() + 1;
+            let mut padding = AudioBuffer::new(self.input_channels, latency);
+            padding.resize(latency);
+            self.push(&padding)?;
+        }
+        Ok(())
+    }
+
+    /// Moves up to "count" frames from the queue into "output".
+    fn pop(&mut self, count: usize, output: &mut AudioBuffer) {
+        let count = count.min(self.available());
+        output.clear();
+        output.resize(count);
+        for (channel, pending) in output.channels_mut().zip(&mut self.pending) {
+            for (y, x) in channel.iter_mut().zip(pending.drain(..count)) {
+                *y = x;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_channel_conversion() {
+    let mono = ChannelLayout::from_channels(1);
+    let stereo = ChannelLayout::from_channels(2);
+    let surround = ChannelLayout::from_channels(6);
+
+    assert!(channel_conversion(&stereo, &stereo).unwrap().is_none());
+    assert!(channel_conversion(&surround, &mono).is_ok());
+    assert!(channel_conversion(&mono, &surround).is_ok());
+    assert!(
+        channel_conversion(&surround, &ChannelLayout::from_channels(4))
+            .is_err()
+    );
+
+    // Mono is spread to both stereo channels.
+    let mut input = AudioBuffer::new(1, 1);
+    input.push_frame([0.5]).unwrap();
+    let mut output = AudioBuffer::new(2, 1);
+    channel_conversion(&mono, &stereo)
+        .unwrap()
+        .unwrap()
+        .process(&input, &mut output)
+        .unwrap();
+    assert_eq!(output.channel(0)[0], output.channel(1)[0]);
+
+    // Stereo is mapped to the front channels of 5.1.
+    let mut input = AudioBuffer::new(2, 1);
+    input.push_frame([0.5, -0.5]).unwrap();
+    let mut output = AudioBuffer::new(6, 1);
+    channel_conversion(&stereo, &surround)
+        .unwrap()
+        .unwrap()
+        .process(&input, &mut output)
+        .unwrap();
+    assert_eq!(
+        output.frame(0).collect::<Vec<f32>>(),
+        vec![0.5, -0.5, 0.0, 0.0, 0.0, 0.0]
+    );
+}
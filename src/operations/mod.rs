@@ -15,4 +15,11 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 \******************************************************************************/
+pub mod concat;
+pub mod mix;
 pub mod normalize;
+pub mod remix;
+pub mod resample;
+pub mod silence;
+pub mod split;
+pub mod trim;
@@ -21,6 +21,7 @@ use crate::analyzer::{
 };
 use crate::effects::amplify::Settings as Amplify;
 use crate::error::Error;
+use crate::frame::ChannelLayout;
 use hound::{WavReader, WavWriter};
 
 #[derive(Clone, Debug, clap::ValueEnum)]
@@ -47,6 +48,9 @@ pub struct Settings {
     /// EBU R128 compliant.
     #[arg(short)]
     strict_ebur128: bool,
+    /// Include the DC offset in the RMS value.
+    #[arg(long)]
+    include_dc: bool,
 }
 
 impl Settings {
@@ -54,6 +58,7 @@ impl Settings {
         &self,
         mut input: &mut WavReader<R>,
         mut output: &mut WavWriter<W>,
+        layout: &ChannelLayout,
     ) -> Result<(), Error>
     where
         R: std::io::Read + std::io::Seek,
@@ -70,19 +75,35 @@ impl Settings {
             Mode::Lufs => {
                 let analyzer =
                     Lufs::new(self.channel_independent, self.strict_ebur128);
-                let loudness = analyzer.analyze(&mut input)?;
+                let loudness = analyzer.analyze(&mut input, layout)?;
                 loudness
                     .iter()
                     .map(|x| {
-                        (10.0_f64.powf(self.target_db / 10.0) / x).sqrt() as f32
+                        // Channels without loudness weight, i.e. LFE,
+                        // are left unchanged.
+                        if *x == 0.0 {
+                            1.0
+                        } else {
+                            (10.0_f64.powf(self.target_db / 10.0) / x).sqrt()
+                                as f32
+                        }
                     })
                     .collect::<Vec<f32>>()
             }
             Mode::Rms => {
-                let analyzer = Rms::new(self.channel_independent);
+                let analyzer =
+                    Rms::new(self.channel_independent, self.include_dc);
                 let rms = analyzer.analyze(&mut input)?;
                 rms.iter()
-                    .map(|x| (10.0_f64.powf(self.target_db / 20.0) / x) as f32)
+                    .map(|x| {
+                        // Silent channels and channels with only a DC
+                        // offset are left unchanged.
+                        if *x == 0.0 {
+                            1.0
+                        } else {
+                            (10.0_f64.powf(self.target_db / 20.0) / x) as f32
+                        }
+                    })
                     .collect::<Vec<f32>>()
             }
         };
@@ -0,0 +1,434 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::frame::{ChannelLayout, ChannelMap, FrameIterator, BLOCK_SIZE};
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+
+#[derive(Clone, Debug, clap::ValueEnum)]
+pub enum Mode {
+    /// Fold down to stereo with ITU-R BS.775 coefficients.
+    /// Mono input is spread to both channels.
+    Stereo,
+    /// Mix down to mono
+    Mono,
+    /// Extract single channels
+    Extract,
+    /// Reorder 5.1 or 7.1 channels from SMPTE to film order
+    SmpteToFilm,
+    /// Reorder 5.1 or 7.1 channels from film to SMPTE order
+    FilmToSmpte,
+    /// Mix with custom matrix
+    Custom,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Remix mode
+    mode: Mode,
+    /// Channels to extract as indices or speaker labels, e.g. "C,LFE".
+    #[arg(short, long, value_delimiter = ',')]
+    channels: Vec<String>,
+    /// Custom mix matrix. One row per output channel separated by ';'
+    /// with one gain per input channel separated by ','.
+    #[arg(short, long)]
+    matrix: Option<String>,
+    /// Speaker labels of the custom matrix output channels, e.g. "L,R".
+    #[arg(short, long, value_delimiter = ',')]
+    layout: Vec<String>,
+}
+
+impl Settings {
+    /// -3 dB gain for center and surround channels
+    const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    /// Reorders SMPTE to film order,
+    /// i.e. L R C LFE Ls Rs (Lb Rb) to L C R Ls Rs (Lb Rb) LFE.
+    const SMPTE_TO_FILM_51: [usize; 6] = [0, 2, 1, 4, 5, 3];
+    const SMPTE_TO_FILM_71: [usize; 8] = [0, 2, 1, 4, 5, 6, 7, 3];
+    /// Reorders film to SMPTE order.
+    const FILM_TO_SMPTE_51: [usize; 6] = [0, 2, 1, 5, 3, 4];
+    const FILM_TO_SMPTE_71: [usize; 8] = [0, 2, 1, 7, 3, 4, 5, 6];
+
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            channels: Vec::new(),
+            matrix: None,
+            layout: Vec::new(),
+        }
+    }
+
+    /// Constructs the mixing matrix for the given input layout.
+    pub fn remixer(&self, layout: &ChannelLayout) -> Result<Remix, Error> {
+        match self.mode {
+            Mode::Stereo => Self::stereo(layout),
+            Mode::Mono => {
+                // Mono is the average of the stereo fold-down.
+                let stereo = Self::stereo(layout)?;
+                let row = stereo.matrix[0]
+                    .iter()
+                    .zip(&stereo.matrix[1])
+                    .map(|(l, r)| 0.5 * (l + r))
+                    .collect();
+                Ok(Remix::new(
+                    vec![row],
+                    ChannelLayout::new(vec![ChannelMap::Center]),
+                ))
+            }
+            Mode::Extract => self.extract(layout),
+            Mode::SmpteToFilm => {
+                let order: &[usize] = match layout.len() {
+                    6 => &Self::SMPTE_TO_FILM_51,
+                    8 => &Self::SMPTE_TO_FILM_71,
+                    _ => {
+                        return Err(Error::InvalidArgument(
+                            "Reordering requires 5.1 or 7.1 input.".into(),
+                        ))
+                    }
+                };
+                let positions =
+                    order.iter().map(|idx| layout.positions()[*idx]).collect();
+                Ok(Remix::new(
+                    Self::reorder(order),
+                    ChannelLayout::new(positions),
+                ))
+            }
+            Mode::FilmToSmpte => {
+                let order: &[usize] = match layout.len() {
+                    6 => &Self::FILM_TO_SMPTE_51,
+                    8 => &Self::FILM_TO_SMPTE_71,
+                    _ => {
+                        return Err(Error::InvalidArgument(
+                            "Reordering requires 5.1 or 7.1 input.".into(),
+                        ))
+                    }
+                };
+                // Film order files carry no usable channel mask, so the
+                // input positions are ignored and the output gets the
+                // default SMPTE positions.
+                Ok(Remix::new(
+                    Self::reorder(order),
+                    ChannelLayout::from_channels(order.len()),
+                ))
+            }
+            Mode::Custom => self.custom(layout),
+        }
+    }
+
+    pub fn remix<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+        layout: &ChannelLayout,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+        let remix = self.remixer(layout)?;
+        if output.spec().channels as usize != remix.layout().len() {
+            return Err(Error::InvalidArgument(
+                "Output channel count does not match remix matrix.".into(),
+            ));
+        }
+
+        let mut progress = Progress::new(duration as usize, "Remixing sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        let mut buffer = AudioBuffer::new(remix.layout().len(), BLOCK_SIZE);
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    remix.process(block, &mut buffer)?;
+                    buffer.write(output)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stereo fold-down according to ITU-R BS.775.
+    fn stereo(layout: &ChannelLayout) -> Result<Remix, Error> {
+        let mut matrix = vec![vec![0.0; layout.len()]; 2];
+        for (i, position) in layout.positions().iter().enumerate() {
+            let (left, right) = match position {
+                ChannelMap::Left | ChannelMap::LeftOfCenter => (1.0, 0.0),
+                ChannelMap::Right | ChannelMap::RightOfCenter => (0.0, 1.0),
+                ChannelMap::Center | ChannelMap::TopFrontCenter => {
+                    (Self::MINUS_3DB, Self::MINUS_3DB)
+                }
+                ChannelMap::RearLeft
+                | ChannelMap::SideLeft
+                | ChannelMap::TopFrontLeft
+                | ChannelMap::TopRearLeft => (Self::MINUS_3DB, 0.0),
+                ChannelMap::RearRight
+                | ChannelMap::SideRight
+                | ChannelMap::TopFrontRight
+                | ChannelMap::TopRearRight => (0.0, Self::MINUS_3DB),
+                ChannelMap::RearCenter
+                | ChannelMap::TopCenter
+                | ChannelMap::TopRearCenter => (0.5, 0.5),
+                // LFE is omitted in the fold-down.
+                ChannelMap::Lfe => (0.0, 0.0),
+                ChannelMap::Unassigned => {
+                    return Err(Error::InvalidArgument(format!(
+                        "Channel {} has no speaker position.",
+                        i
+                    )))
+                }
+            };
+            matrix[0][i] = left;
+            matrix[1][i] = right;
+        }
+
+        Ok(Remix::new(
+            matrix,
+            ChannelLayout::new(vec![ChannelMap::Left, ChannelMap::Right]),
+        ))
+    }
+
+    fn extract(&self, layout: &ChannelLayout) -> Result<Remix, Error> {
+        if self.channels.is_empty() {
+            return Err(Error::InvalidArgument(
+                "No channels to extract were given.".into(),
+            ));
+        }
+
+        let mut matrix = Vec::with_capacity(self.channels.len());
+        let mut positions = Vec::with_capacity(self.channels.len());
+        for channel in &self.channels {
+            let idx = match channel.parse::<usize>() {
+                Ok(idx) => idx,
+                Err(_) => {
+                    let position = channel
+                        .parse::<ChannelMap>()
+                        .map_err(Error::InvalidArgument)?;
+                    layout
+                        .positions()
+                        .iter()
+                        .position(|x| *x == position)
+                        .ok_or_else(|| {
+                            Error::InvalidArgument(format!(
+                                "Input has no {} channel.",
+                                channel
+                            ))
+                        })?
+                }
+            };
+            if idx >= layout.len() {
+                return Err(Error::InvalidArgument(format!(
+                    "Channel index {} is out of range.",
+                    idx
+                )));
+            }
+
+            let mut row = vec![0.0; layout.len()];
+            row[idx] = 1.0;
+            matrix.push(row);
+            positions.push(layout.positions()[idx]);
+        }
+
+        Ok(Remix::new(matrix, ChannelLayout::new(positions)))
+    }
+
+    /// Permutation matrix which moves input channel "order[i]" to output
+    /// channel "i".
+    fn reorder(order: &[usize]) -> Vec<Vec<f32>> {
+        order
+            .iter()
+            .map(|idx| {
+                let mut row = vec![0.0; order.len()];
+                row[*idx] = 1.0;
+                row
+            })
+            .collect()
+    }
+
+    fn custom(&self, layout: &ChannelLayout) -> Result<Remix, Error> {
+        let matrix = match &self.matrix {
+            Some(matrix) => matrix,
+            None => {
+                return Err(Error::InvalidArgument(
+                    "Custom mode requires a matrix.".into(),
+                ))
+            }
+        };
+
+        let matrix = matrix
+            .split(';')
+            .map(|row| {
+                row.split(',')
+                    .map(|x| x.trim().parse::<f32>())
+                    .collect::<Result<Vec<f32>, _>>()
+            })
+            .collect::<Result<Vec<Vec<f32>>, _>>()
+            .map_err(|e| {
+                Error::InvalidArgument(format!("Invalid matrix: {}", e))
+            })?;
+        if matrix.iter().any(|row| row.len() != layout.len()) {
+            return Err(Error::InvalidArgument(
+                "Every matrix row must have one gain per input channel.".into(),
+            ));
+        }
+
+        let output_layout = if self.layout.is_empty() {
+            ChannelLayout::from_channels(matrix.len())
+        } else if self.layout.len() == matrix.len() {
+            ChannelLayout::new(
+                self.layout
+                    .iter()
+                    .map(|x| x.parse::<ChannelMap>())
+                    .collect::<Result<Vec<ChannelMap>, String>>()
+                    .map_err(Error::InvalidArgument)?,
+            )
+        } else {
+            return Err(Error::InvalidArgument(
+                "Layout must have one label per matrix row.".into(),
+            ));
+        };
+
+        Ok(Remix::new(matrix, output_layout))
+    }
+}
+
+/// Channel mixing matrix
+#[derive(Debug, Clone)]
+pub struct Remix {
+    /// Gains, one row per output channel with one gain per input channel.
+    matrix: Vec<Vec<f32>>,
+    /// Speaker layout of the output channels
+    layout: ChannelLayout,
+}
+
+impl Remix {
+    pub fn new(matrix: Vec<Vec<f32>>, layout: ChannelLayout) -> Self {
+        Self { matrix, layout }
+    }
+
+    /// Speaker layout of the output channels
+    pub fn layout(&self) -> &ChannelLayout {
+        &self.layout
+    }
+
+    /// Mixes the input block into the output block.
+    pub fn process(
+        &self,
+        input: &AudioBuffer,
+        output: &mut AudioBuffer,
+    ) -> Result<(), Error> {
+        if output.channel_count() != self.matrix.len()
+            || self.matrix.iter().any(|x| x.len() != input.channel_count())
+        {
+            return Err(Error::InvalidFrame);
+        }
+
+        output.clear();
+        output.resize(input.len());
+        for (channel, row) in output.channels_mut().zip(&self.matrix) {
+            for (input, gain) in input.channels().iter().zip(row) {
+                if *gain == 0.0 {
+                    continue;
+                }
+                for (y, x) in channel.iter_mut().zip(input) {
+                    *y += gain * x;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_remix_presets() {
+    use crate::riff;
+    use hound::{SampleFormat, WavSpec};
+
+    let layout = ChannelLayout::from_channels(6);
+    let mut input = AudioBuffer::new(6, 1);
+    input
+        .push_frame([1.0, 2.0, 3.0, 4.0, 5.0, 6.0].iter().copied())
+        .unwrap();
+
+    let stereo = Settings::new(Mode::Stereo).remixer(&layout).unwrap();
+    let mut output = AudioBuffer::new(2, 1);
+    stereo.process(&input, &mut output).unwrap();
+    let k = std::f32::consts::FRAC_1_SQRT_2;
+    assert_eq!(
+        output.frame(0).collect::<Vec<f32>>(),
+        vec![1.0 + k * 3.0 + k * 5.0, 2.0 + k * 3.0 + k * 6.0]
+    );
+
+    let film = Settings::new(Mode::SmpteToFilm).remixer(&layout).unwrap();
+    let smpte = Settings::new(Mode::FilmToSmpte)
+        .remixer(film.layout())
+        .unwrap();
+    let mut reordered = AudioBuffer::new(6, 1);
+    film.process(&input, &mut reordered).unwrap();
+    assert_eq!(
+        reordered.frame(0).collect::<Vec<f32>>(),
+        vec![1.0, 3.0, 2.0, 5.0, 6.0, 4.0]
+    );
+    let mut restored = AudioBuffer::new(6, 1);
+    smpte.process(&reordered, &mut restored).unwrap();
+    assert_eq!(
+        restored.frame(0).collect::<Vec<f32>>(),
+        vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]
+    );
+    assert_eq!(smpte.layout(), &layout);
+
+    // Film order cannot be stored in a channel mask and is read back
+    // without speaker positions instead of as SMPTE order.
+    let round_trip = |layout: &ChannelLayout| {
+        let spec = WavSpec {
+            channels: 6,
+            sample_rate: 48000,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let mut file = std::io::Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut file, spec).unwrap();
+        reordered.write(&mut writer).unwrap();
+        writer.finalize().unwrap();
+        riff::write_channel_layout(&mut file, layout).unwrap();
+        riff::read_channel_layout(&mut file, 6).unwrap()
+    };
+    let film_layout = round_trip(film.layout());
+    assert!(film_layout
+        .positions()
+        .iter()
+        .all(|x| *x == ChannelMap::Unassigned));
+    assert_eq!(round_trip(smpte.layout()), layout);
+
+    // Reordering a film order file yields SMPTE positions regardless of the
+    // positions read from the file.
+    for input_layout in [film_layout, layout.clone()] {
+        let smpte = Settings::new(Mode::FilmToSmpte)
+            .remixer(&input_layout)
+            .unwrap();
+        assert_eq!(smpte.layout(), &layout);
+        assert_eq!(round_trip(smpte.layout()), layout);
+    }
+}
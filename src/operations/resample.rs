@@ -0,0 +1,407 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::buffer::AudioBuffer;
+use crate::conversion::Conversion;
+use crate::error::Error;
+use crate::filters::fir::{kaiser_beta, kaiser_sinc, kaiser_transition_width};
+use crate::frame::{FrameIterator, BLOCK_SIZE};
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Quality {
+    /// 60 dB stopband attenuation, short filter
+    Fast,
+    /// 90 dB stopband attenuation
+    Medium,
+    /// 120 dB stopband attenuation
+    High,
+    /// 140 dB stopband attenuation, long filter
+    Best,
+}
+
+impl Quality {
+    /// Stopband attenuation in dB.
+    pub fn attenuation_db(self) -> f64 {
+        match self {
+            Self::Fast => 60.0,
+            Self::Medium => 90.0,
+            Self::High => 120.0,
+            Self::Best => 140.0,
+        }
+    }
+
+    /// Number of filter taps per polyphase branch at unity ratio.
+    fn taps(self) -> usize {
+        match self {
+            Self::Fast => 32,
+            Self::Medium => 64,
+            Self::High => 128,
+            Self::Best => 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Output sampling rate in Hz.
+    sample_rate: u32,
+    /// Resampling filter quality.
+    #[arg(short, long, value_enum, default_value_t = Quality::High)]
+    quality: Quality,
+}
+
+impl Settings {
+    /// Output sampling rate in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn resample<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let spec = input.spec();
+        let duration = input.duration();
+        if output.spec().sample_rate != self.sample_rate {
+            return Err(Error::InvalidArgument(
+                "Output sample rate does not match settings.".into(),
+            ));
+        }
+
+        let mut resampler = Resampler::new(
+            spec.sample_rate,
+            self.sample_rate,
+            spec.channels as usize,
+            self.quality,
+        )?;
+        let mut remaining = resampler.output_len(duration as usize);
+
+        let mut progress =
+            Progress::new(duration as usize, "Resampling sample");
+        let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+        let mut buffer = AudioBuffer::new(
+            spec.channels as usize,
+            resampler.output_len(BLOCK_SIZE) + 1,
+        );
+        while let Some(block) = frames.next() {
+            match block {
+                Ok(block) => {
+                    progress.advance(block.len());
+                    resampler.process(block, &mut buffer)?;
+                    buffer.truncate(remaining);
+                    remaining -= buffer.len();
+                    buffer.write(output)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // Drain filter delay line
+        let mut padding =
+            AudioBuffer::new(spec.channels as usize, resampler.latency() + 1);
+        padding.resize(resampler.latency() + 1);
+        let mut buffer = AudioBuffer::new(
+            spec.channels as usize,
+            resampler.output_len(padding.len()) + 1,
+        );
+        resampler.process(&padding, &mut buffer)?;
+        buffer.truncate(remaining);
+        buffer.write(output)?;
+
+        Ok(())
+    }
+}
+
+/// Greatest common divisor
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Polyphase windowed-sinc resampler for rational ratios.
+#[derive(Clone, Debug)]
+pub struct Resampler {
+    /// Upsampling factor
+    up: usize,
+    /// Downsampling factor
+    down: usize,
+    /// Taps per polyphase branch
+    taps: usize,
+    /// Polyphase branch coefficients in reverse order, i.e. the last
+    /// coefficient belongs to the newest sample.
+    phases: Vec<Vec<f64>>,
+    /// Input history, every sample is stored twice so that the
+    /// last "taps" samples are always a contiguous slice.
+    history: Vec<Vec<f64>>,
+    /// Write position in the history buffers
+    write_pos: usize,
+    /// Position of the next output sample relative to the current input
+    /// sample on the upsampled time grid.
+    position: usize,
+}
+
+impl Resampler {
+    pub fn new(
+        fs_in: u32,
+        fs_out: u32,
+        channels: usize,
+        quality: Quality,
+    ) -> Result<Self, Error> {
+        if fs_in == 0 || fs_out == 0 {
+            return Err(Error::InvalidArgument(
+                "Sample rates must be greater than zero.".into(),
+            ));
+        }
+        let divisor = gcd(fs_in as usize, fs_out as usize);
+        let up = fs_out as usize / divisor;
+        let down = fs_in as usize / divisor;
+
+        // Keep the relative transition width constant when downsampling.
+        // An even number of taps results in an integer filter delay.
+        let taps = (quality.taps() * up.max(down) + up - 1) / up;
+        let taps = taps + taps % 2;
+        let prototype = Self::prototype(up, down, taps, quality);
+
+        let phases = (0..up)
+            .map(|p| {
+                (0..taps)
+                    .rev()
+                    .map(|j| *prototype.get(p + j * up).unwrap_or(&0.0))
+                    .collect()
+            })
+            .collect();
+
+        Ok(Self {
+            up,
+            down,
+            taps,
+            phases,
+            history: vec![vec![0.0; 2 * taps]; channels],
+            write_pos: 0,
+            // Start at the filter center to compensate its delay.
+            position: (prototype.len() - 1) / 2,
+        })
+    }
+
+    /// Designs the lowpass prototype filter on the upsampled time grid.
+    pub fn prototype(
+        up: usize,
+        down: usize,
+        taps: usize,
+        quality: Quality,
+    ) -> Vec<f64> {
+        let len = up * taps - 1;
+        // Kaiser's design formulas slightly underestimate the required
+        // filter order for high attenuations, add some margin.
+        let attenuation_db = quality.attenuation_db() + 6.0;
+        // The stopband starts at the Nyquist frequency of the lower rate.
+        let stopband = 0.5 / up.max(down) as f64;
+        let transition = kaiser_transition_width(attenuation_db, len);
+        let cutoff = stopband - transition / 2.0;
+
+        kaiser_sinc(cutoff, len, kaiser_beta(attenuation_db))
+            .into_iter()
+            .map(|x| x * up as f64)
+            .collect()
+    }
+
+    /// Number of output frames for the given number of input frames.
+    pub fn output_len(&self, input_len: usize) -> usize {
+        (input_len * self.up + self.down - 1) / self.down
+    }
+
+    /// Filter delay in input frames.
+    pub fn latency(&self) -> usize {
+        self.taps / 2
+    }
+
+    /// Resamples one block of frames. The output buffer must be able to
+    /// hold "output_len(input.len()) + 1" frames.
+    pub fn process(
+        &mut self,
+        input: &AudioBuffer,
+        output: &mut AudioBuffer,
+    ) -> Result<(), Error> {
+        if input.channel_count() != self.history.len()
+            || output.channel_count() != self.history.len()
+        {
+            return Err(Error::InvalidFrame);
+        }
+
+        // Number of output samples within this block
+        let len = input.len();
+        let count = if self.position >= len * self.up {
+            0
+        } else {
+            (len * self.up - self.position + self.down - 1) / self.down
+        };
+        output.clear();
+        output.resize(count);
+
+        for ((samples, history), channel) in input
+            .channels()
+            .iter()
+            .zip(self.history.iter_mut())
+            .zip(output.channels_mut())
+        {
+            let mut write_pos = self.write_pos;
+            let mut position = self.position;
+            let mut channel = channel.iter_mut();
+
+            for x in samples {
+                history[write_pos] = *x as f64;
+                history[write_pos + self.taps] = *x as f64;
+                write_pos = (write_pos + 1) % self.taps;
+
+                let window = &history[write_pos..write_pos + self.taps];
+                while position < self.up {
+                    if let Some(y) = channel.next() {
+                        *y = self.phases[position]
+                            .iter()
+                            .zip(window)
+                            .fold(0.0, |acc, (b, x)| acc + b * x)
+                            as f32;
+                    }
+                    position += self.down;
+                }
+                position -= self.up;
+            }
+        }
+        self.write_pos = (self.write_pos + len) % self.taps;
+        self.position = self.position + count * self.down - len * self.up;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+/// Magnitude response of a filter in dB at frequency "f" relative to the
+/// sampling frequency.
+fn response_db(b: &[f64], f: f64) -> f64 {
+    let (mut re, mut im) = (0.0, 0.0);
+    for (n, x) in b.iter().enumerate() {
+        let phi = 2.0 * std::f64::consts::PI * f * n as f64;
+        re += x * phi.cos();
+        im -= x * phi.sin();
+    }
+    10.0 * (re * re + im * im).log10()
+}
+
+#[test]
+fn test_resampler_filter() {
+    for (fs_in, fs_out) in [(44100, 48000), (48000, 44100), (48000, 96000)] {
+        for quality in
+            [Quality::Fast, Quality::Medium, Quality::High, Quality::Best]
+        {
+            let resampler = Resampler::new(fs_in, fs_out, 1, quality).unwrap();
+            let (up, down) = (resampler.up, resampler.down);
+            let taps = 2 * resampler.latency();
+            let b: Vec<f64> = Resampler::prototype(up, down, taps, quality)
+                .iter()
+                .map(|x| x / up as f64)
+                .collect();
+
+            let attenuation_db = quality.attenuation_db();
+            let stopband = 0.5 / up.max(down) as f64;
+            let passband = stopband
+                - kaiser_transition_width(attenuation_db + 6.0, b.len());
+            // Usable bandwidth relative to the lower Nyquist frequency
+            assert!(passband / stopband > 0.7);
+
+            let ripple = (0..=50)
+                .map(|i| response_db(&b, passband * i as f64 / 50.0).abs())
+                .fold(0.0, f64::max);
+            // Dense grid near the stopband edge where the largest side lobes
+            // are located, coarse grid for the remaining stopband.
+            let step = 1.0 / b.len() as f64;
+            let stopband_db =
+                (0..200)
+                    .map(|i| (stopband + step * i as f64 / 4.0).min(0.5))
+                    .chain((1..100).map(|i| {
+                        stopband + (0.5 - stopband) * i as f64 / 100.0
+                    }))
+                    .map(|f| -response_db(&b, f))
+                    .fold(f64::INFINITY, f64::min);
+            let delta = 10.0_f64.powf(-attenuation_db / 20.0);
+            assert!(stopband_db > attenuation_db - 1.0);
+            assert!(ripple < 2.0 * 20.0 * (1.0 + delta).log10());
+        }
+    }
+}
+
+#[test]
+fn test_resample_sine() {
+    use hound::{SampleFormat, WavSpec};
+
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: 44100,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let omega = 2.0 * std::f64::consts::PI * 1000.0;
+    let mut input = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut input, spec).unwrap();
+    for i in 0..10000 {
+        let t = i as f64 / 44100.0;
+        writer
+            .write_sample((0.5 * (omega * t).sin()) as f32)
+            .unwrap();
+    }
+    writer.finalize().unwrap();
+    input.set_position(0);
+
+    let mut output = std::io::Cursor::new(Vec::new());
+    let mut reader = WavReader::new(&mut input).unwrap();
+    let mut writer = WavWriter::new(
+        &mut output,
+        WavSpec {
+            sample_rate: 48000,
+            ..spec
+        },
+    )
+    .unwrap();
+    let settings = Settings {
+        sample_rate: 48000,
+        quality: Quality::High,
+    };
+    settings.resample(&mut reader, &mut writer).unwrap();
+    writer.finalize().unwrap();
+    output.set_position(0);
+
+    let samples: Vec<f32> = WavReader::new(&mut output)
+        .unwrap()
+        .samples::<f32>()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(samples.len(), 10885);
+    // Skip the filter transients at both ends.
+    for (i, x) in samples.iter().enumerate().take(10000).skip(1000) {
+        let t = i as f64 / 48000.0;
+        assert!((*x as f64 - 0.5 * (omega * t).sin()).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,179 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::trim::copy_region;
+use crate::analyzer::silence;
+use crate::effects::fade::FadeShape;
+use crate::error::Error;
+use crate::frame::ChannelLayout;
+use crate::progress::Progress;
+use hound::{WavReader, WavWriter};
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    #[command(flatten)]
+    detection: silence::Settings,
+    /// Remove silence at the beginning and the end.
+    #[arg(short, long)]
+    strip_edges: bool,
+    /// Shorten inner pauses to this length in seconds.
+    #[arg(short = 'p', long)]
+    max_pause: Option<f64>,
+}
+
+impl Settings {
+    pub fn strip<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+        layout: &ChannelLayout,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        if !self.strip_edges && self.max_pause.is_none() {
+            return Err(Error::InvalidArgument(
+                "Either strip edges or a maximum pause length must be given."
+                    .into(),
+            ));
+        }
+        let max_pause = match self.max_pause {
+            Some(x) if x < 0.0 => {
+                return Err(Error::InvalidArgument(
+                    "Maximum pause length must not be negative.".into(),
+                ))
+            }
+            Some(x) => {
+                Some((x * input.spec().sample_rate as f64).round() as usize)
+            }
+            None => None,
+        };
+        let duration = input.duration() as usize;
+
+        // The first pass detects silence, the second one copies
+        // the remaining regions.
+        let silent = self.detection.analyze(input, layout)?;
+        let regions = self.keep_regions(&silent, duration, max_pause);
+
+        let mut progress = Progress::new(
+            regions.iter().map(|(start, end)| end - start).sum(),
+            "Stripping sample",
+        );
+        for region in regions {
+            copy_region(
+                input,
+                output,
+                region,
+                (0, 0),
+                FadeShape::Linear,
+                &mut progress,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Calculates the regions to keep from the silent regions.
+    fn keep_regions(
+        &self,
+        silent: &[(usize, usize)],
+        duration: usize,
+        max_pause: Option<usize>,
+    ) -> Vec<(usize, usize)> {
+        let mut regions = Vec::new();
+        let mut pos = 0;
+        for (start, end) in silent.iter().copied() {
+            let cut = if start == 0 || end >= duration {
+                if self.strip_edges {
+                    (start, end)
+                } else {
+                    continue;
+                }
+            } else {
+                match max_pause {
+                    // Keep the borders of the pause so that the cut is
+                    // in the middle of the silence.
+                    Some(max) if end - start > max => {
+                        (start + max / 2, end - (max - max / 2))
+                    }
+                    _ => continue,
+                }
+            };
+            regions.push((pos, cut.0));
+            pos = cut.1;
+        }
+        regions.push((pos, duration));
+        regions.retain(|(start, end)| start < end);
+
+        regions
+    }
+}
+
+#[test]
+fn test_strip_silence() {
+    use clap::Parser;
+    use hound::{SampleFormat, WavSpec};
+
+    #[derive(Parser)]
+    struct Args {
+        #[command(flatten)]
+        settings: Settings,
+    }
+
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: 1000,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut input = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut input, spec).unwrap();
+    for i in 0..3000 {
+        let phi = 2.0 * std::f32::consts::PI * 100.0 * i as f32 / 1000.0;
+        let x = match i {
+            500..=999 | 2000..=2499 => 0.5 * phi.cos(),
+            _ => 0.0,
+        };
+        writer.write_sample(x).unwrap();
+    }
+    writer.finalize().unwrap();
+    input.set_position(0);
+
+    let mut output = std::io::Cursor::new(Vec::new());
+    let mut reader = WavReader::new(&mut input).unwrap();
+    let mut writer = WavWriter::new(&mut output, spec).unwrap();
+    let args = Args::parse_from([
+        "strip", "-t", "-40", "-m", "0.1", "-w", "0.01", "-s", "-p", "0.2",
+    ]);
+    args.settings
+        .strip(&mut reader, &mut writer, &ChannelLayout::from_channels(1))
+        .unwrap();
+    writer.finalize().unwrap();
+    output.set_position(0);
+
+    let samples: Vec<f32> = WavReader::new(&mut output)
+        .unwrap()
+        .samples::<f32>()
+        .map(|x| x.unwrap())
+        .collect();
+    assert_eq!(samples.len(), 1200);
+    assert_eq!(samples[0], 0.5);
+    assert!(samples[500..700].iter().all(|x| *x == 0.0));
+    assert_eq!(samples[700], 0.5);
+    assert!(samples[1199] != 0.0);
+}
@@ -0,0 +1,212 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use super::trim::copy_region;
+use crate::analyzer::silence;
+use crate::effects::fade::FadeShape;
+use crate::error::Error;
+use crate::frame::ChannelLayout;
+use crate::progress::Progress;
+use crate::riff;
+use crate::time::Timestamp;
+use hound::{WavReader, WavWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Source {
+    /// Split in the middle of detected silent regions.
+    Silence,
+    /// Split at the cue points of the input file.
+    Cue,
+    /// Split at the timestamps of a list file.
+    List,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Source of the split points
+    #[arg(value_enum)]
+    source: Source,
+    #[command(flatten)]
+    detection: silence::Settings,
+    /// File with one timestamp per line as "48000smp", seconds or
+    /// "hh:mm:ss.ms". Empty lines and lines starting with '#' are ignored.
+    #[arg(short, long)]
+    list_file: Option<String>,
+}
+
+impl Settings {
+    /// Source of the split points
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    /// Splits the input into files named after "template". The template
+    /// must contain "{n}" or "{n:W}" which is replaced by the number of the
+    /// part, zero padded to "W" digits. "cue_points" are the cue points of
+    /// the input file, they are only required for the cue source. Returns
+    /// the output filenames.
+    pub fn split<R>(
+        &self,
+        input: &mut WavReader<R>,
+        layout: &ChannelLayout,
+        cue_points: &[usize],
+        template: &str,
+    ) -> Result<Vec<String>, Error>
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        // Check the template before the potentially slow analysis.
+        format_filename(template, 1)?;
+
+        let spec = input.spec();
+        let fs = spec.sample_rate as f64;
+        let duration = input.duration() as usize;
+
+        let points = match self.source {
+            Source::Silence => {
+                let regions = self.detection.analyze(input, layout)?;
+                silence_points(&regions, duration)
+            }
+            Source::Cue => cue_points.to_vec(),
+            Source::List => {
+                let filename = match &self.list_file {
+                    Some(x) => x,
+                    None => {
+                        return Err(Error::InvalidArgument(
+                            "Splitting at a list requires a list file.".into(),
+                        ))
+                    }
+                };
+                read_timestamps(&std::fs::read_to_string(filename)?)?
+                    .iter()
+                    .map(|x| x.frames(fs))
+                    .collect()
+            }
+        };
+        let parts = parts(points, duration);
+
+        let mut progress = Progress::new(duration, "Splitting sample");
+        let mut filenames = Vec::with_capacity(parts.len());
+        for (idx, part) in parts.into_iter().enumerate() {
+            let filename = format_filename(template, idx + 1)?;
+            let mut output = WavWriter::create(&filename, spec)?;
+            copy_region(
+                input,
+                &mut output,
+                part,
+                (0, 0),
+                FadeShape::Linear,
+                &mut progress,
+            )?;
+            output.finalize()?;
+            let mut file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&filename)?;
+            riff::write_channel_layout(&mut file, layout)?;
+            filenames.push(filename);
+        }
+
+        Ok(filenames)
+    }
+}
+
+/// Calculates split points in the middle of inner silent regions. Silence
+/// at the beginning and the end stays part of the first and last region.
+fn silence_points(regions: &[(usize, usize)], duration: usize) -> Vec<usize> {
+    regions
+        .iter()
+        .filter(|(start, end)| *start > 0 && *end < duration)
+        .map(|(start, end)| start + (end - start) / 2)
+        .collect()
+}
+
+/// Calculates the regions between the split points.
+fn parts(mut points: Vec<usize>, duration: usize) -> Vec<(usize, usize)> {
+    points.retain(|x| *x > 0 && *x < duration);
+    points.sort_unstable();
+    points.dedup();
+
+    std::iter::once(0)
+        .chain(points.iter().copied())
+        .zip(points.iter().copied().chain(std::iter::once(duration)))
+        .filter(|(start, end)| start < end)
+        .collect()
+}
+
+/// Parses timestamps from the content of a list file.
+fn read_timestamps(content: &str) -> Result<Vec<Timestamp>, Error> {
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.parse().map_err(Error::InvalidArgument))
+        .collect()
+}
+
+/// Replaces "{n}" or "{n:W}" in the template with the zero padded number.
+fn format_filename(template: &str, n: usize) -> Result<String, Error> {
+    let error = || {
+        Error::InvalidArgument(format!(
+            "Filename template '{}' must contain '{{n}}' or '{{n:W}}'.",
+            template
+        ))
+    };
+
+    let start = template.find("{n").ok_or_else(error)?;
+    let len = template[start..].find('}').ok_or_else(error)? + 1;
+    let width = match &template[start + 2..start + len - 1] {
+        "" => 0,
+        x => match x.strip_prefix(':').map(|x| x.parse::<usize>()) {
+            Some(Ok(width)) => width,
+            _ => return Err(error()),
+        },
+    };
+
+    Ok(format!(
+        "{}{:0width$}{}",
+        &template[..start],
+        n,
+        &template[start + len..],
+        width = width
+    ))
+}
+
+#[test]
+fn test_split_parts() {
+    assert_eq!(parts(vec![], 100), vec![(0, 100)]);
+    assert_eq!(
+        parts(vec![70, 0, 30, 30, 100, 120], 100),
+        vec![(0, 30), (30, 70), (70, 100)]
+    );
+
+    // Leading and trailing silence is not split into separate parts.
+    let points = silence_points(&[(0, 10), (40, 60), (90, 100)], 100);
+    assert_eq!(points, vec![50]);
+    assert_eq!(parts(points, 100), vec![(0, 50), (50, 100)]);
+
+    let timestamps = read_timestamps("# chapters\n0:01.5\n\n30smp\n").unwrap();
+    assert_eq!(
+        timestamps,
+        vec![Timestamp::Seconds(1.5), Timestamp::Samples(30)]
+    );
+
+    assert_eq!(format_filename("part_{n}.wav", 7).unwrap(), "part_7.wav");
+    assert_eq!(format_filename("{n:03}-a.wav", 7).unwrap(), "007-a.wav");
+    assert!(format_filename("part.wav", 7).is_err());
+    assert!(format_filename("part_{n:x}.wav", 7).is_err());
+}
@@ -0,0 +1,227 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::conversion::Conversion;
+use crate::effects::fade::{Envelope, FadeShape};
+use crate::error::Error;
+use crate::frame::FrameIterator;
+use crate::progress::Progress;
+use crate::time::Timestamp;
+use hound::{WavReader, WavWriter};
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Start of the region as "48000smp", seconds or "hh:mm:ss.ms".
+    /// Defaults to the beginning of the input.
+    #[arg(short, long)]
+    start: Option<Timestamp>,
+    /// End of the region as "48000smp", seconds or "hh:mm:ss.ms".
+    /// Defaults to the end of the input.
+    #[arg(short, long)]
+    end: Option<Timestamp>,
+    /// Remove the region instead of keeping it.
+    #[arg(short, long)]
+    remove: bool,
+    /// Length of the fades at the cut points in seconds.
+    #[arg(short, long, default_value_t = 0.0)]
+    fade: f64,
+    /// Shape of the fades at the cut points.
+    #[arg(long, value_enum, default_value_t = FadeShape::Linear)]
+    shape: FadeShape,
+}
+
+impl Settings {
+    pub fn trim<R, W>(
+        &self,
+        input: &mut WavReader<R>,
+        output: &mut WavWriter<W>,
+    ) -> Result<(), Error>
+    where
+        R: std::io::Read + std::io::Seek,
+        W: std::io::Write + std::io::Seek,
+    {
+        let fs = input.spec().sample_rate as f64;
+        let duration = input.duration() as usize;
+
+        let start = self.start.map(|x| x.frames(fs)).unwrap_or(0);
+        let end = self.end.map(|x| x.frames(fs)).unwrap_or(duration);
+        if start > end {
+            return Err(Error::InvalidArgument(
+                "Start must not be after end.".into(),
+            ));
+        }
+        if self.fade < 0.0 {
+            return Err(Error::InvalidArgument(
+                "Fade length must not be negative.".into(),
+            ));
+        }
+        let start = start.min(duration);
+        let end = end.min(duration);
+        let fade = (self.fade * fs).round() as usize;
+        // Only cut points inside the input are faded.
+        let fade_start = if start > 0 { fade } else { 0 };
+        let fade_end = if end < duration { fade } else { 0 };
+
+        if self.remove {
+            let mut progress =
+                Progress::new(duration - (end - start), "Trimming sample");
+            copy_region(
+                input,
+                output,
+                (0, start),
+                (0, fade_start),
+                self.shape,
+                &mut progress,
+            )?;
+            copy_region(
+                input,
+                output,
+                (end, duration),
+                (fade_end, 0),
+                self.shape,
+                &mut progress,
+            )
+        } else {
+            let mut progress = Progress::new(end - start, "Trimming sample");
+            copy_region(
+                input,
+                output,
+                (start, end),
+                (fade_start, fade_end),
+                self.shape,
+                &mut progress,
+            )
+        }
+    }
+}
+
+/// Copies the frames of the region "(begin, end)" with fade-in and
+/// fade-out lengths "fades" in frames. Frames before the region are
+/// skipped by seeking.
+pub fn copy_region<R, W>(
+    input: &mut WavReader<R>,
+    output: &mut WavWriter<W>,
+    (begin, end): (usize, usize),
+    (fade_in, fade_out): (usize, usize),
+    shape: FadeShape,
+    progress: &mut Progress,
+) -> Result<(), Error>
+where
+    R: std::io::Read + std::io::Seek,
+    W: std::io::Write + std::io::Seek,
+{
+    if begin >= end {
+        return Ok(());
+    }
+
+    let spec = input.spec();
+    let mut remaining = end - begin;
+    let mut envelope = Envelope::from_fades(
+        spec.sample_rate as f64,
+        remaining,
+        fade_in,
+        fade_out,
+        shape,
+    );
+
+    input.seek(begin as u32)?;
+    let mut frames = FrameIterator::new(input.samples_f32(), spec.channels);
+    while let Some(block) = frames.next() {
+        match block {
+            Ok(block) => {
+                block.truncate(remaining);
+                progress.advance(block.len());
+                envelope.process(block);
+                block.write(output)?;
+                remaining -= block.len();
+                if remaining == 0 {
+                    break;
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_trim() {
+    use hound::{SampleFormat, WavSpec};
+
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate: 1000,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut input = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut input, spec).unwrap();
+    for i in 0..10000 {
+        writer.write_sample(i as f32).unwrap();
+        writer.write_sample(-(i as f32)).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let mut trim = |settings: Settings| {
+        input.set_position(0);
+        let mut output = std::io::Cursor::new(Vec::new());
+        let mut reader = WavReader::new(&mut input).unwrap();
+        let mut writer = WavWriter::new(&mut output, spec).unwrap();
+        settings.trim(&mut reader, &mut writer).unwrap();
+        writer.finalize().unwrap();
+        output.set_position(0);
+        WavReader::new(&mut output)
+            .unwrap()
+            .samples::<f32>()
+            .map(|x| x.unwrap())
+            .collect::<Vec<f32>>()
+    };
+    let settings = Settings {
+        start: Some("1.5".parse().unwrap()),
+        end: Some("4000smp".parse().unwrap()),
+        remove: false,
+        fade: 0.0,
+        shape: FadeShape::Linear,
+    };
+
+    let samples = trim(settings.clone());
+    assert_eq!(samples.len(), 2 * 2500);
+    for (i, x) in samples.chunks(2).enumerate() {
+        assert_eq!(x, [(1500 + i) as f32, -((1500 + i) as f32)]);
+    }
+
+    let samples = trim(Settings {
+        remove: true,
+        ..settings.clone()
+    });
+    assert_eq!(samples.len(), 2 * 7500);
+    assert_eq!(samples[2 * 1499], 1499.0);
+    assert_eq!(samples[2 * 1500], 4000.0);
+    assert_eq!(samples[2 * 7499], 9999.0);
+
+    let samples = trim(Settings {
+        end: None,
+        fade: 0.01,
+        ..settings
+    });
+    assert_eq!(samples.len(), 2 * 8500);
+    assert_eq!(samples[0], 0.0);
+    assert_eq!(samples[2 * 5], 0.5 * 1505.0);
+    assert_eq!(samples[2 * 10], 1510.0);
+    assert_eq!(samples[2 * 8499], 9999.0);
+}
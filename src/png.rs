@@ -0,0 +1,155 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::error::Error;
+use std::io::Write;
+
+/// PNG file signature
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+/// Maximum length of a stored deflate block
+const MAX_STORED_LEN: usize = 0xFFFF;
+
+/// Writes an 8 bit RGB image as PNG. The image data is stored in
+/// uncompressed deflate blocks which every PNG decoder supports.
+/// "pixels" contains the rows from top to bottom with 3 bytes per pixel.
+pub fn write_rgb<W>(
+    output: &mut W,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+) -> Result<(), Error>
+where
+    W: Write,
+{
+    if width == 0 || height == 0 || pixels.len() != 3 * width * height {
+        return Err(Error::InvalidArgument(
+            "Image size does not match pixel data.".into(),
+        ));
+    }
+
+    output.write_all(&SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bit truecolor, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(output, b"IHDR", &header)?;
+
+    // Every row starts with filter type 0 (none).
+    let mut raw = Vec::with_capacity(pixels.len() + height);
+    for row in pixels.chunks(3 * width) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(output, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(output, b"IEND", &[])?;
+
+    Ok(())
+}
+
+fn write_chunk<W>(
+    output: &mut W,
+    id: &[u8; 4],
+    data: &[u8],
+) -> Result<(), Error>
+where
+    W: Write,
+{
+    output.write_all(&(data.len() as u32).to_be_bytes())?;
+    output.write_all(id)?;
+    output.write_all(data)?;
+    let crc = crc32(crc32(0, id), data);
+    output.write_all(&crc.to_be_bytes())?;
+    Ok(())
+}
+
+/// Wraps the data into a zlib stream of stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = (data.len() + MAX_STORED_LEN - 1) / MAX_STORED_LEN;
+    let mut stream = Vec::with_capacity(data.len() + 5 * blocks.max(1) + 6);
+    // Deflate with 32k window, no preset dictionary, check bits for
+    // the header checksum.
+    stream.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_LEN).peekable();
+    if chunks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        stream.push(last as u8);
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(chunk);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+/// Continues the CRC-32 checksum "crc" over "data".
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Adler-32 checksum of the zlib stream
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1, 0);
+    // Sums of up to 5552 bytes cannot overflow before the modulo.
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[test]
+fn test_png() {
+    assert_eq!(crc32(0, b"IEND"), 0xAE42_6082);
+    assert_eq!(crc32(crc32(0, b"IE"), b"ND"), 0xAE42_6082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+
+    let data: Vec<u8> = (0..100_000).map(|x| (x % 251) as u8).collect();
+    let stream = zlib_stored(&data);
+    assert_eq!(stream.len(), 2 + 2 * 5 + data.len() + 4);
+    // The header is a multiple of 31.
+    assert_eq!(u16::from_be_bytes([stream[0], stream[1]]) % 31, 0);
+    assert_eq!(stream[2], 0);
+    assert_eq!(stream[2 + 5 + MAX_STORED_LEN], 1);
+
+    let mut png = Vec::new();
+    write_rgb(&mut png, 2, 1, &[255, 0, 0, 0, 0, 255]).unwrap();
+    assert_eq!(png[..8], SIGNATURE);
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    assert!(write_rgb(&mut png, 2, 2, &[0; 6]).is_err());
+}
@@ -31,14 +31,16 @@ impl Progress {
         Self {
             count: 0,
             total_count,
-            update_every: total_count / 100,
+            update_every: (total_count / 100).max(1),
             message: message.into(),
         }
     }
 
-    pub fn next(&mut self) {
-        self.count += 1;
-        if self.count % self.update_every == 0 {
+    /// Advances the progress by "count" steps at once.
+    pub fn advance(&mut self, count: usize) {
+        let previous = self.count / self.update_every;
+        self.count += count;
+        if self.count / self.update_every != previous {
             eprint!("\r{}: {}/{}", self.message, self.count, self.total_count);
         }
     }
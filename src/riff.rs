@@ -0,0 +1,245 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::error::Error;
+use crate::frame::ChannelLayout;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// WAVE_FORMAT_EXTENSIBLE format tag
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// Offset of the channel mask within a WAVE_FORMAT_EXTENSIBLE fmt chunk
+const CHANNEL_MASK_OFFSET: u64 = 20;
+/// Length of a cue point within the cue chunk
+const CUE_POINT_LEN: usize = 24;
+
+/// Searches the chunk with the given ID and returns the offset of its
+/// data and its length.
+fn find_chunk<R>(
+    reader: &mut R,
+    id: &[u8; 4],
+) -> Result<Option<(u64, u32)>, Error>
+where
+    R: Read + Seek,
+{
+    let mut header = [0; 12];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(Error::InvalidArgument("Input is not a wave file.".into()));
+    }
+
+    let mut chunk_header = [0; 8];
+    loop {
+        match reader.read_exact(&mut chunk_header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_le_bytes([
+            chunk_header[4],
+            chunk_header[5],
+            chunk_header[6],
+            chunk_header[7],
+        ]);
+        if &chunk_header[0..4] == id {
+            return Ok(Some((reader.stream_position()?, len)));
+        }
+        // Chunks are padded to an even number of bytes.
+        reader.seek(SeekFrom::Current(len as i64 + (len & 1) as i64))?;
+    }
+}
+
+/// Reads the speaker layout of a wave file. Files without
+/// WAVE_FORMAT_EXTENSIBLE header get a default layout. A channel mask of 0
+/// assigns no speaker positions.
+pub fn read_channel_layout<R>(
+    reader: &mut R,
+    channels: u16,
+) -> Result<ChannelLayout, Error>
+where
+    R: Read + Seek,
+{
+    let default = ChannelLayout::from_channels(channels as usize);
+    let (offset, len) = match find_chunk(reader, b"fmt ")? {
+        Some(x) => x,
+        None => return Ok(default),
+    };
+    if (len as u64) < CHANNEL_MASK_OFFSET + 4 {
+        return Ok(default);
+    }
+
+    let mut fmt = [0; (CHANNEL_MASK_OFFSET + 4) as usize];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut fmt)?;
+    if u16::from_le_bytes([fmt[0], fmt[1]]) != FORMAT_EXTENSIBLE {
+        return Ok(default);
+    }
+
+    let mask = u32::from_le_bytes([fmt[20], fmt[21], fmt[22], fmt[23]]);
+    Ok(ChannelLayout::from_mask(mask, channels as usize))
+}
+
+/// Writes the speaker layout to the channel mask of a finalized wave file.
+/// Files without WAVE_FORMAT_EXTENSIBLE header, i.e. files with up to two
+/// channels and 16 bits, cannot store a layout and are left unchanged.
+/// Layouts which are not in channel mask order are written as mask 0
+/// without speaker positions.
+pub fn write_channel_layout<F>(
+    file: &mut F,
+    layout: &ChannelLayout,
+) -> Result<(), Error>
+where
+    F: Read + Write + Seek,
+{
+    let (offset, len) = match find_chunk(file, b"fmt ")? {
+        Some(x) => x,
+        None => {
+            return Err(Error::InvalidArgument("Missing fmt chunk.".into()))
+        }
+    };
+    if (len as u64) < CHANNEL_MASK_OFFSET + 4 {
+        return Ok(());
+    }
+
+    let mut tag = [0; 2];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut tag)?;
+    if u16::from_le_bytes(tag) != FORMAT_EXTENSIBLE {
+        return Ok(());
+    }
+
+    file.seek(SeekFrom::Start(offset + CHANNEL_MASK_OFFSET))?;
+    file.write_all(&layout.mask().to_le_bytes())?;
+    Ok(())
+}
+
+/// Reads the sample positions of all cue points of a wave file in
+/// ascending order. Files without cue chunk have no cue points.
+pub fn read_cue_points<R>(reader: &mut R) -> Result<Vec<usize>, Error>
+where
+    R: Read + Seek,
+{
+    let (offset, len) = match find_chunk(reader, b"cue ")? {
+        Some(x) => x,
+        None => return Ok(Vec::new()),
+    };
+
+    if len < 4 {
+        return Err(Error::InvalidArgument("Invalid cue chunk.".into()));
+    }
+    let mut count = [0; 4];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut count)?;
+    let count = u32::from_le_bytes(count) as u64;
+
+    // Validate the untrusted lengths before allocating.
+    let size = reader.seek(SeekFrom::End(0))?;
+    let data_len = count * CUE_POINT_LEN as u64;
+    if data_len > len as u64 - 4 || offset + 4 + data_len > size {
+        return Err(Error::InvalidArgument("Truncated cue chunk.".into()));
+    }
+    let mut data = vec![0; data_len as usize];
+    reader.seek(SeekFrom::Start(offset + 4))?;
+    reader.read_exact(&mut data)?;
+
+    // Each cue point consists of ID, play order position, data chunk ID,
+    // chunk start, block start and sample offset.
+    let mut points = data
+        .chunks_exact(CUE_POINT_LEN)
+        .map(|x| u32::from_le_bytes([x[20], x[21], x[22], x[23]]) as usize)
+        .collect::<Vec<usize>>();
+    points.sort_unstable();
+    Ok(points)
+}
+
+#[test]
+fn test_channel_layout_round_trip() {
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    let spec = WavSpec {
+        channels: 6,
+        sample_rate: 48000,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut file = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut file, spec).unwrap();
+    for _ in 0..12 {
+        writer.write_sample(0.0_f32).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    // hound writes a default mask which is interpreted as 5.1
+    let layout = read_channel_layout(&mut file, 6).unwrap();
+    assert_eq!(layout, ChannelLayout::from_channels(6));
+
+    let side = ChannelLayout::from_mask(0x60F, 6);
+    write_channel_layout(&mut file, &side).unwrap();
+    assert_eq!(read_channel_layout(&mut file, 6).unwrap(), side);
+}
+
+#[test]
+fn test_read_cue_points() {
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: 48000,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut file = std::io::Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut file, spec).unwrap();
+    for _ in 0..10 {
+        writer.write_sample(0_i16).unwrap();
+    }
+    writer.finalize().unwrap();
+    assert!(read_cue_points(&mut file).unwrap().is_empty());
+
+    // Append a cue chunk with two unsorted cue points.
+    let mut chunk = b"cue ".to_vec();
+    chunk.extend_from_slice(&(4 + 2 * CUE_POINT_LEN as u32).to_le_bytes());
+    chunk.extend_from_slice(&2_u32.to_le_bytes());
+    for (id, position) in [(1_u32, 7_u32), (2, 3)] {
+        chunk.extend_from_slice(&id.to_le_bytes());
+        chunk.extend_from_slice(&0_u32.to_le_bytes());
+        chunk.extend_from_slice(b"data");
+        chunk.extend_from_slice(&[0; 8]);
+        chunk.extend_from_slice(&position.to_le_bytes());
+    }
+    let mut data = file.into_inner();
+    data.extend_from_slice(&chunk);
+    let riff_len = (data.len() - 8) as u32;
+    data[4..8].copy_from_slice(&riff_len.to_le_bytes());
+
+    let mut file = std::io::Cursor::new(data.clone());
+    assert_eq!(read_cue_points(&mut file).unwrap(), vec![3, 7]);
+
+    // Lengths beyond the chunk or the file are rejected.
+    let count_offset = data.len() - chunk.len() + 8;
+    for (count, len) in [(3_u32, 4 + 2 * CUE_POINT_LEN as u32), (3, u32::MAX)] {
+        let mut data = data.clone();
+        data[count_offset..count_offset + 4]
+            .copy_from_slice(&count.to_le_bytes());
+        data[count_offset - 4..count_offset]
+            .copy_from_slice(&len.to_le_bytes());
+        let mut file = std::io::Cursor::new(data);
+        assert!(read_cue_points(&mut file).is_err());
+    }
+}
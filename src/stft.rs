@@ -0,0 +1,245 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+use crate::error::Error;
+use crate::fft::{Complex, Fft, Window};
+
+/// Short-time Fourier transform of one channel.
+#[derive(Debug, Clone)]
+pub struct Stft {
+    fft: Fft,
+    /// Window coefficients
+    window: Vec<f64>,
+    /// Samples between consecutive frames
+    hop: usize,
+    /// Samples which were not analyzed yet
+    pending: Vec<f64>,
+    /// Number of pending samples which are part of an analyzed frame.
+    covered: usize,
+    /// Number of analyzed frames
+    frames: usize,
+    /// FFT working buffers
+    windowed: Vec<f64>,
+    spectrum: Vec<Complex>,
+}
+
+impl Stft {
+    pub fn new(
+        fft_size: usize,
+        hop: usize,
+        window: Window,
+    ) -> Result<Self, Error> {
+        let fft = Fft::new(fft_size)?;
+        if hop == 0 || hop > fft_size {
+            return Err(Error::InvalidArgument(
+                "Hop size must be between 1 and the FFT size.".into(),
+            ));
+        }
+
+        Ok(Self {
+            fft,
+            window: window.coefficients(fft_size),
+            hop,
+            pending: Vec::with_capacity(2 * fft_size),
+            covered: 0,
+            frames: 0,
+            windowed: Vec::with_capacity(fft_size),
+            spectrum: Vec::with_capacity(fft_size),
+        })
+    }
+
+    /// Window coefficients
+    pub fn window(&self) -> &[f64] {
+        &self.window
+    }
+
+    /// Number of analyzed frames
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Power normalization of the window. A full scale sinusoid at a bin
+    /// center has a magnitude of sum(window)/2 in the one sided spectrum.
+    pub fn power_scale(&self) -> f64 {
+        let sum = self.window.iter().sum::<f64>();
+        4.0 / (sum * sum)
+    }
+
+    /// Equivalent noise bandwidth of the window in bins
+    pub fn enbw(&self) -> f64 {
+        let sum = self.window.iter().sum::<f64>();
+        let sq_sum = self.window.iter().map(|x| x * x).sum::<f64>();
+        self.window.len() as f64 * sq_sum / (sum * sum)
+    }
+
+    /// Appends samples and passes the one sided spectrum of every
+    /// complete frame to "frame".
+    pub fn process<F>(&mut self, samples: &[f32], mut frame: F)
+    where
+        F: FnMut(&[Complex]),
+    {
+        self.pending.extend(samples.iter().map(|x| *x as f64));
+
+        let len = self.window.len();
+        let mut start = 0;
+        while start + len <= self.pending.len() {
+            self.analyze(start);
+            frame(&self.spectrum);
+            start += self.hop;
+        }
+        if start > 0 {
+            self.pending.drain(..start);
+            self.covered = len - self.hop;
+        }
+    }
+
+    /// Zero pads the remaining samples to a complete frame and analyzes it.
+    /// Nothing happens if all samples were already analyzed, except if no
+    /// frame was analyzed at all.
+    pub fn finish<F>(&mut self, mut frame: F)
+    where
+        F: FnMut(&[Complex]),
+    {
+        if self.pending.len() > self.covered || self.frames == 0 {
+            self.pending.resize(self.window.len(), 0.0);
+            self.analyze(0);
+            frame(&self.spectrum);
+        }
+        self.pending.clear();
+        self.covered = 0;
+    }
+
+    fn analyze(&mut self, start: usize) {
+        let len = self.window.len();
+        self.windowed.clear();
+        self.windowed.extend(
+            self.pending[start..start + len]
+                .iter()
+                .zip(&self.window)
+                .map(|(x, w)| x * w),
+        );
+        // The frame has the FFT size, the transform cannot fail.
+        let _ = self.fft.forward_real(&self.windowed, &mut self.spectrum);
+        self.frames += 1;
+    }
+}
+
+/// Inverse short-time Fourier transform with weighted overlap-add. The
+/// squared window must add up to a constant at the hop size, e.g. a Hann
+/// window with a hop of a quarter of the FFT size.
+#[derive(Debug, Clone)]
+pub struct Istft {
+    fft: Fft,
+    /// Synthesis window coefficients
+    window: Vec<f64>,
+    /// Samples between consecutive frames
+    hop: usize,
+    /// Normalization of the overlapping windows
+    scale: f64,
+    /// Overlapping frames which are not complete yet
+    overlap: Vec<f64>,
+    /// Inverse FFT working buffer
+    frame: Vec<f64>,
+}
+
+impl Istft {
+    pub fn new(
+        fft_size: usize,
+        hop: usize,
+        window: Window,
+    ) -> Result<Self, Error> {
+        let fft = Fft::new(fft_size)?;
+        if hop == 0 || hop > fft_size {
+            return Err(Error::InvalidArgument(
+                "Hop size must be between 1 and the FFT size.".into(),
+            ));
+        }
+        let window = window.coefficients(fft_size);
+        let scale = hop as f64 / window.iter().map(|x| x * x).sum::<f64>();
+
+        Ok(Self {
+            fft,
+            window,
+            hop,
+            scale,
+            overlap: vec![0.0; fft_size],
+            frame: Vec::with_capacity(fft_size),
+        })
+    }
+
+    /// Adds the frame of a one sided spectrum and appends the next "hop"
+    /// completed samples to "output".
+    pub fn process(&mut self, spectrum: &[Complex], output: &mut Vec<f64>) {
+        // The spectrum has the FFT size, the transform cannot fail.
+        let _ = self.fft.inverse_real(spectrum, &mut self.frame);
+        for ((y, x), w) in
+            self.overlap.iter_mut().zip(&self.frame).zip(&self.window)
+        {
+            *y += self.scale * w * x;
+        }
+        output.extend_from_slice(&self.overlap[..self.hop]);
+        self.overlap.drain(..self.hop);
+        self.overlap.resize(self.window.len(), 0.0);
+    }
+}
+
+/// Adds the weighted powers of a one sided spectrum to "powers".
+/// "scale" is the power normalization of the window.
+pub fn add_powers(spectrum: &[Complex], scale: f64, powers: &mut [f64]) {
+    let nyquist = spectrum.len() - 1;
+    for (k, (power, x)) in powers.iter_mut().zip(spectrum).enumerate() {
+        // DC and Nyquist have no mirrored negative frequency.
+        let scale = if k == 0 || k == nyquist {
+            scale / 4.0
+        } else {
+            scale
+        };
+        *power += scale * x.norm_sqr();
+    }
+}
+
+#[test]
+fn test_stft() {
+    let mut stft = Stft::new(8, 2, Window::Hann).unwrap();
+    assert!(Stft::new(8, 9, Window::Hann).is_err());
+
+    let mut count = 0;
+    stft.process(&[1.0; 7], |_| count += 1);
+    assert_eq!(count, 0);
+    stft.process(&[1.0; 4], |x| {
+        assert_eq!(x.len(), 5);
+        count += 1
+    });
+    // Frames start at 0 and 2.
+    assert_eq!(count, 2);
+    stft.finish(|_| count += 1);
+    assert_eq!(count, 3);
+    assert_eq!(stft.frames(), 3);
+    stft.finish(|_| count += 1);
+    assert_eq!(count, 3);
+
+    // Analysis and synthesis restore the signal after the first frame.
+    let mut stft = Stft::new(16, 4, Window::Hann).unwrap();
+    let mut istft = Istft::new(16, 4, Window::Hann).unwrap();
+    let signal: Vec<f32> = (0..64).map(|x| (x as f32 * 0.3).sin()).collect();
+    let mut output = Vec::new();
+    stft.process(&signal, |x| istft.process(x, &mut output));
+    assert_eq!(output.len(), 13 * 4);
+    for (x, y) in output[12..].iter().zip(&signal[12..]) {
+        assert!((x - *y as f64).abs() < 1e-6);
+    }
+}
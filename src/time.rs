@@ -0,0 +1,109 @@
+/******************************************************************************\
+    wavehacker
+    Copyright (C) 2023 Max Maisel
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+\******************************************************************************/
+
+/// Position in an audio stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timestamp {
+    /// Position in frames
+    Samples(usize),
+    /// Position in seconds
+    Seconds(f64),
+}
+
+impl Timestamp {
+    /// Position in frames at sampling frequency "fs".
+    pub fn frames(&self, fs: f64) -> usize {
+        match self {
+            Self::Samples(x) => *x,
+            Self::Seconds(x) => (x * fs).round() as usize,
+        }
+    }
+}
+
+impl std::str::FromStr for Timestamp {
+    type Err = String;
+
+    /// Parses "48000smp" as frames, "1.5" or "1.5s" as seconds and
+    /// "hh:mm:ss.ms" or "mm:ss.ms" as time.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let error = || format!("Invalid timestamp '{}'", s);
+
+        if let Some(x) = s.strip_suffix("smp") {
+            return x.parse().map(Self::Samples).map_err(|_| error());
+        }
+
+        let mut seconds = 0.0;
+        let fields: Vec<&str> =
+            s.strip_suffix('s').unwrap_or(s).split(':').collect();
+        if fields.len() > 3 {
+            return Err(error());
+        }
+        for (idx, field) in fields.iter().enumerate() {
+            let is_last = idx + 1 == fields.len();
+            let value = if is_last {
+                field.parse::<f64>().map_err(|_| error())?
+            } else {
+                field.parse::<u32>().map_err(|_| error())? as f64
+            };
+            // Minutes and seconds of a time must be less than 60.
+            if value < 0.0 || (idx != 0 && value >= 60.0) {
+                return Err(error());
+            }
+            seconds = seconds * 60.0 + value;
+        }
+
+        if seconds.is_finite() && seconds >= 0.0 {
+            Ok(Self::Seconds(seconds))
+        } else {
+            Err(error())
+        }
+    }
+}
+
+/// Formats a position in frames as "hh:mm:ss.mmm".
+pub fn format_frames(frames: usize, fs: f64) -> String {
+    let millis = (frames as f64 * 1000.0 / fs).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3600000,
+        millis / 60000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+#[test]
+fn test_timestamp() {
+    let frames = |s: &str| s.parse::<Timestamp>().unwrap().frames(1000.0);
+
+    assert_eq!(frames("1234smp"), 1234);
+    assert_eq!(frames("1.5"), 1500);
+    assert_eq!(frames("2s"), 2000);
+    assert_eq!(frames("01:30.25"), 90250);
+    assert_eq!(frames("1:00:00.001"), 3600001);
+    assert!("1:60".parse::<Timestamp>().is_err());
+    assert!("1:2:3:4".parse::<Timestamp>().is_err());
+    assert!("1:-5".parse::<Timestamp>().is_err());
+    assert!("-1".parse::<Timestamp>().is_err());
+    assert!("1.5smp".parse::<Timestamp>().is_err());
+    assert!("".parse::<Timestamp>().is_err());
+
+    assert_eq!(format_frames(3600001, 1000.0), "01:00:00.001");
+    assert_eq!(format_frames(90250, 1000.0), "00:01:30.250");
+}